@@ -1,51 +1,101 @@
-use std::{path::Path, process::ExitStatus};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
 
 use color_eyre::eyre::{eyre, OptionExt as _};
-use graph_rs_sdk::{http::HttpResponseExt as _, GraphClient, ODataQuery as _};
 use itertools::Itertools;
-use serde::Deserialize;
 
-use crate::utils::{
-    config::Config,
-    git::{check_out_create_branch, git_command_wrapper, wrap_git_command_error, GIT_AUTHOR},
+use crate::{
+    jobs::transcription::link::Link,
+    utils::{
+        cloud_storage::{get_storage, list_files, CloudEntry, CloudStorage},
+        config::{AudioMapping, Config, StorageProvider},
+        git::{
+            branch_exists, check_out_create_branch, commit_all, git_command_wrapper,
+            wrap_git_command_error, GIT_AUTHOR,
+        },
+    },
 };
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct OneDriveChildren {
-    name: String,
-    // file: Option<OneDriveFile>,
-    folder: Option<OneDriveFolder>,
-    last_modified_date_time: String,
-}
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct OneDriveChildrenVec {
-    value: Vec<OneDriveChildren>,
-}
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct OneDriveFolder {}
-// #[derive(Debug, Deserialize)]
-// #[serde(rename_all = "camelCase")]
-// struct OneDriveFile {}
 pub async fn link_audio(config: &Config) -> color_eyre::Result<()> {
-    let credential_config = config.credentials.clone();
     let audio_sync = config
         .clone()
         .audio_sync
         .ok_or_eyre("Expected audio_sync config to be initialized")?;
 
-    let github_repo_root = &config.git_directory;
-
     //TODO: validate that the branch exists
 
-    check_out_create_branch(&audio_sync.git_branch, &config)?;
+    // a dry run mustn't create the branch; it is treated as empty instead
+    let branch_missing = config.dry_run && !branch_exists(&audio_sync.git_branch, &config)?;
+    if branch_missing {
+        println!("[dry-run] would create branch '{}'", audio_sync.git_branch);
+    } else {
+        check_out_create_branch(&audio_sync.git_branch, &config)?;
+    }
+
+    // one client per storage
+    let mut storages: HashMap<StorageProvider, Box<dyn CloudStorage>> = HashMap::new();
+    let mut failed = 0;
+    for mapping in audio_sync.mappings.iter() {
+        let archive_dir = &audio_sync.archive_dir;
+        let res = match storages.entry(mapping.storage) {
+            Entry::Occupied(x) => Ok(x.into_mut()),
+            Entry::Vacant(x) => get_storage(mapping.storage, &config.credentials)
+                .await
+                .map(|storage| x.insert(storage)),
+        };
+        let res = match res {
+            Ok(storage) => {
+                link_mapping(
+                    config,
+                    mapping,
+                    storage.as_ref(),
+                    archive_dir,
+                    branch_missing,
+                )
+                .await
+            }
+            Err(why) => Err(why),
+        };
+        if let Err(why) = res {
+            log::error!(
+                "Failed to link {}:{} -> {:?}: {:?}",
+                mapping.storage.scheme(),
+                mapping.source_folder,
+                mapping.git_destination_folder,
+                why
+            );
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(eyre!(
+            "Failed to link {} of {} mappings",
+            failed,
+            audio_sync.mappings.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Links all new recordings of a mapping & commits them
+/// Links of renamed / moved recordings are updated and the ones of deleted recordings archived beforehand
+/// Expects the audio branch to be checked out
+async fn link_mapping(
+    config: &Config,
+    mapping: &AudioMapping,
+    storage: &dyn CloudStorage,
+    archive_dir: &Path,
+    branch_missing: bool,
+) -> color_eyre::Result<()> {
+    let github_repo_root = &config.git_directory;
     let git_target_path = github_repo_root.join(
-        audio_sync
+        mapping
             .git_destination_folder
             .strip_prefix("/")
-            .unwrap_or(&audio_sync.git_destination_folder),
+            .unwrap_or(&mapping.git_destination_folder),
     );
     if git_target_path.is_file() {
         return Err(eyre!(
@@ -53,119 +103,102 @@ pub async fn link_audio(config: &Config) -> color_eyre::Result<()> {
             git_target_path.to_str()
         ));
     }
-    if !git_target_path.exists() {
+    if !git_target_path.exists() && !config.dry_run {
         std::fs::create_dir_all(&git_target_path)?;
     }
 
-    let github_files: Result<Vec<_>, _> = std::fs::read_dir(&git_target_path)?
-        .filter(|x| match x {
-            Ok(x) => match x.file_type() {
-                Ok(x) => x.is_file(),
-                Err(_) => false,
-            },
-            Err(_) => false,
-        })
-        .map(|x| x.map(|x| x.path()))
-        .collect();
-    let github_files = github_files?;
+    let (children, skipped_folders) =
+        list_files(storage, &mapping.source_folder, mapping.recursion_depth).await?;
 
-    // OneDrive
-    let token = crate::utils::credentials::get_onedrive_credentials(&credential_config).await?;
-    if !token.scope.contains("Files.Read") {
-        return Err(eyre!("Access token didn't cover the scrope 'Files.Read'"));
-    }
-    let graph_client = GraphClient::new(token.token);
-
-    let onedrive_path = audio_sync.onedrive_source_folder;
+    let moves = match !branch_missing && git_target_path.exists() {
+        true => reconcile_links(
+            config,
+            mapping,
+            storage.provider(),
+            &children,
+            &skipped_folders,
+            &git_target_path,
+            archive_dir,
+        )?,
+        false => vec![],
+    };
 
-    let mut onedrive_source_path = onedrive_path
-        .strip_suffix("/")
-        .map(|x| x.to_owned())
-        .unwrap_or(onedrive_path);
-    if !onedrive_source_path.starts_with("/") {
-        onedrive_source_path = format!("/{}", onedrive_source_path);
-    }
-    if onedrive_source_path == "/" {
-        return Err(eyre!("Due to technical limitations the onedrive source_dir cant be located at the drives root"));
+    // links relative to the target dir
+    let mut github_files = if branch_missing || !git_target_path.exists() {
+        vec![]
+    } else {
+        walkdir::WalkDir::new(&git_target_path)
+            .into_iter()
+            .filter_map(|x| x.ok())
+            .filter(|x| x.file_type().is_file())
+            .filter_map(|x| {
+                x.path()
+                    .strip_prefix(&git_target_path)
+                    .ok()
+                    .map(|x| x.to_path_buf())
+            })
+            .collect_vec()
+    };
+    if config.dry_run {
+        // the links haven't been moved, so the renamed recordings would be linked again otherwise
+        github_files.retain(|x| !moves.iter().any(|(from, _)| from == x));
+        github_files.extend(moves.into_iter().filter_map(|(_, to)| to));
     }
 
-    let children = graph_client
-        .me()
-        .drive()
-        .item_by_path(format!(":{}:", onedrive_source_path))
-        .list_children()
-        .select(&["name", "folder", "file", "lastModifiedDateTime"])
-        .paging()
-        .json::<OneDriveChildrenVec>()
-        .await?;
-
-    let mut files_to_sync = vec![];
+    // (link relative to the target dir, link content)
+    let mut files_to_sync: Vec<(PathBuf, String)> = vec![];
+    let mut seen_links = HashSet::new();
 
     // check which files are already synced
-    for res in children.into_iter() {
-        if !res.status().is_success() {
-            return Err(eyre!(
-                "Failed to read url {:?}: ({})",
-                res.url(),
-                res.status().as_u16()
-            ));
+    for (folder, res) in children {
+        if !mapping.is_permitted(&res.name) {
+            log::info!("Skipped non audio or filtered file {}", res.reference);
+            continue;
         }
-        let res = res.into_body()?;
-        for res in res.value {
-            if res.folder.is_some() {
-                continue;
-            }
-            let remote_file_extension = res.name.split(".").last().unwrap_or_default();
-            if !audio_sync
-                .permitted_file_types
-                .contains(&remote_file_extension.to_owned())
-            {
-                log::info!(
-                    "Skipped non audio file {}/{}",
-                    onedrive_source_path,
-                    res.name
-                );
-                continue;
-            }
 
-            let name = format!("{}.link", res.name);
-            if github_files
-                .iter()
-                .find(|x| {
-                    x.file_name()
-                        .is_some_and(|x| x.to_str().is_some_and(|x| name == x))
-                })
-                .is_none()
-            {
-                files_to_sync.push((name, res.last_modified_date_time));
-            }
+        let link = mapping.link_path(&folder, &res.name);
+        if !seen_links.insert(link.clone()) {
+            log::warn!(
+                "Skipped {}, because another recording is already linked as {:?}",
+                res.reference,
+                link
+            );
+            continue;
+        }
+        if !github_files.contains(&link) {
+            files_to_sync.push((link, link_content(mapping, storage.provider(), &res)?));
         }
     }
 
     let mut synced_files = 0;
     // syncing files
-    for (file, date) in &files_to_sync {
+    for (file, content) in &files_to_sync {
         let git_target_file = git_target_path.join(&file);
-        let onedrive_file = file.strip_suffix(".link").expect("Infallible").to_owned();
-        let onedrive_path = format!("{}/{}", onedrive_source_path, onedrive_file);
-        let date = chrono::DateTime::parse_from_rfc3339(&date)?;
-
-        match std::fs::write(
-            &git_target_file,
-            format!("onedrive:({}):{}", date.timestamp(), onedrive_path),
-        ) {
-            Ok(_) => {
-                log::info!(
-                    "Wrote link onedrive:{} -> {:?}",
-                    onedrive_path,
+
+        if config.dry_run {
+            println!(
+                "[dry-run] would create link {:?} -> {}",
+                Path::new("/").join(
                     git_target_file
-                );
+                        .strip_prefix(github_repo_root)
+                        .unwrap_or(&git_target_file)
+                ),
+                content
+            );
+            continue;
+        }
+        if let Some(parent) = git_target_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        match std::fs::write(&git_target_file, content) {
+            Ok(_) => {
+                log::info!("Wrote link {} -> {:?}", content, git_target_file);
                 synced_files += 1;
             }
             Err(why) => {
                 log::error!(
-                    "Failed to write link onedrive:{} -> {:?}: {:?}",
-                    onedrive_path,
+                    "Failed to write link {} -> {:?}: {:?}",
+                    content,
                     git_target_file,
                     why
                 );
@@ -188,7 +221,7 @@ pub async fn link_audio(config: &Config) -> color_eyre::Result<()> {
                     "add: {}",
                     files_to_sync
                         .iter()
-                        .map(|x| x.0.clone())
+                        .map(|x| x.0.to_string_lossy())
                         .collect_vec()
                         .join(",")
                 ),
@@ -205,3 +238,454 @@ pub async fn link_audio(config: &Config) -> color_eyre::Result<()> {
 
     Ok(())
 }
+
+/// Content of the .link file of a recording of the mapping
+fn link_content(
+    mapping: &AudioMapping,
+    provider: StorageProvider,
+    entry: &CloudEntry,
+) -> color_eyre::Result<String> {
+    let date = entry.last_modified.ok_or_eyre(format!(
+        "Expected {}:{} to have a modification date",
+        provider.scheme(),
+        entry.reference
+    ))?;
+    let target = format!(
+        "{}:({}):{}",
+        provider.scheme(),
+        date.timestamp(),
+        entry.reference
+    );
+    // lets the transcription recognize duplicates & the linker renamed recordings (see the link header)
+    // and the transcription to correlate notes over the length of the recording
+    let id = entry.id.clone().filter(|x| *x != entry.reference);
+    let header = [
+        ("hash", entry.hash.clone().map(toml::Value::String)),
+        // Google Drive references already are ids
+        ("id", id.map(toml::Value::String)),
+        ("duration", entry.duration.map(toml::Value::Float)),
+        // only links with an id can be reconciled
+        (
+            "source",
+            entry
+                .id
+                .is_some()
+                .then(|| toml::Value::String(mapping.source())),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| value.map(|x| format!("{} = {}\n", key, x)))
+    .join("");
+    Ok(match header.is_empty() {
+        true => target,
+        false => format!("+++\n{}+++\n{}", header, target),
+    })
+}
+
+/// What happens to an existing link of a mapping, whose recording has changed
+#[derive(Debug, Clone, PartialEq)]
+enum Reconciliation {
+    /// the recording has been renamed or moved: the link gets the new reference & path (relative to the target dir)
+    Move { path: PathBuf, reference: String },
+    /// the recording has been deleted
+    Archive,
+}
+
+/// Compares the existing links, which store the id of their recording, with the listed recordings:
+/// links of renamed or moved recordings are rewritten (keeping their header & timestamp) and moved to their new path,
+/// links of recordings, which don't exist anymore, are moved into the archive dir (keeping their path inside the repository)
+/// Only links written by this mapping (see `LinkMetadata::source`) are touched
+/// returns the moved links relative to the target dir (from, to; None if archived), which are only planned on a dry run
+fn reconcile_links(
+    config: &Config,
+    mapping: &AudioMapping,
+    provider: StorageProvider,
+    children: &[(String, CloudEntry)],
+    skipped_folders: &[String],
+    git_target_path: &Path,
+    archive_dir: &Path,
+) -> color_eyre::Result<Vec<(PathBuf, Option<PathBuf>)>> {
+    let by_id: HashMap<&str, &(String, CloudEntry)> = children
+        .iter()
+        .filter_map(|x| x.1.id.as_deref().map(|id| (id, x)))
+        .collect();
+    let link_files = walkdir::WalkDir::new(git_target_path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|x| x.ok())
+        .filter(|x| x.file_type().is_file() && x.file_name().to_string_lossy().ends_with(".link"))
+        .map(|x| x.path().to_path_buf())
+        .collect_vec();
+
+    let mut changes = vec![];
+    let mut moves = vec![];
+    for file in link_files {
+        let content = std::fs::read_to_string(&file)?;
+        let link = match Link::parse_link_file(&content, &config.timezone) {
+            Ok(x) => x,
+            Err(why) => {
+                log::warn!("Skipped reconciling unparsable link {:?}: {:?}", file, why);
+                continue;
+            }
+        };
+        if link.link_target.cloud_file()?.map(|x| x.0) != Some(provider) {
+            continue;
+        }
+        let relative = file.strip_prefix(git_target_path)?.to_path_buf();
+
+        let (target, content) = match reconcile_link(
+            &link,
+            &relative,
+            mapping,
+            &by_id,
+            skipped_folders,
+        )? {
+            None => continue,
+            Some(Reconciliation::Move { path, reference }) => {
+                let target = git_target_path.join(&path);
+                if target != file && target.exists() {
+                    log::warn!(
+                            "Can't move the link {:?} of the renamed recording {}, because {:?} already exists",
+                            file,
+                            reference,
+                            target
+                        );
+                    continue;
+                }
+                let target_line = format!(
+                    "{}:({}):{}",
+                    provider.scheme(),
+                    link.last_modified.timestamp(),
+                    reference
+                );
+                let content = content
+                    .split("\n")
+                    .map(
+                        |x| match x.starts_with(&format!("{}:(", provider.scheme())) {
+                            true => target_line.as_str(),
+                            false => x,
+                        },
+                    )
+                    .join("\n");
+                changes.push(format!(
+                    "{} -> {}",
+                    relative.to_string_lossy(),
+                    path.to_string_lossy()
+                ));
+                moves.push((relative, Some(path)));
+                (target, content)
+            }
+            Some(Reconciliation::Archive) => {
+                let destination = &mapping.git_destination_folder;
+                let target = config
+                    .git_directory
+                    .join(archive_dir.strip_prefix("/").unwrap_or(archive_dir))
+                    .join(destination.strip_prefix("/").unwrap_or(destination))
+                    .join(&relative);
+                changes.push(format!("archived {}", relative.to_string_lossy()));
+                moves.push((relative, None));
+                (target, content)
+            }
+        };
+
+        if config.dry_run {
+            println!(
+                "[dry-run] would move link {:?} -> {:?}",
+                file.strip_prefix(&config.git_directory).unwrap_or(&file),
+                target
+                    .strip_prefix(&config.git_directory)
+                    .unwrap_or(&target)
+            );
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::remove_file(&file)?;
+        std::fs::write(&target, content)?;
+        log::info!("Moved link {:?} -> {:?}", file, target);
+    }
+
+    if !changes.is_empty() && !config.dry_run {
+        commit_all(&format!("reconcile: {}", changes.join(",")), config)?;
+    }
+    Ok(moves)
+}
+
+/// Decides what happens to the link (at `relative` inside the target dir); None: it stays as it is
+/// Links of other mappings, links without id or source (a warning is logged) & links of recordings,
+/// which may be inside a subfolder deeper than `recursion_depth`, are never touched
+fn reconcile_link(
+    link: &Link,
+    relative: &Path,
+    mapping: &AudioMapping,
+    by_id: &HashMap<&str, &(String, CloudEntry)>,
+    skipped_folders: &[String],
+) -> color_eyre::Result<Option<Reconciliation>> {
+    let (Some(id), Some((provider, reference))) = (link.item_id(), link.link_target.cloud_file()?)
+    else {
+        // written before ids were stored
+        return Ok(None);
+    };
+    if provider != mapping.storage {
+        return Ok(None);
+    }
+    match &link.metadata.source {
+        None => {
+            // written before the mapping was stored; it may belong to another mapping sharing the target dir
+            log::warn!(
+                "Skipped reconciling the link {:?}, because it doesn't store its mapping; add 'source = \"{}\"' to its header, if it belongs to this one",
+                relative,
+                mapping.source()
+            );
+            return Ok(None);
+        }
+        // written by another mapping
+        Some(x) if *x != mapping.source() => return Ok(None),
+        Some(_) => {}
+    }
+    Ok(match by_id.get(id.as_str()) {
+        Some((folder, entry)) => {
+            let path = mapping.link_path(folder, &entry.name);
+            match path == relative && entry.reference == reference {
+                true => None,
+                false => Some(Reconciliation::Move {
+                    path,
+                    reference: entry.reference.clone(),
+                }),
+            }
+        }
+        None => {
+            // Google Drive references are ids, so it can't be told in which folder the recording was
+            let maybe_skipped = match provider {
+                StorageProvider::GoogleDrive => !skipped_folders.is_empty(),
+                _ => skipped_folders
+                    .iter()
+                    .any(|x| reference.starts_with(&format!("{}/", x.trim_end_matches('/')))),
+            };
+            if maybe_skipped {
+                log::info!(
+                    "Kept the link {:?}, because its recording may be in a folder deeper than the recursion depth",
+                    relative
+                );
+                return Ok(None);
+            }
+            Some(Reconciliation::Archive)
+        }
+    })
+}
+
+#[test]
+fn test_reconcile_link() {
+    use crate::utils::config::FolderLayout;
+
+    let mapping = AudioMapping {
+        storage: StorageProvider::OneDrive,
+        source_folder: "/school".to_owned(),
+        git_destination_folder: PathBuf::from("/audio_files"),
+        permitted_file_types: vec!["mp3".to_owned()],
+        filename_filter: None,
+        recursion_depth: 1,
+        folder_layout: FolderLayout::Mirror,
+    };
+    let link = |source: &str, reference: &str| {
+        Link::parse_link_file(
+            &format!(
+                "+++\nid = \"item-1\"\nsource = \"{}\"\n+++\nonedrive:(1720958400):{}",
+                source, reference
+            ),
+            &chrono_tz::UTC,
+        )
+        .unwrap()
+    };
+    let entry = |name: &str, reference: &str| CloudEntry {
+        name: name.to_owned(),
+        is_folder: false,
+        last_modified: None,
+        reference: reference.to_owned(),
+        hash: None,
+        id: Some("item-1".to_owned()),
+        duration: None,
+    };
+    let reconcile =
+        |link: &Link, relative: &str, children: &[(String, CloudEntry)], skipped: &[&str]| {
+            let by_id = children
+                .iter()
+                .map(|x| (x.1.id.as_deref().unwrap(), x))
+                .collect();
+            let skipped = skipped.iter().map(|x| x.to_string()).collect_vec();
+            reconcile_link(link, Path::new(relative), &mapping, &by_id, &skipped).unwrap()
+        };
+
+    let lesson = link("onedrive:/school", "/school/lesson.mp3");
+    let unchanged = [(String::new(), entry("lesson.mp3", "/school/lesson.mp3"))];
+    assert_eq!(reconcile(&lesson, "lesson.mp3.link", &unchanged, &[]), None);
+    let moved = [(
+        "week1".to_owned(),
+        entry("renamed.mp3", "/school/week1/renamed.mp3"),
+    )];
+    assert_eq!(
+        reconcile(&lesson, "lesson.mp3.link", &moved, &[]),
+        Some(Reconciliation::Move {
+            path: PathBuf::from("week1/renamed.mp3.link"),
+            reference: "/school/week1/renamed.mp3".to_owned(),
+        })
+    );
+    assert_eq!(
+        reconcile(&lesson, "lesson.mp3.link", &[], &[]),
+        Some(Reconciliation::Archive)
+    );
+
+    // links of other mappings (sharing the target dir) stay
+    let other = link("onedrive:/work", "/work/lesson.mp3");
+    assert_eq!(reconcile(&other, "lesson.mp3.link", &[], &[]), None);
+    let unknown = Link::parse_link_file(
+        "+++\nid = \"item-1\"\n+++\nonedrive:(1720958400):/school/lesson.mp3",
+        &chrono_tz::UTC,
+    )
+    .unwrap();
+    assert_eq!(reconcile(&unknown, "lesson.mp3.link", &[], &[]), None);
+
+    // recordings in folders deeper than the recursion depth aren't deleted
+    let deep = link("onedrive:/school", "/school/week1/monday/lesson.mp3");
+    assert_eq!(
+        reconcile(
+            &deep,
+            "week1/monday/lesson.mp3.link",
+            &[],
+            &["/school/week1/monday"]
+        ),
+        None
+    );
+    assert_eq!(
+        reconcile(&lesson, "lesson.mp3.link", &[], &["/school/week1/monday"]),
+        Some(Reconciliation::Archive)
+    );
+}
+
+#[tokio::test]
+async fn test_reconcile_links_dry_run() {
+    let mut env = crate::jobs::test_pipeline::TestEnvironment::lesson().await;
+    env.config.dry_run = true;
+    let audio_sync = env.config.audio_sync.clone().unwrap();
+    let mapping = &audio_sync.mappings[0];
+    let link = |name: &str, id: &str| {
+        format!(
+            "+++\nid = \"{}\"\nsource = \"{}\"\n+++\nonedrive:(1720958400):/school/recordings/{}",
+            id,
+            mapping.source(),
+            name
+        )
+    };
+    env.write("audio_files/lesson.mp3.link", &link("lesson.mp3", "item-1"));
+    env.write("audio_files/other.mp3.link", &link("other.mp3", "item-2"));
+    let children = vec![(
+        String::new(),
+        CloudEntry {
+            name: "renamed.mp3".to_owned(),
+            is_folder: false,
+            last_modified: None,
+            reference: "/school/recordings/renamed.mp3".to_owned(),
+            hash: None,
+            id: Some("item-1".to_owned()),
+            duration: None,
+        },
+    )];
+    let target = env.repo.path().join("audio_files");
+
+    let moves = reconcile_links(
+        &env.config,
+        mapping,
+        StorageProvider::OneDrive,
+        &children,
+        &[],
+        &target,
+        &audio_sync.archive_dir,
+    )
+    .unwrap();
+    assert_eq!(
+        moves,
+        vec![
+            (
+                PathBuf::from("lesson.mp3.link"),
+                Some(PathBuf::from("renamed.mp3.link"))
+            ),
+            (PathBuf::from("other.mp3.link"), None),
+        ]
+    );
+    // only planned
+    assert!(target.join("lesson.mp3.link").exists());
+    assert!(target.join("other.mp3.link").exists());
+    assert!(!target.join("renamed.mp3.link").exists());
+}
+
+#[test]
+fn test_link_content() {
+    use crate::utils::config::FolderLayout;
+
+    let mapping = |storage, source_folder: &str| AudioMapping {
+        storage,
+        source_folder: source_folder.to_owned(),
+        git_destination_folder: PathBuf::from("/audio_files"),
+        permitted_file_types: vec!["mp3".to_owned()],
+        filename_filter: None,
+        recursion_depth: 0,
+        folder_layout: FolderLayout::Mirror,
+    };
+    let entry = |reference: &str| CloudEntry {
+        name: "lesson.mp3".to_owned(),
+        is_folder: false,
+        last_modified: chrono::DateTime::from_timestamp(1720958400, 0),
+        reference: reference.to_owned(),
+        hash: None,
+        id: None,
+        duration: None,
+    };
+    let onedrive = mapping(StorageProvider::OneDrive, "/school/recordings/");
+
+    assert_eq!(
+        link_content(
+            &onedrive,
+            StorageProvider::OneDrive,
+            &entry("/school/recordings/lesson.mp3")
+        )
+        .unwrap(),
+        "onedrive:(1720958400):/school/recordings/lesson.mp3"
+    );
+    assert_eq!(
+        link_content(
+            &onedrive,
+            StorageProvider::OneDrive,
+            &CloudEntry {
+                hash: Some("sha256:ab12".to_owned()),
+                id: Some("item-1".to_owned()),
+                duration: Some(2700.),
+                ..entry("/school/recordings/lesson.mp3")
+            }
+        )
+        .unwrap(),
+        "+++\nhash = \"sha256:ab12\"\nid = \"item-1\"\nduration = 2700.0\nsource = \"onedrive:/school/recordings/\"\n+++\nonedrive:(1720958400):/school/recordings/lesson.mp3"
+    );
+    // Google Drive references are ids
+    assert_eq!(
+        link_content(
+            &mapping(StorageProvider::GoogleDrive, "root-id"),
+            StorageProvider::GoogleDrive,
+            &CloudEntry {
+                id: Some("f1".to_owned()),
+                ..entry("f1")
+            }
+        )
+        .unwrap(),
+        "+++\nsource = \"gdrive:root-id\"\n+++\ngdrive:(1720958400):f1"
+    );
+    assert!(link_content(
+        &onedrive,
+        StorageProvider::OneDrive,
+        &CloudEntry {
+            last_modified: None,
+            ..entry("/school/recordings/lesson.mp3")
+        }
+    )
+    .is_err());
+}
@@ -1,2 +1,4 @@
 pub mod audio_linker;
+#[cfg(test)]
+pub(crate) mod test_pipeline;
 pub mod transcription;
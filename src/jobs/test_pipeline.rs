@@ -0,0 +1,536 @@
+//! End-to-end tests of the whole pipeline & the environment they (and the tests of the cloud storages) run in
+//! A local stand-in server replaces the OneDrive token server & the graph api (and Google Drive, Dropbox & WebDAV),
+//! transcription is done by the mock backend and all git operations run in a temporary repo
+//! The decisions of the single steps are tested next to their code
+use std::{path::PathBuf, process::Command, str::FromStr as _};
+
+use chrono::{DateTime, Utc};
+use itertools::Itertools as _;
+use reqwest::{header::HeaderValue, Url};
+use wiremock::{
+    matchers::{body_string_contains, header, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::utils::config::{
+    AudioMapping, AudioSyncConfig, BlameTime, Config, CredentialConfig, CredentialProvider,
+    FolderLayout, RefreshTokenConfig, StorageProvider, TokenUrlConfig, TranscriptionConfig,
+    TranscriptionProvider, WebDavConfig,
+};
+
+/// the current time with the precision of the links
+pub(crate) fn now() -> DateTime<Utc> {
+    DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap()
+}
+
+pub(crate) struct TestEnvironment {
+    pub server: MockServer,
+    pub repo: tempfile::TempDir,
+    pub config: Config,
+}
+impl TestEnvironment {
+    /// starts the stand-in server and creates a repo containing the given notes (committed now)
+    pub async fn new(notes: &[(&str, &str)]) -> TestEnvironment {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/token"))
+            .and(header("authorization", "secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "token": "test-token",
+                "scope": "Files.Read offline_access"
+            })))
+            .mount(&server)
+            .await;
+
+        let repo = tempfile::tempdir().unwrap();
+        let mut config = Config {
+            credentials: CredentialConfig {
+                provider: CredentialProvider::TokenUrl,
+                token_url: Some(TokenUrlConfig {
+                    onedrive_access_token_authorization: HeaderValue::from_static("secret"),
+                    onedrive_access_token_url: Url::parse(&format!("{}/token", server.uri()))
+                        .unwrap(),
+                }),
+                oauth: None,
+                graph_api_base_url: Some(Url::parse(&format!("{}/v1.0", server.uri())).unwrap()),
+                gdrive: None,
+                dropbox: None,
+                webdav: None,
+            },
+            audio_sync: Some(AudioSyncConfig {
+                git_branch: "audio".to_owned(),
+                mappings: vec![AudioMapping {
+                    storage: StorageProvider::OneDrive,
+                    source_folder: "/school/recordings/".to_owned(),
+                    git_destination_folder: PathBuf::from_str("/audio_files/").unwrap(),
+                    permitted_file_types: vec!["mp3".to_owned()],
+                    filename_filter: None,
+                    recursion_depth: 0,
+                    folder_layout: FolderLayout::Mirror,
+                }],
+                archive_dir: PathBuf::from_str("/audio_archive/").unwrap(),
+            }),
+            transcription: Some(TranscriptionConfig {
+                transcription_script_search_path: PathBuf::from_str("/notes/").unwrap(),
+                transcription_target_path: PathBuf::from_str("/attachements/").unwrap(),
+                git_source_branch: "audio".to_owned(),
+                git_target_branch: "master".to_owned(),
+                git_source_path: PathBuf::from_str("/audio_files/").unwrap(),
+                time_window: chrono::Duration::minutes(100),
+                padding_before: chrono::Duration::minutes(15),
+                padding_after: chrono::Duration::minutes(30),
+                blame_time: BlameTime::Committer,
+                include_parent: false,
+                provider: TranscriptionProvider::Mock,
+                deepgram_key: None,
+                local: None,
+                state_file: PathBuf::from_str("/.korosensei/state.json").unwrap(),
+                cost_per_minute: Some(0.5),
+                link_filter: None,
+                force: false,
+                mark_orphaned: false,
+            }),
+            git_directory: repo.path().to_path_buf(),
+            timezone: "Europe/Berlin".parse().unwrap(),
+            git_exec: PathBuf::new(),
+            dry_run: false,
+        };
+        crate::utils::prerequisits::check_prerequisits(&mut config).unwrap();
+
+        let env = TestEnvironment {
+            server,
+            repo,
+            config,
+        };
+        env.git(&["init", "-q", "-b", "master"]);
+        env.git(&["config", "user.name", "Nagisa Shiota"]);
+        env.git(&["config", "user.email", "nagisa@kunugigaoka.jp"]);
+        for (file, content) in notes {
+            env.write(file, content);
+        }
+        env.git(&["add", "."]);
+        env.git(&["commit", "-q", "-m", "notes"]);
+        env
+    }
+
+    /// a repo containing only the note 'notes/lesson.md'
+    pub async fn lesson() -> TestEnvironment {
+        TestEnvironment::new(&[("notes/lesson.md", "# Mocking\n")]).await
+    }
+
+    /// lets the stand-in graph api list the given files (name, last modified) in the source folder
+    pub async fn mount_onedrive_files(&self, files: &[(&str, DateTime<Utc>)]) {
+        self.mount_onedrive_folder("/school/recordings", files)
+            .await;
+    }
+
+    /// lets the stand-in graph api list the given files (name, last modified) in a folder (without trailing '/')
+    /// names ending with '/' are listed as subfolders
+    pub async fn mount_onedrive_folder(&self, folder: &str, files: &[(&str, DateTime<Utc>)]) {
+        let value = files
+            .iter()
+            .map(|(name, date)| match name.strip_suffix("/") {
+                Some(name) => serde_json::json!({
+                    "name": name,
+                    "folder": { "childCount": 1 },
+                    "lastModifiedDateTime": date.to_rfc3339(),
+                }),
+                None => serde_json::json!({
+                    "name": name,
+                    "file": {},
+                    "lastModifiedDateTime": date.to_rfc3339(),
+                }),
+            })
+            .collect_vec();
+        Mock::given(method("GET"))
+            .and(path(format!("/v1.0/me/drive/root:{}:/children", folder)))
+            .and(header("authorization", "Bearer test-token"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "value": value })),
+            )
+            .mount(&self.server)
+            .await;
+    }
+
+    /// configures Google Drive & Dropbox apps, whose refresh tokens are exchanged at the stand-in server
+    /// access tokens: 'gdrive-token' & 'dropbox-token'
+    pub async fn mount_cloud_credentials(&mut self) {
+        for name in ["gdrive", "dropbox"] {
+            Mock::given(method("POST"))
+                .and(path(format!("/{}/token", name)))
+                .and(body_string_contains("grant_type=refresh_token"))
+                .and(body_string_contains(format!(
+                    "refresh_token={}-refresh",
+                    name
+                )))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "access_token": format!("{}-token", name),
+                    "expires_in": 3600,
+                })))
+                .mount(&self.server)
+                .await;
+            let conf = RefreshTokenConfig {
+                client_id: format!("{}-client", name),
+                client_secret: Some("secret".to_owned()),
+                refresh_token: format!("{}-refresh", name),
+                token_url: Url::parse(&format!("{}/{}/token", self.server.uri(), name)).unwrap(),
+                api_base_url: Url::parse(&format!("{}/{}", self.server.uri(), name)).unwrap(),
+            };
+            match name {
+                "gdrive" => self.config.credentials.gdrive = Some(conf),
+                _ => self.config.credentials.dropbox = Some(conf),
+            }
+        }
+    }
+
+    /// configures a Nextcloud like WebDAV server at /remote.php/dav/files/nagisa/ (user 'nagisa', password 'app-password')
+    pub fn configure_webdav(&mut self) {
+        self.config.credentials.webdav = Some(WebDavConfig {
+            url: Url::parse(&format!(
+                "{}/remote.php/dav/files/nagisa/",
+                self.server.uri()
+            ))
+            .unwrap(),
+            username: Some("nagisa".to_owned()),
+            password: Some("app-password".to_owned()),
+        });
+    }
+
+    pub fn git(&self, args: &[&str]) -> String {
+        let res = Command::new("git")
+            .current_dir(self.repo.path())
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            res.status.success(),
+            "git {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&res.stderr)
+        );
+        String::from_utf8(res.stdout).unwrap()
+    }
+
+    pub fn write(&self, file: &str, content: &str) {
+        let path = self.repo.path().join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    pub fn read(&self, file: &str) -> String {
+        std::fs::read_to_string(self.repo.path().join(file)).unwrap()
+    }
+
+    /// links the recordings, transcribes them & checks out the transcription branch
+    pub async fn run(&self) {
+        crate::jobs::audio_linker::link_audio(&self.config)
+            .await
+            .unwrap();
+        crate::jobs::transcription::transcribe_audio(&self.config)
+            .await
+            .unwrap();
+        self.git(&["checkout", "-q", "master"]);
+    }
+
+    /// the state file of the checked out branch
+    pub fn state(&self) -> serde_json::Value {
+        serde_json::from_str(&self.read(".korosensei/state.json")).unwrap()
+    }
+
+    /// the notes linked to the recording according to the state file, sorted
+    pub fn linked_notes(&self, key: &str) -> Vec<String> {
+        self.state()["recordings"][key]["linked_notes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x.as_str().unwrap().to_owned())
+            .sorted()
+            .collect_vec()
+    }
+
+    /// commit subjects of the branch, newest first
+    /// href of the links to the transcript of the recording inside the notes
+    pub fn transcript_href(&self, recording: &str) -> String {
+        let path = self
+            .repo
+            .path()
+            .join("attachements")
+            .join(format!("{}.transcript.md", recording));
+        url_escape::encode_path(&path.to_string_lossy()).to_string()
+    }
+    pub fn log(&self, branch: &str) -> Vec<String> {
+        self.git(&["log", "--format=%s", branch])
+            .lines()
+            .map(|x| x.to_owned())
+            .collect_vec()
+    }
+}
+
+#[tokio::test]
+async fn test_full_pipeline() {
+    // old.mp3 has been transcribed before the state file existed
+    let mut env = TestEnvironment::new(&[
+        ("notes/lesson.md", "# Mocking\nmocks are great\n"),
+        ("attachements/old.mp3.transcript.md", "# Transcript\n"),
+    ])
+    .await;
+    let recorded = now();
+    env.mount_onedrive_files(&[
+        ("lesson.mp3", recorded),
+        ("old.mp3", recorded),
+        ("slides.pdf", recorded),
+        ("week1/", recorded),
+    ])
+    .await;
+    // recorded a day before the notes have been written
+    let monday = recorded - chrono::Duration::days(1);
+    env.mount_onedrive_folder("/school/recordings/week1", &[("monday.mp3", monday)])
+        .await;
+    env.config.audio_sync.as_mut().unwrap().mappings[0].recursion_depth = 1;
+
+    env.config.dry_run = true;
+    env.run().await;
+    assert_eq!(env.git(&["branch", "--list", "audio"]), "");
+
+    env.config.dry_run = false;
+    crate::jobs::audio_linker::link_audio(&env.config)
+        .await
+        .unwrap();
+    assert_eq!(
+        env.log("audio"),
+        vec!["add: lesson.mp3.link,old.mp3.link,week1/monday.mp3.link"]
+    );
+    env.git(&["checkout", "-q", "audio"]);
+    assert_eq!(
+        env.read("audio_files/lesson.mp3.link"),
+        format!(
+            "onedrive:({}):/school/recordings/lesson.mp3",
+            recorded.timestamp()
+        )
+    );
+    assert_eq!(
+        env.read("audio_files/week1/monday.mp3.link"),
+        format!(
+            "onedrive:({}):/school/recordings/week1/monday.mp3",
+            monday.timestamp()
+        )
+    );
+
+    env.config.dry_run = true;
+    crate::jobs::transcription::transcribe_audio(&env.config)
+        .await
+        .unwrap();
+    env.git(&["checkout", "-q", "master"]);
+    assert_eq!(env.log("master"), vec!["notes"]);
+    assert_eq!(
+        env.git(&["status", "--porcelain", "--untracked-files=all"]),
+        ""
+    );
+    assert_eq!(env.read("notes/lesson.md"), "# Mocking\nmocks are great\n");
+
+    env.config.dry_run = false;
+    env.run().await;
+    assert_eq!(
+        env.log("master"),
+        vec![
+            "transcribed: lesson.mp3.transcript.md,monday.mp3.transcript.md",
+            "notes"
+        ]
+    );
+    assert_eq!(env.git(&["status", "--porcelain"]), "");
+
+    let transcript = env.read("attachements/lesson.mp3.transcript.md");
+    assert!(transcript.starts_with(&format!(
+        "# Transcript '{}'",
+        recorded.format("%d.%m.%Y %H:%M")
+    )));
+    assert!(transcript.contains("Today we talk about mocks."));
+    assert!(transcript.contains("> [Mocks](topic://Mocks)"));
+    assert!(env
+        .read("attachements/week1/monday.mp3.transcript.md")
+        .contains("Today we talk about mocks."));
+    assert_eq!(
+        env.read("attachements/old.mp3.transcript.md"),
+        "# Transcript\n"
+    );
+
+    // the note has been written at the end of the minute long recording: the second paragraph starts closest to it
+    assert_eq!(
+        env.read("notes/lesson.md"),
+        format!(
+            "\
+# Mocking
+> _Links
+> 
+> [{}](transcript:(5):{})
+
+mocks are great
+",
+            recorded.format("%d.%m.%Y %H:%M"),
+            env.transcript_href("lesson.mp3")
+        )
+    );
+
+    let state = env.state();
+    assert_eq!(state["version"], 1);
+    let recording = &state["recordings"]["lesson.mp3.link"];
+    assert_eq!(recording["provider"], "mock");
+    assert_eq!(recording["request_id"], "mock");
+    assert_eq!(recording["cost"], 0.5);
+    assert_eq!(recording["duration"], 60.);
+    assert_eq!(
+        recording["transcript"],
+        "/attachements/lesson.mp3.transcript.md"
+    );
+    assert_eq!(
+        env.linked_notes("lesson.mp3.link"),
+        vec!["/notes/lesson.md"]
+    );
+    assert!(env.linked_notes("week1/monday.mp3.link").is_empty());
+    assert_eq!(state["recordings"]["old.mp3.link"]["provider"], "unknown");
+
+    // nothing new to do
+    env.run().await;
+    assert_eq!(env.log("audio").len(), 1);
+    assert_eq!(env.log("master").len(), 2);
+}
+
+#[tokio::test]
+async fn test_recording_lifecycle() {
+    let mut env = TestEnvironment::lesson().await;
+    let recorded = now();
+    let file = |id: &str, name: &str| {
+        serde_json::json!({
+            "id": id,
+            "name": name,
+            "file": {},
+            "lastModifiedDateTime": recorded.to_rfc3339(),
+        })
+    };
+    Mock::given(method("GET"))
+        .and(path("/v1.0/me/drive/root:/school/recordings:/children"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "value": [file("item-1", "lesson.mp3"), file("item-2", "other.mp3")],
+        })))
+        .up_to_n_times(1)
+        .mount(&env.server)
+        .await;
+    env.run().await;
+    assert_eq!(
+        env.log("master")[0],
+        "transcribed: lesson.mp3.transcript.md,other.mp3.transcript.md"
+    );
+
+    // notes written afterwards are linked by relinking
+    env.write("notes/summary.md", "# Summary\n");
+    env.git(&["add", "."]);
+    env.git(&["commit", "-q", "-m", "summary"]);
+    crate::jobs::transcription::relink::relink_transcript(&env.config, "lesson.mp3.transcript.md")
+        .unwrap();
+    assert_eq!(env.log("master")[0], "relinked: lesson.mp3.transcript.md");
+    assert_eq!(
+        env.linked_notes("lesson.mp3.link"),
+        vec!["/notes/lesson.md", "/notes/summary.md"]
+    );
+    let summary = env.read("notes/summary.md");
+    assert!(summary.contains(&format!(
+        "](transcript:(5):{})",
+        env.transcript_href("lesson.mp3")
+    )));
+    // relinking doesn't duplicate the existing links
+    crate::jobs::transcription::relink::relink_transcript(&env.config, "lesson.mp3.transcript.md")
+        .unwrap();
+    assert_eq!(env.read("notes/summary.md"), summary);
+    assert_eq!(env.log("master")[1], "summary");
+
+    // lesson.mp3 has been renamed & other.mp3 deleted
+    Mock::given(method("GET"))
+        .and(path("/v1.0/me/drive/root:/school/recordings:/children"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "value": [file("item-1", "renamed.mp3")],
+        })))
+        .mount(&env.server)
+        .await;
+    crate::jobs::audio_linker::link_audio(&env.config)
+        .await
+        .unwrap();
+    env.git(&["checkout", "-q", "audio"]);
+    assert_eq!(
+        env.log("audio")[0],
+        "reconcile: lesson.mp3.link -> renamed.mp3.link,archived other.mp3.link"
+    );
+    assert_eq!(
+        env.read("audio_files/renamed.mp3.link"),
+        format!(
+            "+++\nid = \"item-1\"\nsource = \"onedrive:/school/recordings/\"\n+++\nonedrive:({}):/school/recordings/renamed.mp3",
+            recorded.timestamp()
+        )
+    );
+    assert!(!env.repo.path().join("audio_files/lesson.mp3.link").exists());
+    assert!(env
+        .read("audio_archive/audio_files/other.mp3.link")
+        .ends_with("/school/recordings/other.mp3"));
+
+    env.config.transcription.as_mut().unwrap().mark_orphaned = true;
+    env.run().await;
+    assert_eq!(
+        env.log("master")[0],
+        "renamed: lesson.mp3.link -> renamed.mp3.link; orphaned: other.mp3.link"
+    );
+    let state = env.state();
+    assert_eq!(
+        state["recordings"]["renamed.mp3.link"]["transcript"],
+        "/attachements/lesson.mp3.transcript.md"
+    );
+    assert_eq!(state["recordings"]["other.mp3.link"]["orphaned"], true);
+    assert!(env
+        .read("attachements/other.mp3.transcript.md")
+        .starts_with("> **Orphaned**"));
+    let note = env.read("notes/lesson.md");
+    assert!(note.contains(&format!(
+        " (orphaned)](transcript:(5):{})",
+        env.transcript_href("other.mp3")
+    )));
+    assert!(!note.contains(&format!(
+        " (orphaned)](transcript:(5):{})",
+        env.transcript_href("lesson.mp3")
+    )));
+
+    // nothing changes anymore
+    env.run().await;
+    assert_eq!(env.log("audio").len(), 2);
+    assert_eq!(env.log("master").len(), 5);
+
+    // unlinking removes the link, the transcript & its links inside the notes
+    crate::jobs::transcription::unlink::unlink_transcript(
+        &env.config,
+        "/attachements/lesson.mp3.transcript.md",
+    )
+    .unwrap();
+    assert_eq!(env.log("audio")[0], "remove: renamed.mp3.link");
+    env.git(&["checkout", "-q", "master"]);
+    assert_eq!(env.log("master")[0], "unlinked: lesson.mp3.transcript.md");
+    assert!(!env
+        .repo
+        .path()
+        .join("attachements/lesson.mp3.transcript.md")
+        .exists());
+    assert_eq!(
+        env.state()["recordings"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect_vec(),
+        vec!["other.mp3.link"]
+    );
+    assert_eq!(env.read("notes/summary.md"), "# Summary\n");
+    assert!(!env
+        .read("notes/lesson.md")
+        .contains("/attachements/lesson.mp3.transcript.md"));
+
+    assert!(crate::jobs::transcription::unlink::unlink_transcript(
+        &env.config,
+        "lesson.mp3.transcript.md"
+    )
+    .is_err());
+}
@@ -0,0 +1,69 @@
+use color_eyre::eyre::OptionExt as _;
+
+use crate::utils::config::{Config, TranscriptionProvider};
+
+use super::link::Link;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscriptionResult {
+    pub paragraphs: Vec<Paragraph>,
+    pub summary: String,
+    pub topics: Vec<Topic>,
+    /// id of the request at the provider, if it has one
+    pub request_id: Option<String>,
+    /// length of the recording in seconds, if known
+    pub duration: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Paragraph {
+    /// 0-indexed; `None` if the backend couldn't tell the speakers apart
+    pub speaker: Option<usize>,
+    /// seconds since the start of the recording
+    pub start: f64,
+    /// seconds since the start of the recording
+    pub end: f64,
+    pub sentences: Vec<Sentence>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sentence {
+    pub text: String,
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topic {
+    pub topic: String,
+    pub confidence_score: f64,
+}
+
+/// A service which turns a linked recording into a transcript
+#[async_trait::async_trait]
+pub(crate) trait TranscriptionBackend: Send + Sync {
+    /// short name of the provider, e.g. 'deepgram'
+    fn name(&self) -> &'static str;
+    async fn transcribe(
+        &self,
+        link: &Link,
+        conf: &Config,
+    ) -> color_eyre::Result<TranscriptionResult>;
+}
+
+/// Creates the backend selected by `TranscriptionConfig::provider`
+pub(crate) async fn get_backend(
+    conf: &Config,
+) -> color_eyre::Result<Box<dyn TranscriptionBackend>> {
+    let transcription_conf = conf
+        .transcription
+        .clone()
+        .ok_or_eyre("Expected transcription conf to be initialized")?;
+
+    Ok(match transcription_conf.provider {
+        TranscriptionProvider::Deepgram => Box::new(super::deepgram::DeepgramBackend::new(conf)?),
+        TranscriptionProvider::Local => Box::new(super::local::LocalBackend::new(conf)?),
+        #[cfg(test)]
+        TranscriptionProvider::Mock => Box::new(super::mock::MockBackend {}),
+    })
+}
@@ -1,50 +1,108 @@
-use std::path::PathBuf;
-
 use color_eyre::eyre::{eyre, OptionExt};
 use deepgram::transcription::prerecorded::{
     audio_source::AudioSource,
     options::{self, OptionsBuilder},
-    response::{Paragraph, TopicDetail},
+    response,
 };
-use graph_rs_sdk::{GraphClient, ODataQuery};
 use itertools::Itertools;
-use serde::Deserialize;
 
-use crate::utils::config::{Config, TranscriptionConfig};
+use crate::utils::config::Config;
+
+use super::{
+    backend::{Paragraph, Sentence, Topic, TranscriptionBackend, TranscriptionResult},
+    link::Link,
+    source::{self, AudioLocation},
+};
 
-use super::link::Link;
+pub(crate) struct DeepgramBackend {
+    deepgram: deepgram::Deepgram,
+}
+impl DeepgramBackend {
+    pub(crate) fn new(conf: &Config) -> color_eyre::Result<DeepgramBackend> {
+        let transcription_conf = conf
+            .transcription
+            .clone()
+            .ok_or_eyre("Expected transcription conf to be initialized")?;
+        let key = transcription_conf
+            .deepgram_key
+            .ok_or_eyre("Expected TRANSCRIPTION_DEEPGRAM_KEY to be set")?;
+
+        Ok(DeepgramBackend {
+            deepgram: deepgram::Deepgram::new(key),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl TranscriptionBackend for DeepgramBackend {
+    fn name(&self) -> &'static str {
+        "deepgram"
+    }
+    async fn transcribe(
+        &self,
+        link: &Link,
+        conf: &Config,
+    ) -> color_eyre::Result<TranscriptionResult> {
+        transcribe_link(link, conf, &self.deepgram).await
+    }
+}
 
-#[derive(Debug, Clone)]
-pub struct TranscriptionResult {
-    pub paragraphs: Vec<Paragraph>,
-    pub summary: String,
-    pub topics: Vec<TopicDetail>,
+impl From<response::Paragraph> for Paragraph {
+    fn from(value: response::Paragraph) -> Self {
+        Paragraph {
+            speaker: value.speaker,
+            start: value.start,
+            end: value.end,
+            sentences: value
+                .sentences
+                .into_iter()
+                .map(|x| Sentence {
+                    text: x.text,
+                    start: x.start,
+                    end: x.end,
+                })
+                .collect_vec(),
+        }
+    }
+}
+impl From<response::TopicDetail> for Topic {
+    fn from(value: response::TopicDetail) -> Self {
+        Topic {
+            topic: value.topic,
+            confidence_score: value.confidence_score,
+        }
+    }
 }
 
-pub(crate) async fn transcribe_link(
+async fn transcribe_link(
     link: &Link,
     conf: &Config,
     deepgram: &deepgram::Deepgram,
-    graph: &GraphClient,
 ) -> color_eyre::Result<TranscriptionResult> {
-    let source = get_source(link, conf, graph).await?;
-    let options = OptionsBuilder::new()
+    let (source, temp_file) = get_source(link, conf).await?;
+    let builder = OptionsBuilder::new()
         .model(options::Model::Nova2Meeting)
         .diarize(true)
-        .detect_language(true)
         .summarize("v2")
         .topics(true)
         .smart_format(true)
         .punctuate(true)
-        .paragraphs(true)
-        .build();
+        .paragraphs(true);
+    let options = match &link.metadata.language {
+        Some(x) => builder.language(options::Language::Other(x.clone())),
+        None => builder.detect_language(true),
+    }
+    .build();
 
     //TODO: finish & check if the crate has been updated yet
 
-    let response = deepgram
-        .transcription()
-        .prerecorded(source, &options)
-        .await?;
+    let response = deepgram.transcription().prerecorded(source, &options).await;
+    if let Some(x) = temp_file {
+        if let Err(why) = std::fs::remove_file(&x) {
+            log::warn!("Failed to remove temporary file {:?}: {:?}", x, why);
+        }
+    }
+    let response = response?;
     let res = response
         .clone()
         .results
@@ -71,7 +129,10 @@ pub(crate) async fn transcribe_link(
         .paragraphs
         .clone()
         .ok_or_eyre(format!("Expected to get paragraphs, got {:?}", res))?
-        .paragraphs;
+        .paragraphs
+        .into_iter()
+        .map(Paragraph::from)
+        .collect_vec();
     let topics = match res.clone().topics {
         Some(x) => x
             .segments
@@ -79,6 +140,7 @@ pub(crate) async fn transcribe_link(
             .into_iter()
             .flat_map(|x| x.topics)
             .sorted_by(|a, b| b.confidence_score.total_cmp(&a.confidence_score))
+            .map(Topic::from)
             .collect_vec(),
         None => vec![],
     };
@@ -87,53 +149,27 @@ pub(crate) async fn transcribe_link(
         paragraphs,
         topics,
         summary,
+        request_id: Some(response.metadata.request_id.to_string()),
+        duration: Some(response.metadata.duration),
     })
 }
+/// the temporary file has to be deleted after the request
 async fn get_source(
     link: &Link,
     config: &Config,
-    graph: &GraphClient,
-) -> color_eyre::Result<AudioSource> {
-    let res = match &link.link_target {
-        crate::jobs::transcription::link::LinkType::FileSytemLink(rel_path) => {
-            let path = config
-                .git_directory
-                .join(rel_path.strip_prefix("/").unwrap_or(&rel_path));
+) -> color_eyre::Result<(AudioSource, Option<std::path::PathBuf>)> {
+    let res = match source::resolve_link(link, config).await? {
+        AudioLocation::Local(path) => {
             let file = tokio::fs::File::open(path).await?;
-            AudioSource::from_buffer(file)
-        }
-        crate::jobs::transcription::link::LinkType::WebLink(link) => {
-            AudioSource::from_url(link.clone())
+            (AudioSource::from_buffer(file), None)
         }
-        crate::jobs::transcription::link::LinkType::OneDriveLink(link) => {
-            AudioSource::from_url(get_onedrive_download_link(link.clone(), graph).await?)
+        AudioLocation::Remote(url) => (AudioSource::from_url(url), None),
+        // deepgram can't authenticate itself, so the audio is uploaded
+        AudioLocation::AuthenticatedRemote(url, authorization) => {
+            let path = source::download_to_temp_file(&url, Some(&authorization)).await?;
+            let file = tokio::fs::File::open(&path).await?;
+            (AudioSource::from_buffer(file), Some(path))
         }
     };
     Ok(res)
 }
-#[derive(Debug, Deserialize)]
-struct GraphResponse {
-    #[serde(rename = "@microsoft.graph.downloadUrl")]
-    download_url: String,
-}
-async fn get_onedrive_download_link(
-    path: PathBuf,
-    graph: &GraphClient,
-) -> color_eyre::Result<reqwest::Url> {
-    let path = path.strip_prefix("/").unwrap_or(&path);
-    let file = graph
-        .me()
-        .drive()
-        .item_by_path(format!(
-            ":/{}:",
-            path.to_str()
-                .ok_or_eyre(format!("Expected path {:?} to be parsable", path))?
-        ))
-        .get_items()
-        .select(&["@microsoft.graph.downloadUrl"])
-        .send()
-        .await?
-        .json::<GraphResponse>()
-        .await?;
-    Ok(file.download_url.parse()?)
-}
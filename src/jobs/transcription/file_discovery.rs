@@ -1,7 +1,7 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
-use chrono::{DateTime, Utc};
-use color_eyre::eyre::{Context, OptionExt as _};
+use chrono::Utc;
+use color_eyre::eyre::OptionExt as _;
 use itertools::Itertools;
 
 use crate::utils::{
@@ -9,9 +9,193 @@ use crate::utils::{
     git,
 };
 
+use super::{
+    link::{Link, LinkType},
+    state::{self, RecordingState, State},
+};
+
+/// A .link file found in the audio source dir
+#[derive(Debug, Clone)]
+pub(crate) struct DiscoveredLink {
+    /// absolute path
+    pub path: PathBuf,
+    /// key inside the state file
+    pub key: String,
+    /// sha256 of the link file content
+    pub hash: String,
+    /// content hash of the recording, see `content_hash`
+    pub content_hash: Option<String>,
+    /// see `recording_identity`
+    pub recording: String,
+    pub link: Link,
+}
+
+/// Result of `discover_files`
+pub(crate) struct Discovery {
+    /// links, which have to be transcribed
+    pub links: Vec<DiscoveredLink>,
+    /// duplicates & the key of the recording they duplicate
+    pub duplicates: Vec<(DiscoveredLink, String)>,
+    /// (old key, new key) of links moved by the audio linker; already applied to the state
+    pub renamed: Vec<(String, String)>,
+    /// keys of recordings, whose .link file is gone & which aren't marked as orphaned yet
+    pub orphaned: Vec<String>,
+    pub state: State,
+}
+
 /// Discovers all .link files which have to be transcribed
-/// returns absolute paths
-pub(crate) fn discover_files(conf: &Config) -> color_eyre::Result<Vec<PathBuf>> {
+/// The decision is based on the state file of the target branch (see `State::needs_transcription`), the `skip_transcription` flag of the link header
+/// & `TranscriptionConfig::force`
+/// Recordings with the same content hash as an already transcribed (or earlier discovered) one aren't transcribed again
+/// A new link with the item id of a recording, whose link is gone, is the renamed link of that recording
+pub(crate) fn discover_files(conf: &Config) -> color_eyre::Result<Discovery> {
+    let transcription_conf = conf
+        .transcription
+        .clone()
+        .ok_or_eyre("Expected transcription configuration to be loaded")?;
+    let (links, state) = discover_links(conf)?;
+    classify_links(links, state, &transcription_conf)
+}
+
+/// Decides what happens to the discovered links (see `discover_files`) without touching the repository
+fn classify_links(
+    links: Vec<DiscoveredLink>,
+    mut state: State,
+    transcription_conf: &TranscriptionConfig,
+) -> color_eyre::Result<Discovery> {
+    let mut gone = state
+        .recordings
+        .keys()
+        .filter(|x| {
+            transcription_conf
+                .link_filter
+                .as_ref()
+                .map_or(true, |filter| filter.is_match(x))
+        })
+        .filter(|x| links.iter().all(|y| &y.key != *x))
+        .cloned()
+        .collect_vec();
+    let mut renamed = vec![];
+    for link in links.iter() {
+        if state.recordings.contains_key(&link.key) {
+            continue;
+        }
+        let Some(id) = link.link.item_id() else {
+            continue;
+        };
+        let Some(idx) = gone
+            .iter()
+            .position(|x| state.recordings[x].item_id.as_ref() == Some(&id))
+        else {
+            continue;
+        };
+        let old = gone.remove(idx);
+        log::info!("{} has been renamed to {}", old, link.key);
+        let mut recording = state.recordings.remove(&old).ok_or_eyre("Infallible")?;
+        recording.link_hash = link.hash.clone();
+        state.recordings.insert(link.key.clone(), recording);
+        for x in state.recordings.values_mut() {
+            if x.duplicate_of.as_ref() == Some(&old) {
+                x.duplicate_of = Some(link.key.clone());
+            }
+        }
+        renamed.push((old, link.key.clone()));
+    }
+    let orphaned = gone
+        .into_iter()
+        .filter(|x| !state.recordings[x].orphaned)
+        .collect_vec();
+
+    // content hash -> key of the recording, whose transcript is used
+    let mut transcribed: HashMap<String, String> = state
+        .recordings
+        .iter()
+        .filter(|(_, x)| x.duplicate_of.is_none())
+        .filter_map(|(key, x)| Some((x.content_hash.clone()?, key.clone())))
+        .collect();
+
+    let mut links_to_transcribe = vec![];
+    let mut duplicates = vec![];
+    for link in links {
+        if !transcription_conf.force
+            && !state.needs_transcription(&link.key, &link.hash, &link.recording)
+        {
+            continue;
+        }
+        if link.link.metadata.skip_transcription {
+            log::info!("Skipping {} (skip_transcription)", link.key);
+            continue;
+        }
+        let original = link
+            .content_hash
+            .as_ref()
+            .and_then(|x| transcribed.get(x))
+            .filter(|x| **x != link.key);
+        match original {
+            Some(original) => {
+                log::info!("{} is a duplicate of {}", link.key, original);
+                duplicates.push((link.clone(), original.clone()));
+            }
+            None => {
+                if let Some(x) = &link.content_hash {
+                    transcribed.insert(x.clone(), link.key.clone());
+                }
+                links_to_transcribe.push(link);
+            }
+        }
+    }
+    Ok(Discovery {
+        links: links_to_transcribe,
+        duplicates,
+        renamed,
+        orphaned,
+        state,
+    })
+}
+
+/// Identifies the recording independent of the rest of the .link file (e.g. an edited title or a rewritten header):
+/// its content hash, its id inside the cloud storage or the target of the link
+fn recording_identity(link: &Link, content_hash: Option<&str>) -> color_eyre::Result<String> {
+    if let Some(x) = content_hash.map(|x| x.to_owned()).or(link.item_id()) {
+        return Ok(x);
+    }
+    Ok(match &link.link_target {
+        LinkType::WebLink(x) => x.to_string(),
+        LinkType::FileSytemLink(x) => x.to_string_lossy().into_owned(),
+        x => {
+            let (provider, reference) = x.cloud_file()?.ok_or_eyre("Expected a cloud link")?;
+            format!("{}:{}", provider.scheme(), reference)
+        }
+    })
+}
+
+/// The hash of the link header or the sha256 of the linked file for local links
+/// Expects the source branch to be checked out
+fn content_hash(link: &Link, conf: &Config) -> Option<String> {
+    if let Some(x) = &link.metadata.hash {
+        return Some(x.clone());
+    }
+    let LinkType::FileSytemLink(path) = &link.link_target else {
+        return None;
+    };
+    let path = conf
+        .git_directory
+        .join(path.strip_prefix("/").unwrap_or(path));
+    match state::hash_file(&path) {
+        Ok(x) => Some(format!("sha256:{}", x)),
+        Err(why) => {
+            log::warn!("Failed to hash {:?}: {:?}", path, why);
+            None
+        }
+    }
+}
+
+/// Reads all .link files (matching `TranscriptionConfig::link_filter`) & the state file of the target branch
+/// Subdirectories of the audio source dir are searched as well
+/// If there is no state file yet, it is created from the existing transcripts (matched by path),
+/// so they aren't transcribed again
+/// NOTE: leaves the target branch checked out (except on a dry run without target branch)
+pub(crate) fn discover_links(conf: &Config) -> color_eyre::Result<(Vec<DiscoveredLink>, State)> {
     let transcription_conf = conf
         .transcription
         .clone()
@@ -27,74 +211,256 @@ pub(crate) fn discover_files(conf: &Config) -> color_eyre::Result<Vec<PathBuf>>
 
     let mut link_files = Vec::new();
 
-    for file in std::fs::read_dir(&source_path)? {
-        let dir_entry = match file {
+    for dir_entry in walkdir::WalkDir::new(&source_path).sort_by_file_name() {
+        let dir_entry = match dir_entry {
             Ok(x) => x,
             Err(why) => {
                 log::error!("Skipped dir entry: {:?}", why);
                 continue;
             }
         };
-        if dir_entry.file_type().is_ok_and(|x| x.is_dir()) {
+        if !dir_entry.file_type().is_file() {
+            continue;
+        }
+        if !dir_entry.file_name().to_string_lossy().ends_with(".link") {
+            log::warn!("Skipping non .link file: {:?}", dir_entry.path());
             continue;
         }
-        if let Some(filename) = dir_entry.file_name().to_str() {
-            if !filename.ends_with(".link") {
-                log::warn!("Skipping non .link file: {:?}", dir_entry.path());
+
+        let path = dir_entry.path().to_path_buf();
+        let link = match Link::from_path(&path, conf) {
+            Ok(x) => x,
+            Err(why) => {
+                log::error!("Failed to parse link for file {:?}: {:?}", path, why);
                 continue;
             }
-            let name_without_extension = filename
-                .strip_suffix(".link")
-                .expect("Infallible")
-                .to_owned();
-            debug_assert!(name_without_extension.split(".").last().is_some()); // should be mp3/wav/ or any other configured audio file format
-
-            link_files.push((dir_entry.path(), name_without_extension));
-        }
+        };
+        let content_hash = content_hash(&link, conf);
+        link_files.push(DiscoveredLink {
+            key: state::link_key(&path, conf)?,
+            hash: state::hash_file(&path)?,
+            recording: recording_identity(&link, content_hash.as_deref())?,
+            content_hash,
+            path,
+            link,
+        });
     }
 
+    let matches_filter = |x: &DiscoveredLink| {
+        transcription_conf
+            .link_filter
+            .as_ref()
+            .map_or(true, |filter| filter.is_match(&x.key))
+    };
+
+    // a dry run mustn't create the branch; it is treated as empty instead
+    if conf.dry_run && !git::branch_exists(&transcription_conf.git_target_branch, &conf)? {
+        return Ok((
+            link_files.into_iter().filter(matches_filter).collect_vec(),
+            State::new(),
+        ));
+    }
     let _ = git::check_out_create_branch(&transcription_conf.git_target_branch, &conf)?;
 
-    let mut transcribed_files = vec![];
+    let state = match State::read(conf)? {
+        Some(x) => x,
+        None => {
+            log::info!("No state file found; creating it from the existing transcripts");
+            let mut state = State::new();
+            for discovered in link_files.iter() {
+                let path = super::get_transcript_path(&discovered.path, conf)?;
+                if path.is_file() {
+                    state.recordings.insert(
+                        discovered.key.clone(),
+                        RecordingState {
+                            link_hash: discovered.hash.clone(),
+                            recording: Some(discovered.recording.clone()),
+                            provider: "unknown".to_owned(),
+                            request_id: None,
+                            cost: None,
+                            recorded_at: discovered.link.last_modified,
+                            recording_start: discovered.link.metadata.start,
+                            recording_end: discovered.link.metadata.end,
+                            subject: discovered.link.metadata.subject.clone(),
+                            duration: discovered.link.metadata.duration,
+                            content_hash: discovered.content_hash.clone(),
+                            duplicate_of: None,
+                            item_id: discovered.link.item_id(),
+                            orphaned: false,
+                            transcribed_at: Utc::now(),
+                            transcript: super::to_repo_path(&path, conf),
+                            linked_notes: vec![],
+                        },
+                    );
+                }
+            }
+            state
+        }
+    };
 
-    let target_path = conf.git_directory.join(
-        transcription_conf
-            .transcription_target_path
-            .strip_prefix("/")
-            .unwrap_or(&transcription_conf.transcription_target_path),
+    // the filter is applied last, so the state is always created from all transcripts
+    let links = link_files.into_iter().filter(matches_filter).collect_vec();
+
+    Ok((links, state))
+}
+
+#[test]
+fn test_recording_identity() {
+    let identity = |content: &str| {
+        let link = Link::parse_link_file(content, &chrono_tz::UTC).unwrap();
+        recording_identity(&link, link.metadata.hash.as_deref()).unwrap()
+    };
+    // the audio linker rewrites the reference of renamed recordings & users edit the header
+    assert_eq!(
+        identity("+++\nid = \"item-1\"\n+++\nonedrive:(1720958400):/school/lesson.mp3"),
+        identity("+++\ntitle = \"Photosynthesis\"\nid = \"item-1\"\n+++\nonedrive:(1720958400):/school/renamed.mp3")
+    );
+    assert_eq!(
+        identity("+++\nhash = \"sha256:ab12\"\nid = \"item-1\"\n+++\nonedrive:(1720958400):/a.mp3"),
+        "sha256:ab12"
+    );
+    assert_eq!(
+        identity("+++\nspeakers = [\"Nagisa\"]\n+++\nhttps://example.com/a.mp3"),
+        identity("https://example.com/a.mp3")
     );
-    std::fs::create_dir_all(target_path.clone())?;
+    assert_ne!(
+        identity("dropbox:(1720958400):/a.mp3"),
+        identity("dropbox:(1720958400):/b.mp3")
+    );
+}
 
-    for file in std::fs::read_dir(&target_path)? {
-        let dir_entry = match file {
-            Ok(x) => x,
-            Err(why) => {
-                log::error!("Skipped dir entry: {:?}", why);
-                continue;
-            }
-        };
-        if dir_entry.file_type().is_ok_and(|x| x.is_dir()) {
-            continue;
-        }
-        if let Some(filename) = dir_entry.file_name().to_str() {
-            if !filename.ends_with(".transcript.md") {
-                log::warn!("Skipping non .transcript.md file: {:?}", dir_entry.path());
-                continue;
-            }
-            let name_without_extension = filename
-                .strip_suffix(".transcript.md")
-                .expect("Infallible")
-                .to_owned();
-            debug_assert!(name_without_extension.split(".").last().is_some()); // should be mp3/wav/ or any other configured audio file format
-            transcribed_files.push((dir_entry.path(), name_without_extension))
+#[test]
+fn test_classify_links() {
+    use sha2::Digest as _;
+
+    let vars = crate::utils::config_file::Variables::parse(
+        r#"
+[transcription]
+script_search_path = "/notes/"
+target_path = "/attachements/"
+git_branch = "master"
+audio_branch = "audio"
+audio_source_dir = "/audio_files/"
+"#,
+        std::path::Path::new("korosensei.toml"),
+        None,
+    )
+    .unwrap();
+    let mut conf = TranscriptionConfig::from_environment(&vars).unwrap();
+    let discovered = |key: &str, header: &str| {
+        let content = format!(
+            "+++\n{}\n+++\nonedrive:(1720958400):/school/{}",
+            header,
+            key.trim_end_matches(".link")
+        );
+        let link = Link::parse_link_file(&content, &chrono_tz::UTC).unwrap();
+        let content_hash = link.metadata.hash.clone();
+        DiscoveredLink {
+            path: PathBuf::from("/repo/audio_files").join(key),
+            key: key.to_owned(),
+            hash: format!("{:x}", sha2::Sha256::digest(&content)),
+            recording: recording_identity(&link, content_hash.as_deref()).unwrap(),
+            content_hash,
+            link,
         }
+    };
+    let transcribed = |x: &DiscoveredLink| RecordingState {
+        link_hash: x.hash.clone(),
+        recording: Some(x.recording.clone()),
+        provider: "mock".to_owned(),
+        request_id: None,
+        cost: None,
+        recorded_at: x.link.last_modified,
+        recording_start: None,
+        recording_end: None,
+        subject: None,
+        duration: None,
+        transcribed_at: x.link.last_modified,
+        transcript: PathBuf::new(),
+        linked_notes: vec![],
+        content_hash: x.content_hash.clone(),
+        duplicate_of: None,
+        item_id: x.link.item_id(),
+        orphaned: false,
+    };
+
+    let copy = discovered("copy.mp3.link", "hash = \"sha256:ab12\"");
+    let other = discovered("other.mp3.link", "id = \"item-2\"");
+    let mut state = State::new();
+    for (x, duplicate_of, orphaned) in [
+        (
+            discovered("lesson.mp3.link", "hash = \"sha256:ab12\"\nid = \"item-1\""),
+            None,
+            false,
+        ),
+        (copy.clone(), Some("lesson.mp3.link".to_owned()), false),
+        (other.clone(), None, false),
+        (discovered("old.mp3.link", "id = \"item-3\""), None, true),
+    ] {
+        state.recordings.insert(
+            x.key.clone(),
+            RecordingState {
+                duplicate_of,
+                orphaned,
+                ..transcribed(&x)
+            },
+        );
     }
+    // sorted like the files of the audio dir
+    let links = vec![
+        copy,
+        discovered("lesson (1).mp3.link", "hash = \"sha256:ab12\""),
+        discovered("new (1).mp3.link", "hash = \"sha256:cd34\""),
+        discovered("new.mp3.link", "hash = \"sha256:cd34\""),
+        discovered(
+            "renamed.mp3.link",
+            "hash = \"sha256:ab12\"\nid = \"item-1\"",
+        ),
+        discovered("skipped.mp3.link", "skip_transcription = true"),
+    ];
+    let keys = |x: &[DiscoveredLink]| x.iter().map(|x| x.key.clone()).collect_vec();
 
-    let links_to_transcribe = link_files
-        .into_iter()
-        .filter(|(_, name)| transcribed_files.iter().all(|(_, b)| b != name))
-        .map(|x| x.0)
-        .collect_vec();
+    let discovery = classify_links(links.clone(), state.clone(), &conf).unwrap();
+    assert_eq!(keys(&discovery.links), vec!["new (1).mp3.link"]);
+    // the renamed recording keeps its transcript, so its duplicates don't need another one
+    assert_eq!(
+        discovery
+            .duplicates
+            .iter()
+            .map(|(x, original)| (x.key.as_str(), original.as_str()))
+            .collect_vec(),
+        vec![
+            ("lesson (1).mp3.link", "renamed.mp3.link"),
+            ("new.mp3.link", "new (1).mp3.link")
+        ]
+    );
+    assert_eq!(
+        discovery.renamed,
+        vec![("lesson.mp3.link".to_owned(), "renamed.mp3.link".to_owned())]
+    );
+    assert!(!discovery.state.recordings.contains_key("lesson.mp3.link"));
+    assert_eq!(
+        discovery.state.recordings["renamed.mp3.link"].link_hash,
+        links[4].hash
+    );
+    assert_eq!(
+        discovery.state.recordings["copy.mp3.link"].duplicate_of,
+        Some("renamed.mp3.link".to_owned())
+    );
+    // old.mp3.link has already been marked
+    assert_eq!(discovery.orphaned, vec!["other.mp3.link"]);
+
+    // recordings outside of the filter aren't gone
+    conf.link_filter = Some(regex::Regex::new("^lesson").unwrap());
+    let discovery = classify_links(vec![], state.clone(), &conf).unwrap();
+    assert_eq!(discovery.orphaned, vec!["lesson.mp3.link"]);
+    conf.link_filter = None;
 
-    Ok(links_to_transcribe)
+    assert!(classify_links(vec![other.clone()], state.clone(), &conf)
+        .unwrap()
+        .links
+        .is_empty());
+    conf.force = true;
+    let discovery = classify_links(vec![other], state, &conf).unwrap();
+    assert_eq!(keys(&discovery.links), vec!["other.mp3.link"]);
 }
@@ -4,7 +4,7 @@ use std::{
 };
 
 use crate::utils::{
-    config::{Config, CredentialConfig},
+    config::{Config, CredentialConfig, CredentialProvider, TokenUrlConfig},
     git,
 };
 use chrono::{DateTime, TimeZone, Utc};
@@ -115,13 +115,22 @@ fn test_date_from_name() {
 
     let conf = Config {
         credentials: CredentialConfig {
-            onedrive_access_token_authorization: HeaderValue::from_static(""),
-            onedrive_access_token_url: Url::from_str("http://google.com/").unwrap(),
+            provider: CredentialProvider::TokenUrl,
+            token_url: Some(TokenUrlConfig {
+                onedrive_access_token_authorization: HeaderValue::from_static(""),
+                onedrive_access_token_url: Url::from_str("http://google.com/").unwrap(),
+            }),
+            oauth: None,
+            graph_api_base_url: None,
+            gdrive: None,
+            dropbox: None,
+            webdav: None,
         },
         audio_sync: None,
         git_directory: PathBuf::new(),
         timezone: "Europe/Berlin".parse().unwrap(),
         git_exec: PathBuf::new(),
+        dry_run: false,
         transcription: None,
     };
 
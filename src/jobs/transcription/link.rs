@@ -1,32 +1,44 @@
 use std::{path::PathBuf, str::FromStr};
 
-use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeZone as _, Utc};
 use color_eyre::eyre::{eyre, Context, OptionExt};
 use env_logger::fmt::Timestamp;
 use graph_rs_sdk::GraphClient;
 use itertools::Itertools;
 use reqwest::Url;
+use serde::Deserialize;
 use serde_json::from_str;
 
-use crate::utils::config::Config;
+use crate::utils::config::{Config, StorageProvider};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 /// WARNING: this link struct is only applicable to links in the working directory
 pub struct Link {
     pub link_target: LinkType,
+    /// time the recording ended (or the explicit start / end of the header)
     pub last_modified: DateTime<Utc>,
+    pub metadata: LinkMetadata,
 }
 impl Link {
     /// WARNING: only accepts ABSOLUTE paths
     /// Expects to be in the right git context
     pub fn from_path(path: &PathBuf, config: &Config) -> color_eyre::Result<Link> {
         let content = std::fs::read_to_string(path)?;
-        let mut link = Link::parse_link_file(&content)?;
+        let mut link = Link::parse_link_file(&content, &config.timezone)?;
 
-        match link.link_target {
-            LinkType::OneDriveLink(_) => {}
-            _ => {
-                link.last_modified = super::file_meta::extract_file_change_date(&path, config)?;
+        if let Some(x) = link.metadata.end.or(link.metadata.start) {
+            // the header overrides the modification date
+            link.last_modified = x;
+        } else {
+            match link.link_target {
+                // cloud links store the modification date
+                LinkType::OneDriveLink(_)
+                | LinkType::GoogleDriveLink(_)
+                | LinkType::DropboxLink(_)
+                | LinkType::WebDavLink(_) => {}
+                _ => {
+                    link.last_modified = super::file_meta::extract_file_change_date(&path, config)?;
+                }
             }
         }
 
@@ -38,53 +50,111 @@ impl Link {
         if self.last_modified == crate::utils::time::get_uninitalized_timestamp() {
             return Err(eyre!("Time not properly parsed; got 01.01.0001"));
         }
+        if let Some((start, end)) = self.metadata.start.zip(self.metadata.end) {
+            if start > end {
+                return Err(eyre!(
+                    "Expected the recording to start ({}) before it ends ({})",
+                    start,
+                    end
+                ));
+            }
+        }
         Ok(())
     }
-    fn parse_link_file(content: &str) -> color_eyre::Result<Link> {
+    /// stable id of the recording inside its cloud storage, which is kept when it's renamed or moved
+    pub fn item_id(&self) -> Option<String> {
+        match (&self.metadata.id, &self.link_target) {
+            (Some(x), _) => Some(x.clone()),
+            // Google Drive files are referenced by their id
+            (None, LinkType::GoogleDriveLink(x)) => Some(x.clone()),
+            (None, _) => None,
+        }
+    }
+    /// times without offset in the header are in the given timezone
+    pub(crate) fn parse_link_file(
+        content: &str,
+        timezone: &chrono_tz::Tz,
+    ) -> color_eyre::Result<Link> {
         let lines = content.split("\n").collect_vec();
         if lines.len() == 0 {
             return Err(eyre!("Can't parse empty link file"))?;
         }
-        let line = lines[0];
+        let (metadata, line) = match lines[0].trim() {
+            "+++" => {
+                let header_end = lines
+                    .iter()
+                    .skip(1)
+                    .position(|x| x.trim() == "+++")
+                    .ok_or_eyre("Expected the link header to be closed by '+++'")?
+                    + 1;
+                let metadata = LinkMetadata::parse(&lines[1..header_end].join("\n"), timezone)?;
+                let line = lines
+                    .iter()
+                    .skip(header_end + 1)
+                    .find(|x| !x.trim().is_empty())
+                    .ok_or_eyre("Expected a link after the link header")?;
+                (metadata, line.trim_end_matches('\r'))
+            }
+            _ => (LinkMetadata::default(), lines[0]),
+        };
 
-        return Ok(if line.starts_with("onedrive:") {
-            let (_, timestamp, path) =
-                lazy_regex::regex_captures!("onedrive:\\((\\d{1,})\\):(.*)", line).ok_or_eyre(
-                    format!(
-                        "Expected onedrive link in format onedrive:(timestamp):/path; got {}",
-                        line
-                    ),
-                )?;
+        return Ok(
+            if let Some(storage) = StorageProvider::ALL
+                .iter()
+                .find(|x| line.starts_with(&format!("{}:", x.scheme())))
+            {
+                let (_, timestamp, target) =
+                    lazy_regex::regex_captures!("^[a-z]+:\\((\\d{1,})\\):(.*)", line).ok_or_eyre(
+                        format!(
+                            "Expected {0} link in format {0}:(timestamp):{1}; got {2}",
+                            storage.scheme(),
+                            match storage {
+                                StorageProvider::GoogleDrive => "id",
+                                _ => "/path",
+                            },
+                            line
+                        ),
+                    )?;
 
-            let timestamp = timestamp.parse::<i64>()?;
-            let link = Link {
-                link_target: LinkType::OneDriveLink(
-                    PathBuf::from_str(path).wrap_err(format!("Expected path, got {}", path))?,
-                ),
-                last_modified: DateTime::from_timestamp(timestamp, 0)
-                    .ok_or_eyre(format!("Failed to parse timestamp {}", timestamp))?,
-            };
-            link
-        } else if ["http", "https"]
-            .into_iter()
-            .find(|x| line.starts_with(x))
-            .is_some()
-        {
-            Link {
-                link_target: LinkType::WebLink(
-                    Url::parse(line).wrap_err(format!("Failed to parse URL: {}", line))?,
-                ),
-                last_modified: crate::utils::time::get_uninitalized_timestamp(),
-            }
-        } else {
-            // has to be local file
-            let path = PathBuf::from_str(line)
-                .wrap_err(eyre!("Failed to parse file_system path: {}", line))?;
-            Link {
-                link_target: LinkType::FileSytemLink(path),
-                last_modified: crate::utils::time::get_uninitalized_timestamp(),
-            }
-        });
+                let timestamp = timestamp.parse::<i64>()?;
+                let path =
+                    || PathBuf::from_str(target).wrap_err(format!("Expected path, got {}", target));
+                Link {
+                    link_target: match storage {
+                        StorageProvider::OneDrive => LinkType::OneDriveLink(path()?),
+                        StorageProvider::GoogleDrive => {
+                            LinkType::GoogleDriveLink(target.trim().to_owned())
+                        }
+                        StorageProvider::Dropbox => LinkType::DropboxLink(path()?),
+                        StorageProvider::WebDav => LinkType::WebDavLink(path()?),
+                    },
+                    last_modified: DateTime::from_timestamp(timestamp, 0)
+                        .ok_or_eyre(format!("Failed to parse timestamp {}", timestamp))?,
+                    metadata,
+                }
+            } else if ["http", "https"]
+                .into_iter()
+                .find(|x| line.starts_with(x))
+                .is_some()
+            {
+                Link {
+                    link_target: LinkType::WebLink(
+                        Url::parse(line).wrap_err(format!("Failed to parse URL: {}", line))?,
+                    ),
+                    last_modified: crate::utils::time::get_uninitalized_timestamp(),
+                    metadata,
+                }
+            } else {
+                // has to be local file
+                let path = PathBuf::from_str(line)
+                    .wrap_err(eyre!("Failed to parse file_system path: {}", line))?;
+                Link {
+                    link_target: LinkType::FileSytemLink(path),
+                    last_modified: crate::utils::time::get_uninitalized_timestamp(),
+                    metadata,
+                }
+            },
+        );
     }
 }
 
@@ -98,6 +168,35 @@ fn test_link_parse() {
                     PathBuf::from_str("/assets/audio/audio1.mp3").unwrap(),
                 ),
                 last_modified: DateTime::from_timestamp(1436809466, 0).unwrap(),
+                metadata: LinkMetadata::default(),
+            },
+        ),
+        (
+            "gdrive:(1436809466):1aBcD-eFgH_iJ",
+            Link {
+                link_target: LinkType::GoogleDriveLink("1aBcD-eFgH_iJ".to_owned()),
+                last_modified: DateTime::from_timestamp(1436809466, 0).unwrap(),
+                metadata: LinkMetadata::default(),
+            },
+        ),
+        (
+            "dropbox:(1436809466):/Recordings/audio1.m4a",
+            Link {
+                link_target: LinkType::DropboxLink(
+                    PathBuf::from_str("/Recordings/audio1.m4a").unwrap(),
+                ),
+                last_modified: DateTime::from_timestamp(1436809466, 0).unwrap(),
+                metadata: LinkMetadata::default(),
+            },
+        ),
+        (
+            "webdav:(1436809466):/school/lesson 1.mp3",
+            Link {
+                link_target: LinkType::WebDavLink(
+                    PathBuf::from_str("/school/lesson 1.mp3").unwrap(),
+                ),
+                last_modified: DateTime::from_timestamp(1436809466, 0).unwrap(),
+                metadata: LinkMetadata::default(),
             },
         ),
         (
@@ -108,6 +207,7 @@ fn test_link_parse() {
                         .unwrap(),
                 ),
                 last_modified: crate::utils::time::get_uninitalized_timestamp(),
+                metadata: LinkMetadata::default(),
             },
         ),
         (
@@ -117,12 +217,13 @@ fn test_link_parse() {
                     Url::parse("https://www.youtube.com/watch?v=dQw4w9WgXcQ").unwrap(),
                 ),
                 last_modified: crate::utils::time::get_uninitalized_timestamp(),
+                metadata: LinkMetadata::default(),
             },
         ),
     ];
     for (input, output) in tests {
         assert_eq!(
-            Link::parse_link_file(input).unwrap(),
+            Link::parse_link_file(input, &chrono_tz::UTC).unwrap(),
             output,
             "input {}; expected {:?}",
             input,
@@ -139,4 +240,168 @@ pub enum LinkType {
     WebLink(Url),
     /// Link to a file hosted on onedrive;
     OneDriveLink(PathBuf),
+    /// id of a file hosted on Google Drive
+    GoogleDriveLink(String),
+    /// Link to a file hosted on Dropbox
+    DropboxLink(PathBuf),
+    /// Link to a file on the WebDAV server, relative to WEBDAV_URL
+    WebDavLink(PathBuf),
+}
+impl LinkType {
+    /// the storage & the reference of the file inside it, for links to a cloud storage
+    pub fn cloud_file(&self) -> color_eyre::Result<Option<(StorageProvider, String)>> {
+        let path = |x: &PathBuf| -> color_eyre::Result<String> {
+            Ok(x.to_str()
+                .ok_or_eyre(format!("Expected path {:?} to be parsable", x))?
+                .to_owned())
+        };
+        Ok(match self {
+            LinkType::OneDriveLink(x) => Some((StorageProvider::OneDrive, path(x)?)),
+            LinkType::GoogleDriveLink(x) => Some((StorageProvider::GoogleDrive, x.clone())),
+            LinkType::DropboxLink(x) => Some((StorageProvider::Dropbox, path(x)?)),
+            LinkType::WebDavLink(x) => Some((StorageProvider::WebDav, path(x)?)),
+            LinkType::FileSytemLink(_) | LinkType::WebLink(_) => None,
+        })
+    }
+}
+
+/// Optional toml header of a link file, enclosed by '+++' lines above the link:
+/// ```text
+/// +++
+/// title = "Photosynthesis"
+/// language = "en"
+/// speakers = ["Koro-Sensei", "Nagisa"]
+/// start = 2024-07-14T10:00:00
+/// +++
+/// onedrive:(1720958400):/school/biology.mp3
+/// ```
+/// unknown keys are ignored, so links written for newer versions can still be read
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkMetadata {
+    pub title: Option<String>,
+    pub subject: Option<String>,
+    /// passed to the backend instead of detecting the language, e.g. 'de'
+    pub language: Option<String>,
+    /// names of the speakers in the order the backend numbers them
+    pub speakers: Vec<String>,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    /// the link is kept, but never transcribed
+    pub skip_transcription: bool,
+    /// content hash of the recording as '<algorithm>:<value>', written by the audio linker
+    pub hash: Option<String>,
+    /// id of the file inside the cloud storage, written by the audio linker
+    pub id: Option<String>,
+    /// length of the recording in seconds, written by the audio linker if the storage knows it
+    pub duration: Option<f64>,
+    /// the mapping, which wrote the link (see `AudioMapping::source`); only its links are moved or archived by it
+    pub source: Option<String>,
+}
+#[derive(Debug, Deserialize)]
+struct RawLinkMetadata {
+    title: Option<String>,
+    subject: Option<String>,
+    language: Option<String>,
+    #[serde(default)]
+    speakers: Vec<String>,
+    start: Option<toml::value::Datetime>,
+    end: Option<toml::value::Datetime>,
+    #[serde(default)]
+    skip_transcription: bool,
+    hash: Option<String>,
+    id: Option<String>,
+    duration: Option<f64>,
+    source: Option<String>,
+}
+impl LinkMetadata {
+    fn parse(header: &str, timezone: &chrono_tz::Tz) -> color_eyre::Result<LinkMetadata> {
+        let raw: RawLinkMetadata =
+            toml::from_str(header).wrap_err("Failed to parse the link header")?;
+        let time = |x: Option<toml::value::Datetime>| -> color_eyre::Result<Option<DateTime<Utc>>> {
+            let Some(x) = x else {
+                return Ok(None);
+            };
+            let text = x.to_string();
+            if let Ok(x) = DateTime::parse_from_rfc3339(&text) {
+                return Ok(Some(x.to_utc()));
+            }
+            // local time
+            let local = NaiveDateTime::parse_from_str(&text, "%Y-%m-%dT%H:%M:%S%.f")
+                .wrap_err(format!("Expected a date & time; got {}", text))?;
+            Ok(Some(
+                timezone
+                    .from_local_datetime(&local)
+                    .single()
+                    .ok_or_eyre(format!("Expected {} to be unique in {}", text, timezone))?
+                    .to_utc(),
+            ))
+        };
+        Ok(LinkMetadata {
+            title: raw.title,
+            subject: raw.subject,
+            language: raw.language,
+            speakers: raw.speakers,
+            start: time(raw.start)?,
+            end: time(raw.end)?,
+            skip_transcription: raw.skip_transcription,
+            hash: raw.hash,
+            id: raw.id,
+            duration: raw.duration,
+            source: raw.source,
+        })
+    }
+}
+
+#[test]
+fn test_link_header() {
+    let content = "\
++++
+title = \"Photosynthesis\"
+subject = \"biology\"
+language = \"en\"
+speakers = [\"Koro-Sensei\", \"Nagisa\"]
+start = 2024-07-14T12:00:00
+end = 2024-07-14T10:45:00Z
+duration = 2700
+future_key = 1
++++
+
+onedrive:(1436809466):/school/biology.mp3
+";
+    let link = Link::parse_link_file(content, &chrono_tz::Europe::Berlin).unwrap();
+    assert_eq!(
+        link,
+        Link {
+            link_target: LinkType::OneDriveLink(PathBuf::from("/school/biology.mp3")),
+            last_modified: DateTime::from_timestamp(1436809466, 0).unwrap(),
+            metadata: LinkMetadata {
+                title: Some("Photosynthesis".to_owned()),
+                subject: Some("biology".to_owned()),
+                language: Some("en".to_owned()),
+                speakers: vec!["Koro-Sensei".to_owned(), "Nagisa".to_owned()],
+                start: Some(DateTime::from_timestamp(1720951200, 0).unwrap()),
+                end: Some(DateTime::from_timestamp(1720953900, 0).unwrap()),
+                skip_transcription: false,
+                hash: None,
+                id: None,
+                duration: Some(2700.),
+                source: None,
+            },
+        }
+    );
+    assert!(link.validate_link().is_ok());
+
+    let link = Link::parse_link_file(
+        "+++\nskip_transcription = true\nstart = 2024-07-14T12:00:00Z\nend = 2024-07-14T11:00:00Z\n+++\nhttps://example.com/a.mp3",
+        &chrono_tz::UTC,
+    )
+    .unwrap();
+    assert!(link.metadata.skip_transcription);
+    assert!(link.validate_link().is_err());
+
+    assert!(Link::parse_link_file(
+        "+++\ntitle = \"unclosed\"\nhttps://example.com/a.mp3",
+        &chrono_tz::UTC
+    )
+    .is_err());
 }
@@ -0,0 +1,394 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context as _, OptionExt as _};
+use itertools::Itertools as _;
+use serde::Deserialize;
+
+use crate::utils::config::{Config, LocalOutputFormat, LocalTranscriptionConfig};
+
+use super::{
+    backend::{Paragraph, Sentence, TranscriptionBackend, TranscriptionResult},
+    link::Link,
+    source::{self, AudioLocation},
+};
+
+/// pause (in seconds) between two segments, after which a new paragraph is started
+const PARAGRAPH_PAUSE: f64 = 2.0;
+
+/// Runs a locally installed whisper.cpp-style executable
+/// (`<exec> -m <model> -f <audio> -l <lang> -oj|-osrt -of <prefix>`)
+/// NOTE: the executable has to be able to decode the audio format of the recordings
+pub(crate) struct LocalBackend {
+    conf: LocalTranscriptionConfig,
+}
+impl LocalBackend {
+    pub(crate) fn new(conf: &Config) -> color_eyre::Result<LocalBackend> {
+        let local = conf
+            .transcription
+            .clone()
+            .ok_or_eyre("Expected transcription conf to be initialized")?
+            .local
+            .ok_or_eyre("Expected local transcription conf to be initialized")?;
+        Ok(LocalBackend { conf: local })
+    }
+
+    /// returns the content of the generated output file
+    async fn run_executable(&self, audio: &Path, language: &str) -> color_eyre::Result<String> {
+        let output_prefix =
+            std::env::temp_dir().join(format!("korosensei-{}", rand::random::<u64>()));
+        let (format_flag, extension) = match self.conf.output_format {
+            LocalOutputFormat::Json => ("-oj", "json"),
+            LocalOutputFormat::Srt => ("-osrt", "srt"),
+        };
+        let mut args = vec![
+            "-m".to_owned(),
+            path_to_string(&self.conf.model)?,
+            "-f".to_owned(),
+            path_to_string(audio)?,
+            "-l".to_owned(),
+            language.to_owned(),
+            format_flag.to_owned(),
+            "-of".to_owned(),
+            path_to_string(&output_prefix)?,
+        ];
+        if self.conf.diarize {
+            args.push("-tdrz".to_owned());
+        }
+
+        log::info!("Running {:?} {:?}", self.conf.executable, args);
+        let res = tokio::process::Command::new(&self.conf.executable)
+            .args(&args)
+            .output()
+            .await?;
+        if !res.status.success() {
+            return Err(eyre!(
+                "{:?} failed ({:?}): {}",
+                self.conf.executable,
+                res.status.code(),
+                String::from_utf8_lossy(&res.stderr)
+            ));
+        }
+
+        let output_file =
+            PathBuf::from(format!("{}.{}", path_to_string(&output_prefix)?, extension));
+        let content = tokio::fs::read_to_string(&output_file)
+            .await
+            .wrap_err(format!("Failed to read output file {:?}", output_file))?;
+        if let Err(why) = std::fs::remove_file(&output_file) {
+            log::warn!("Failed to remove {:?}: {:?}", output_file, why);
+        }
+        Ok(content)
+    }
+}
+
+#[async_trait::async_trait]
+impl TranscriptionBackend for LocalBackend {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+    async fn transcribe(
+        &self,
+        link: &Link,
+        conf: &Config,
+    ) -> color_eyre::Result<TranscriptionResult> {
+        let (audio, is_temporary) = match source::resolve_link(link, conf).await? {
+            AudioLocation::Local(path) => (path, false),
+            AudioLocation::Remote(url) => (source::download_to_temp_file(&url, None).await?, true),
+            AudioLocation::AuthenticatedRemote(url, authorization) => (
+                source::download_to_temp_file(&url, Some(&authorization)).await?,
+                true,
+            ),
+        };
+
+        // the language of the link header wins
+        let language = link
+            .metadata
+            .language
+            .as_ref()
+            .unwrap_or(&self.conf.language);
+        let output = self.run_executable(&audio, language).await;
+        if is_temporary {
+            if let Err(why) = std::fs::remove_file(&audio) {
+                log::warn!("Failed to remove temporary file {:?}: {:?}", audio, why);
+            }
+        }
+        let output = output?;
+
+        let segments = match self.conf.output_format {
+            LocalOutputFormat::Json => parse_json_output(&output)?,
+            LocalOutputFormat::Srt => parse_srt_output(&output)?,
+        };
+
+        let paragraphs = segments_to_paragraphs(segments);
+        Ok(TranscriptionResult {
+            duration: paragraphs.last().map(|x| x.end),
+            paragraphs,
+            summary: String::new(),
+            topics: vec![],
+            request_id: None,
+        })
+    }
+}
+
+fn path_to_string(path: &Path) -> color_eyre::Result<String> {
+    Ok(path
+        .to_str()
+        .ok_or_eyre(format!("Expected path {:?} to be parsable", path))?
+        .to_owned())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Segment {
+    /// seconds
+    start: f64,
+    /// seconds
+    end: f64,
+    text: String,
+    speaker: Option<usize>,
+    /// set by tinydiarize, when the speaker changes after this segment
+    speaker_turn_next: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct WhisperOutput {
+    transcription: Vec<WhisperSegment>,
+}
+#[derive(Debug, Deserialize)]
+struct WhisperSegment {
+    offsets: WhisperOffsets,
+    text: String,
+    speaker: Option<String>,
+    #[serde(default)]
+    speaker_turn_next: bool,
+}
+#[derive(Debug, Deserialize)]
+struct WhisperOffsets {
+    /// milliseconds
+    from: u64,
+    /// milliseconds
+    to: u64,
+}
+
+fn parse_json_output(content: &str) -> color_eyre::Result<Vec<Segment>> {
+    let output: WhisperOutput =
+        serde_json::from_str(content).wrap_err("Failed to parse json transcription output")?;
+    Ok(output
+        .transcription
+        .into_iter()
+        .map(|x| Segment {
+            start: x.offsets.from as f64 / 1000.,
+            end: x.offsets.to as f64 / 1000.,
+            text: x.text.trim().to_owned(),
+            speaker: x.speaker.and_then(|x| x.trim().parse().ok()),
+            speaker_turn_next: x.speaker_turn_next,
+        })
+        .collect_vec())
+}
+
+fn parse_srt_output(content: &str) -> color_eyre::Result<Vec<Segment>> {
+    let mut res = Vec::new();
+    let content = content.replace("\r\n", "\n");
+    for block in content.split("\n\n").filter(|x| !x.trim().is_empty()) {
+        let lines = block.trim().split("\n").collect_vec();
+        if lines.len() < 2 {
+            return Err(eyre!(
+                "Expected srt block to have a timestamp; got {:?}",
+                block
+            ));
+        }
+        let (_, from, to) = lazy_regex::regex_captures!(
+            r"^(\d{1,}:\d{2}:\d{2}[,\.]\d{3})\s*-->\s*(\d{1,}:\d{2}:\d{2}[,\.]\d{3})",
+            lines[1].trim()
+        )
+        .ok_or_eyre(format!("Expected srt timestamp line; got {:?}", lines[1]))?;
+
+        let mut text = lines[2..].join(" ").trim().to_owned();
+        let mut speaker = None;
+        if let Some((_, id, rest)) =
+            lazy_regex::regex_captures!(r"^\(speaker (\d{1,})\)(.*)$", &text)
+        {
+            speaker = Some(id.parse::<usize>()?);
+            text = rest.trim().to_owned();
+        }
+        let speaker_turn_next = text.ends_with("[SPEAKER_TURN]");
+        if speaker_turn_next {
+            text = text
+                .strip_suffix("[SPEAKER_TURN]")
+                .expect("Infallible")
+                .trim()
+                .to_owned();
+        }
+
+        res.push(Segment {
+            start: parse_srt_timestamp(from)?,
+            end: parse_srt_timestamp(to)?,
+            text,
+            speaker,
+            speaker_turn_next,
+        });
+    }
+    Ok(res)
+}
+
+/// hh:mm:ss,mmm -> seconds
+fn parse_srt_timestamp(timestamp: &str) -> color_eyre::Result<f64> {
+    let (_, hh, mm, ss, ms) =
+        lazy_regex::regex_captures!(r"^(\d{1,}):(\d{2}):(\d{2})[,\.](\d{3})$", timestamp)
+            .ok_or_eyre(format!("Expected srt timestamp, got {:?}", timestamp))?;
+    Ok(hh.parse::<f64>()? * 3600.
+        + mm.parse::<f64>()? * 60.
+        + ss.parse::<f64>()?
+        + ms.parse::<f64>()? / 1000.)
+}
+
+/// Groups segments into paragraphs
+/// A new paragraph is started when the speaker changes or after a longer pause
+/// Speaker turns (tinydiarize) only tell that the speaker changed, so they alternate between 0 and 1
+fn segments_to_paragraphs(segments: Vec<Segment>) -> Vec<Paragraph> {
+    let mut res: Vec<Paragraph> = Vec::new();
+    let mut turn_speaker = 0;
+    let mut turn = false;
+    for segment in segments {
+        if segment.text.is_empty() {
+            turn |= segment.speaker_turn_next;
+            continue;
+        }
+        if turn {
+            turn_speaker = 1 - turn_speaker;
+        }
+        let speaker = segment.speaker.unwrap_or(turn_speaker);
+        let sentence = Sentence {
+            text: segment.text,
+            start: segment.start,
+            end: segment.end,
+        };
+
+        match res.last_mut() {
+            Some(x)
+                if !turn
+                    && x.speaker == Some(speaker)
+                    && segment.start - x.end < PARAGRAPH_PAUSE =>
+            {
+                x.end = sentence.end;
+                x.sentences.push(sentence);
+            }
+            _ => res.push(Paragraph {
+                speaker: Some(speaker),
+                start: sentence.start,
+                end: sentence.end,
+                sentences: vec![sentence],
+            }),
+        }
+        turn = segment.speaker_turn_next;
+    }
+    res
+}
+
+#[test]
+fn test_parse_json_output() {
+    let input = r#"{
+    "systeminfo": "AVX = 1",
+    "model": {"type": "base"},
+    "result": {"language": "de"},
+    "transcription": [
+        {
+            "timestamps": {"from": "00:00:00,000", "to": "00:00:02,500"},
+            "offsets": {"from": 0, "to": 2500},
+            "text": " Guten Morgen.",
+            "speaker_turn_next": true
+        },
+        {
+            "timestamps": {"from": "00:00:02,500", "to": "00:00:04,000"},
+            "offsets": {"from": 2500, "to": 4000},
+            "text": " Morgen!"
+        }
+    ]
+}"#;
+    assert_eq!(
+        parse_json_output(input).unwrap(),
+        vec![
+            Segment {
+                start: 0.,
+                end: 2.5,
+                text: "Guten Morgen.".to_owned(),
+                speaker: None,
+                speaker_turn_next: true
+            },
+            Segment {
+                start: 2.5,
+                end: 4.,
+                text: "Morgen!".to_owned(),
+                speaker: None,
+                speaker_turn_next: false
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_parse_srt_output() {
+    let input = "\
+1
+00:00:00,000 --> 00:00:02,500
+Guten Morgen. [SPEAKER_TURN]
+
+2
+00:00:02,500 --> 00:01:04,120
+(speaker 1) Morgen!
+Wie geht's?
+";
+    assert_eq!(
+        parse_srt_output(input).unwrap(),
+        vec![
+            Segment {
+                start: 0.,
+                end: 2.5,
+                text: "Guten Morgen.".to_owned(),
+                speaker: None,
+                speaker_turn_next: true
+            },
+            Segment {
+                start: 2.5,
+                end: 64.12,
+                text: "Morgen! Wie geht's?".to_owned(),
+                speaker: Some(1),
+                speaker_turn_next: false
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_segments_to_paragraphs() {
+    let segment = |start: f64, end: f64, text: &str, speaker_turn_next: bool| Segment {
+        start,
+        end,
+        text: text.to_owned(),
+        speaker: None,
+        speaker_turn_next,
+    };
+    let res = segments_to_paragraphs(vec![
+        segment(0., 1., "a", false),
+        segment(1., 2., "b", true),
+        segment(2., 3., "c", false),
+        segment(10., 11., "d", true),
+        segment(11., 12., "", false),
+        segment(12., 13., "e", false),
+    ]);
+    assert_eq!(
+        res.iter()
+            .map(|x| (
+                x.speaker,
+                x.start,
+                x.end,
+                x.sentences.iter().map(|x| x.text.clone()).join(" ")
+            ))
+            .collect_vec(),
+        vec![
+            (Some(0), 0., 2., "a b".to_owned()),
+            (Some(1), 2., 3., "c".to_owned()),
+            (Some(1), 10., 11., "d".to_owned()),
+            (Some(0), 12., 13., "e".to_owned()),
+        ]
+    );
+}
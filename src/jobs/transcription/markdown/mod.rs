@@ -1,11 +1,14 @@
+use std::collections::HashMap;
 use std::ops::Sub as _;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use chrono::{DateTime, Utc};
 use color_eyre::eyre::{eyre, ContextCompat, OptionExt};
 use nodes::block::BlockNode;
-use nodes::link::LinkNode;
+use nodes::code_block::CodeBlockState;
+use nodes::front_matter;
+use nodes::link::{LinkKind, LinkNode, LinkQuery};
 use nodes::paragraph::ParagraphNode;
 use parse_markdown::MarkdownNode;
 use serde::de::Expected;
@@ -28,16 +31,39 @@ pub(crate) struct CorrelatingFile {
     pub headlines: Vec<u64>,
     /// content
     pub content: String,
+    /// key: headline index; time the first line below it has been changed
+    pub written_at: HashMap<u64, DateTime<Utc>>,
 }
 impl CorrelatingFile {
+    /// For every headline the start (in seconds) of the paragraph, which was recorded closest to the time the headline's section was written
+    /// `paragraphs` are the starts of the transcript's paragraphs, the recording started at `recording_start`
+    pub(crate) fn transcript_offsets(
+        &self,
+        paragraphs: &[f64],
+        recording_start: &DateTime<Utc>,
+    ) -> HashMap<u64, f64> {
+        self.written_at
+            .iter()
+            .filter_map(|(headline, time)| {
+                let written = time.sub(*recording_start).num_milliseconds() as f64 / 1000.;
+                paragraphs
+                    .iter()
+                    .min_by(|a, b| (*a - written).abs().total_cmp(&(*b - written).abs()))
+                    .map(|x| (*headline, *x))
+            })
+            .collect()
+    }
+    /// Links the transcript below all headlines; headlines with an offset link to that position of the transcript
     pub(crate) fn link_to_transcript(
         &self,
         transcript_path: PathBuf,
         content: &str,
         transcript_time: &DateTime<Utc>,
+        offsets: &HashMap<u64, f64>,
     ) -> color_eyre::Result<String> {
         let parsed = parse_markdown::parse_markdown(content)?;
-        println!("got parsed: {:#?}", parsed);
+        log::trace!("got parsed: {:#?}", parsed);
+        let query = LinkQuery::new(&parsed);
         let mut stream = ItemStream::new(&parsed.into_iter().rev().collect_vec());
         let whitespace = stream.take_while(|x| {
             x.get_paragraph()
@@ -56,10 +82,23 @@ impl CorrelatingFile {
         headlines.sort();
         headlines.dedup();
 
+        let href = url_escape::encode_path(
+            transcript_path
+                .to_str()
+                .ok_or_eyre("expected transcription path to be parsable")?,
+        )
+        .to_string();
+        let text = transcript_time.format("%d.%m.%Y %H:%M").to_string();
+        let target = |headline: usize| match offsets.get(&(headline as u64)) {
+            // see `template::format_link`
+            Some(x) => format!("transcript:({}):{}", x, href),
+            None => href.clone(),
+        };
+
         let mut general_offset = 0;
         for headline in headlines {
             let mut line_offset = 0;
-            println!("-> searchline_offset: {}", general_offset);
+            log::trace!("-> searchline_offset: {}", general_offset);
             result_buf.extend_from_slice(&stream.take_while(|x| {
                 !x.get_headline()
                     .is_some_and(|x| x.line == (headline + general_offset))
@@ -75,17 +114,38 @@ impl CorrelatingFile {
                     "expected to get headline on line {}",
                     headline
                 )))?;
-            println!("-> H1: {:?}", h);
+            log::trace!("-> H1: {:?}", h);
             result_buf.push(MarkdownNode::Headline(h.clone()));
-            let htmls = stream.take_while(|x| {
-                x.get_html().is_some() || x.get_paragraph().is_some_and(|x| x.content == "")
-            });
-            println!("-> HTMLs: {:?}", htmls);
+            // the underline of a setext heading belongs to it
+            result_buf.extend_from_slice(&stream.take_while(|x| {
+                x.get_line() != h.line
+                    && h.line_span().contains(&x.get_line())
+                    && x.get_block_end().is_none()
+            }));
+            let mut htmls = vec![];
+            loop {
+                htmls.extend(stream.take_while(|x| {
+                    x.get_html().is_some() || x.get_paragraph().is_some_and(|x| x.content == "")
+                }));
+                // a multiline element is a single unit, the links are placed after its closing tag
+                let end = htmls
+                    .iter()
+                    .filter_map(|x| x.get_html())
+                    .map(|x| x.end_line)
+                    .max();
+                let element = stream.take_while(|x| end.is_some_and(|end| x.get_line() <= end));
+                if element.is_empty() {
+                    break;
+                }
+                htmls.extend(element);
+            }
+            log::trace!("-> HTMLs: {:?}", htmls);
             result_buf.extend_from_slice(&htmls);
             // check if is block
             // true: skip past all empty paragraphs -> check if paragraph =
 
             let mut need_header = true;
+            let mut already_linked = false;
             let mut block_end = None;
             let bak = stream.clone();
             let mut block_nodes = vec![];
@@ -94,9 +154,9 @@ impl CorrelatingFile {
                 match next {
                     Some(x) => {
                         if let Some(block_start) = x.get_block_start() {
-                            println!("-> [{}] Existing block", result_buf.len());
+                            log::trace!("-> [{}] Existing block", result_buf.len());
                             // existing block
-                            println!("-> prev: {:?}", stream.preview(1));
+                            log::trace!("-> prev: {:?}", stream.preview(1));
                             block_nodes.push(x.clone());
                             block_nodes.extend_from_slice(&stream.take_while(|x| {
                                 x.get_paragraph()
@@ -111,18 +171,32 @@ impl CorrelatingFile {
                             {
                                 block_nodes.extend_from_slice(&stream.take(1));
 
-                                println!("-> prev: {:?}", stream.preview(1));
-                                println!("-> [{}] all empty lines before links", result_buf.len());
+                                log::trace!("-> prev: {:?}", stream.preview(1));
+                                log::trace!(
+                                    "-> [{}] all empty lines before links",
+                                    result_buf.len()
+                                );
                                 let mut empty_line_counter = block_nodes.len();
                                 // all empty lines before links
-                                block_nodes.extend_from_slice(&stream.take_while(|x| {
+                                let mut empty_lines = stream.take_while(|x| {
                                     x.get_paragraph()
-                                        .is_some_and(|x| x.content == "" || x.content == "")
-                                }));
+                                        .is_some_and(|x| x.content.trim().is_empty())
+                                });
+                                // the whitespace in front of a link isn't an empty line
+                                if let Some(last) = empty_lines.pop() {
+                                    match stream
+                                        .test(|x| x.get_line() == last.get_line())
+                                        .is_some_and(|x| x)
+                                    {
+                                        true => stream.prepend(vec![last]),
+                                        false => empty_lines.push(last),
+                                    }
+                                }
+                                block_nodes.extend_from_slice(&empty_lines);
                                 empty_line_counter = block_nodes.len() - empty_line_counter;
 
-                                println!("-> prev: {:?}", stream.preview(1));
-                                println!("-> [{}] existing links", result_buf.len());
+                                log::trace!("-> prev: {:?}", stream.preview(1));
+                                log::trace!("-> [{}] existing links", result_buf.len());
                                 // existing links
                                 let mut _break = false;
                                 loop {
@@ -139,7 +213,7 @@ impl CorrelatingFile {
                                             stream.take(1)[0].get_paragraph().expect("Infallible");
                                         line = Some(c.line.clone());
                                         block_nodes.push(MarkdownNode::ParagraphNode(c));
-                                        println!("-> prev: {:?}", stream.preview(1));
+                                        log::trace!("-> prev: {:?}", stream.preview(1));
                                     }
                                     if stream.test(|x| x.get_link().is_some()).is_some_and(|x| x) {
                                         let c = stream
@@ -169,14 +243,14 @@ impl CorrelatingFile {
                                 }
 
                                 if _break {
-                                    println!("-> we dont have at least one empty line between _Links & [link]()");
+                                    log::trace!("-> we dont have at least one empty line between _Links & [link]()");
                                     stream = bak;
                                     break;
                                 }
 
-                                println!("-> prev: {:?}", stream.preview(1));
+                                log::trace!("-> prev: {:?}", stream.preview(1));
 
-                                println!("-> [{}] expected end of block", result_buf.len());
+                                log::trace!("-> [{}] expected end of block", result_buf.len());
                                 // expected end of block
                                 if stream
                                     .test(|x| {
@@ -192,25 +266,41 @@ impl CorrelatingFile {
                                             .get_block_end()
                                             .expect("Infallible"),
                                     );
+                                    // already linked: only update the existing link
+                                    if let Some(MarkdownNode::LinkNode(link)) =
+                                        block_nodes.iter_mut().find(|x| {
+                                            x.get_link().is_some_and(|x| {
+                                                links_to_transcript(&query.resolve(&x), &href)
+                                            })
+                                        })
+                                    {
+                                        log::trace!("-> updating existing link {:?}", link);
+                                        // reference links keep pointing to their definition
+                                        if link.kind == LinkKind::Inline {
+                                            link.content = text.clone();
+                                            link.href = target(headline);
+                                        }
+                                        already_linked = true;
+                                    }
                                     result_buf.extend_from_slice(&block_nodes);
 
-                                    println!("-> prev: {:?}", stream.preview(1)); // DEBUG: block end should be on line
+                                    log::trace!("-> prev: {:?}", stream.preview(1)); // DEBUG: block end should be on line
                                     need_header = false;
                                     break;
                                 } else {
-                                    println!("-> no eob");
+                                    log::trace!("-> no eob");
                                     stream = bak;
                                     break;
                                 }
                             } else {
-                                println!("-> prev: {:?}", stream.preview(1));
-                                println!("-> no header");
+                                log::trace!("-> prev: {:?}", stream.preview(1));
+                                log::trace!("-> no header");
                                 stream = bak;
-                                println!("-> prev: {:?}", stream.preview(1));
+                                log::trace!("-> prev: {:?}", stream.preview(1));
                             }
                             break;
                         } else {
-                            println!("-> next is not block start, its {:?}", x);
+                            log::trace!("-> next is not block start, its {:?}", x);
 
                             if stream
                                 .test(|y| {
@@ -220,7 +310,7 @@ impl CorrelatingFile {
                                 && x.get_paragraph()
                                     .is_some_and(|x| x.content.trim().is_empty())
                             {
-                                println!("-> searching forward {:?}", stream.preview(1));
+                                log::trace!("-> searching forward {:?}", stream.preview(1));
                                 block_nodes.push(x);
                                 continue;
                             } else {
@@ -230,19 +320,25 @@ impl CorrelatingFile {
                         }
                     }
                     None => {
-                        println!("-> no existing block");
+                        log::trace!("-> no existing block");
                         stream = bak;
                         break;
                     }
                 }
             }
-            println!(
+            log::trace!(
                 "-> [{}] need_header: {}, last_block: {:?}",
                 result_buf.len(),
                 need_header,
                 block_end
             );
-            let last_item = result_buf
+            if already_linked {
+                result_buf.push(MarkdownNode::BlockEnd(
+                    block_end.ok_or_eyre("Infallible: existing block without end")?,
+                ));
+                continue;
+            }
+            let last_line = result_buf
                 .clone()
                 .into_iter()
                 .chunk_by(|x| x.get_line())
@@ -252,9 +348,8 @@ impl CorrelatingFile {
                 .last()
                 .ok_or_eyre("Expected to have at least one item")?
                 .1
-                .first()
-                .expect("Infallible")
                 .clone();
+            let last_item = last_line.first().expect("Infallible").clone();
             let last_block_level = result_buf
                 .iter()
                 .rev()
@@ -268,8 +363,13 @@ impl CorrelatingFile {
                     _ => 0,
                 })
                 .unwrap_or(0);
-            let mut stripped = last_item.get_stripped();
-            println!(
+            // a new block continues the line of the heading, an existing one its own lines
+            let mut stripped = if need_header {
+                parse_markdown::line_prefix(&last_line)
+            } else {
+                last_item.get_prefix()
+            };
+            log::trace!(
                 "-> [{}] last_item: {:?}; last_block_level: {:?}; prev: {:?}",
                 result_buf.len(),
                 last_item,
@@ -283,7 +383,7 @@ impl CorrelatingFile {
                 let need_spacing_p = last_item
                     .get_paragraph()
                     .is_some_and(|x| !x.get_whitespace().is_empty());
-                println!("-> need spacing?: {}", need_spacing_p);
+                log::trace!("-> need spacing?: {}", need_spacing_p);
                 if need_spacing_p {
                     // we need to add an paragraph header
                     result_buf.push(MarkdownNode::ParagraphNode(ParagraphNode::new(
@@ -296,7 +396,7 @@ impl CorrelatingFile {
                     )));
                 }
 
-                println!("-> [{}] pushing header", result_buf.len());
+                log::trace!("-> [{}] pushing header", result_buf.len());
                 result_buf.push(MarkdownNode::BlockStart(BlockNode::new(
                     last_item.get_line() + 1,
                     last_block_level,
@@ -319,7 +419,7 @@ impl CorrelatingFile {
                     " ".into(),
                     Some(format!(
                         "{}{}>",
-                        last_item.get_stripped().unwrap_or_default(),
+                        stripped.clone().unwrap_or_default(),
                         if need_spacing_p {
                             last_item
                                 .get_paragraph()
@@ -331,13 +431,13 @@ impl CorrelatingFile {
                     )),
                 )));
                 line_offset += 1;
-                println!("-> pushing link seperation section");
+                log::trace!("-> pushing link seperation section");
                 result_buf.push(MarkdownNode::ParagraphNode(ParagraphNode::new(
                     last_item.get_line() + 3,
                     " ".into(),
                     Some(format!(
                         "{}{}>",
-                        last_item.get_stripped().unwrap_or_default(),
+                        stripped.clone().unwrap_or_default(),
                         if need_spacing_p {
                             last_item
                                 .get_paragraph()
@@ -350,7 +450,7 @@ impl CorrelatingFile {
                 )));
                 line_offset += 1;
             } else {
-                println!("-> dont need header");
+                log::trace!("-> dont need header");
                 let line = result_buf
                     .clone()
                     .into_iter()
@@ -365,9 +465,9 @@ impl CorrelatingFile {
 
                 let whitespace = match line.last().expect("Infallible") {
                     MarkdownNode::ParagraphNode(x) => {
-                        println!("-> we got empty line / _Links");
+                        log::trace!("-> we got empty line / _Links");
                         if x.content.trim() == "_Links" {
-                            println!("-> _Links");
+                            log::trace!("-> _Links");
                             // get whitespace from there
                             if let Some(y) = stripped {
                                 stripped = Some(format!("{}>", y));
@@ -375,7 +475,7 @@ impl CorrelatingFile {
                                 stripped = Some(format!(">"));
                             }
 
-                            println!("-> pushing link seperation section");
+                            log::trace!("-> pushing link seperation section");
                             result_buf.push(MarkdownNode::ParagraphNode(ParagraphNode::new(
                                 last_item.get_line() + 1,
                                 x.get_whitespace(),
@@ -386,7 +486,7 @@ impl CorrelatingFile {
 
                             x.get_whitespace()
                         } else if x.content.trim().is_empty() {
-                            println!("-> newline");
+                            log::trace!("-> newline");
                             // is empty line
                             x.content.clone()
                         } else {
@@ -397,19 +497,19 @@ impl CorrelatingFile {
                         }
                     }
                     MarkdownNode::LinkNode(_) => {
-                        println!("-> got link node; searching for paragraph to find whitespace");
+                        log::trace!("-> got link node; searching for paragraph to find whitespace");
                         match line.iter().find(|x| {
                             x.get_paragraph()
                                 .is_some_and(|x| x.content.trim().is_empty())
                         }) {
                             Some(x) => {
-                                println!("-> Found p {:?}", x);
+                                log::trace!("-> Found p {:?}", x);
                                 // take whitespace from here
                                 let p = x.get_paragraph().expect("Infallible");
                                 p.get_whitespace()
                             }
                             None => {
-                                println!("-> found none: line: {:?}", line);
+                                log::trace!("-> found none: line: {:?}", line);
                                 String::new()
                             }
                         }
@@ -422,9 +522,10 @@ impl CorrelatingFile {
                     }
                 };
 
-                println!(
+                log::trace!(
                     "-> got whitespace {:?}; stripped: {:?}; adding space paragraph",
-                    whitespace, stripped
+                    whitespace,
+                    stripped
                 );
                 if !whitespace.is_empty() {
                     result_buf.push(MarkdownNode::ParagraphNode(ParagraphNode::new(
@@ -434,7 +535,7 @@ impl CorrelatingFile {
                     )));
                     line_offset += 1;
                 } else {
-                    println!("-> not added paragraph; whitespace is empty");
+                    log::trace!("-> not added paragraph; whitespace is empty");
                 }
             }
             let last_line_items = result_buf
@@ -450,21 +551,15 @@ impl CorrelatingFile {
                 .clone();
             let last_item = last_line_items.first().expect("Infallible").clone();
 
-            println!(
+            log::trace!(
                 "-> [{}] pushing link; last_line_items: {:?}",
                 result_buf.len(),
                 last_line_items
             );
             result_buf.push(MarkdownNode::LinkNode(LinkNode::new(
                 last_item.get_line(),
-                transcript_time.format("%d.%m.%Y %H:%M").to_string(),
-                url_escape::encode_path(
-                    &transcript_path
-                        .to_str()
-                        .ok_or_eyre("expected transcription path to be parsable")?
-                        .to_string(),
-                )
-                .to_string(),
+                text.clone(),
+                target(headline),
                 if last_line_items.is_empty() {
                     stripped
                 } else {
@@ -474,7 +569,7 @@ impl CorrelatingFile {
             // line_offset += 1;
 
             if need_header {
-                println!("-> [{}] pushing end block", result_buf.len());
+                log::trace!("-> [{}] pushing end block", result_buf.len());
                 let last_block_level = result_buf
                     .iter()
                     .rev()
@@ -491,7 +586,7 @@ impl CorrelatingFile {
                     None,
                 )));
             } else if let Some(x) = block_end {
-                println!("-> [{}] pushing endblock (existing)", result_buf.len());
+                log::trace!("-> [{}] pushing endblock (existing)", result_buf.len());
                 let mut x = MarkdownNode::BlockEnd(x);
                 x.increment_line_by(line_offset);
                 result_buf.push(x);
@@ -517,7 +612,7 @@ impl CorrelatingFile {
                 .1
                 .clone();
 
-            println!("-> newline_offset: {}", new_line_offset);
+            log::trace!("-> newline_offset: {}", new_line_offset);
             // adding an empty line after
             if stream
                 .test(|x| {
@@ -528,8 +623,8 @@ impl CorrelatingFile {
                 })
                 .is_some_and(|x| !x)
             {
-                println!("-> adding empty paragraph for seperation");
-                println!(
+                log::trace!("-> adding empty paragraph for seperation");
+                log::trace!(
                     "-> last_item: {:?}; prev: {:?}",
                     last_item,
                     stream.preview(1)
@@ -540,11 +635,17 @@ impl CorrelatingFile {
                     last_line_before_block
                         .first()
                         .ok_or_eyre("Infallible")?
-                        .get_stripped(),
+                        .get_stripped()
+                        .or_else(|| {
+                            // continue a blockquote opened on the line
+                            parse_markdown::line_prefix(&last_line_before_block)
+                                .map(|x| x.trim_end().to_string())
+                                .filter(|x| !x.is_empty())
+                        }),
                 )));
                 line_offset += 1;
             } else {
-                println!(
+                log::trace!(
                     "-> already got empty line: {:?}; last_line_before_block: {:?}, last_line: {:?}, elem_at_skip {:?}",
                     stream.preview(1),
                     last_line_before_block,
@@ -553,12 +654,12 @@ impl CorrelatingFile {
                 );
             }
 
-            println!(
+            log::trace!(
                 "-> [{}] incrementing lines by offset {}",
                 result_buf.len(),
                 line_offset
             );
-            println!("-> prev: {:?}", stream.preview(1));
+            log::trace!("-> prev: {:?}", stream.preview(1));
             // Increments all the following nodes
             stream = ItemStream::new(
                 &stream
@@ -571,7 +672,7 @@ impl CorrelatingFile {
                     })
                     .collect_vec(),
             );
-            println!("-> ---------------- End: [{}]", result_buf.len());
+            log::trace!("-> ---------------- End: [{}]", result_buf.len());
             general_offset += line_offset;
         }
         if !stream.is_empty() {
@@ -586,18 +687,221 @@ impl CorrelatingFile {
             })
             .collect_vec();
         result_buf.extend_from_slice(&whitespace);
-        println!("{:#?}", result_buf);
+        log::trace!("{:#?}", result_buf);
         let res = parse_markdown::construct_markdown(result_buf)?;
 
         Ok(res)
     }
 }
+/// whether the (resolved) link points to the (encoded) transcript path, with or without offset
+fn links_to_transcript(link: &LinkNode, href: &str) -> bool {
+    // '<...>' allows unencoded hrefs
+    let target = match link
+        .href
+        .strip_prefix('<')
+        .and_then(|x| x.strip_suffix('>'))
+    {
+        Some(x) => url_escape::encode_path(x).to_string(),
+        None => link.href.clone(),
+    };
+    target == href
+        || lazy_regex::regex_captures!(r"^transcript:\([0-9.]+\):(.*)$", &target)
+            .is_some_and(|(_, target)| target == href)
+}
+/// Inverse of `CorrelatingFile::link_to_transcript`: removes every link to the transcript (with or without offset),
+/// including reference links, their definitions & links inside html elements
+/// `_Links` blocks left without links are dropped together with the empty line following them,
+/// all other lines are kept byte-for-byte
+pub(crate) fn remove_transcript_links(
+    content: &str,
+    transcript_path: &Path,
+) -> color_eyre::Result<String> {
+    let href = url_escape::encode_path(
+        transcript_path
+            .to_str()
+            .ok_or_eyre("expected transcription path to be parsable")?,
+    )
+    .to_string();
+    let parsed = parse_markdown::parse_markdown(content)?;
+    let query = LinkQuery::new(&parsed);
+    let transcript_links = |x: &MarkdownNode| {
+        query
+            .links(std::slice::from_ref(x))
+            .into_iter()
+            .filter(|x| links_to_transcript(x, &href))
+            .collect_vec()
+    };
+    // only whitespace & '>'
+    let is_empty = |x: &[MarkdownNode]| {
+        x.iter().all(|x| match x {
+            MarkdownNode::BlockStart(_) | MarkdownNode::BlockEnd(_) => true,
+            MarkdownNode::ParagraphNode(x) => x.content.trim().is_empty(),
+            _ => false,
+        })
+    };
+
+    let original = content.split("\n").collect_vec();
+    let mut lines = vec![Vec::new(); original.len()];
+    for node in parsed.clone() {
+        lines
+            .get_mut(node.get_line())
+            .ok_or_eyre(format!("Expected line {} to exist", node.get_line()))?
+            .push(node);
+    }
+
+    let mut res = original.iter().map(|x| Some(x.to_string())).collect_vec();
+    let mut touched = vec![false; lines.len()];
+    for (idx, nodes) in lines.iter_mut().enumerate() {
+        if nodes.iter().all(|x| transcript_links(x).is_empty()) {
+            continue;
+        }
+        touched[idx] = true;
+        let mut kept: Vec<MarkdownNode> = Vec::new();
+        let mut stripped = None;
+        for mut node in nodes.drain(..) {
+            let links = transcript_links(&node);
+            if let MarkdownNode::HtmlNode(x) = &mut node {
+                // the element stays, only the links inside it are removed
+                for link in links.iter() {
+                    x.original = x.original.replacen(&link.construct(), "", 1);
+                    x.content = x.content.replacen(&link.construct(), "", 1);
+                }
+            } else if !links.is_empty() {
+                // the prefix of the line moves to the next node
+                stripped = stripped.or(node.get_stripped());
+                continue;
+            }
+            if let Some(x) = stripped.take() {
+                node.set_stripped(Some(format!(
+                    "{}{}",
+                    x,
+                    node.get_stripped().unwrap_or_default()
+                )));
+            }
+            kept.push(node);
+        }
+        res[idx] = match is_empty(&kept) {
+            true => None,
+            false => Some(parse_markdown::construct_line(&kept)),
+        };
+        *nodes = kept;
+    }
+
+    // '> _Links' blocks, whose links have all been removed
+    for (idx, nodes) in lines.iter().enumerate() {
+        let Some(links) = nodes.iter().position(|x| {
+            x.get_paragraph()
+                .is_some_and(|x| x.content.trim() == "_Links")
+        }) else {
+            continue;
+        };
+        if !is_empty(&nodes[..links]) || !is_empty(&nodes[links + 1..]) {
+            continue;
+        }
+        // the block starts on this line or continues a blockquote (e.g. below a heading inside it)
+        let level = match nodes[..links]
+            .iter()
+            .filter_map(|x| x.get_block_start())
+            .last()
+        {
+            Some(x) => x.level,
+            None => nodes
+                .first()
+                .and_then(|x| x.get_stripped())
+                .map(|x| x.matches('>').count())
+                .unwrap_or_default(),
+        };
+        if level == 0 {
+            continue;
+        }
+        let block_end = lines
+            .iter()
+            .enumerate()
+            .skip(idx)
+            .find(|(_, x)| {
+                x.iter()
+                    .any(|x| x.get_block_end().is_some_and(|x| x.level <= level))
+            })
+            .map(|(x, _)| x)
+            .unwrap_or(lines.len() - 1);
+        let end = (idx + 1..=block_end)
+            .take_while(|x| is_empty(&lines[*x]))
+            .last()
+            .unwrap_or(idx);
+        if !(idx..=end).any(|x| touched[x]) {
+            continue;
+        }
+        for x in idx..=end {
+            res[x] = None;
+        }
+        // the empty line inserted after the block, '>' inside a blockquote (a trailing one belongs to the end of the file)
+        if end + 2 < original.len()
+            && original[end + 1]
+                .trim_start_matches(|x: char| x == '>' || x.is_whitespace())
+                .is_empty()
+        {
+            res[end + 1] = None;
+        }
+    }
+
+    Ok(res.into_iter().flatten().join("\n"))
+}
+/// Appends the suffix to the text of every link to the transcript (with or without offset), whose text doesn't end with it yet
+/// including reference links; the changed lines are constructed again from their nodes, all other lines are kept byte-for-byte
+pub(crate) fn mark_transcript_links(
+    content: &str,
+    transcript_path: &Path,
+    suffix: &str,
+) -> color_eyre::Result<String> {
+    let href = url_escape::encode_path(
+        transcript_path
+            .to_str()
+            .ok_or_eyre("expected transcription path to be parsable")?,
+    )
+    .to_string();
+    let parsed = parse_markdown::parse_markdown(content)?;
+    let query = LinkQuery::new(&parsed);
+
+    let original = content.split("\n").collect_vec();
+    let mut lines = vec![Vec::new(); original.len()];
+    for node in parsed.clone() {
+        lines
+            .get_mut(node.get_line())
+            .ok_or_eyre(format!("Expected line {} to exist", node.get_line()))?
+            .push(node);
+    }
+
+    let mut res = original.iter().map(|x| x.to_string()).collect_vec();
+    for (idx, nodes) in lines.iter_mut().enumerate() {
+        let mut marked = false;
+        for node in nodes.iter_mut() {
+            let MarkdownNode::LinkNode(link) = node else {
+                continue;
+            };
+            // definitions & autolinks have no text
+            if !matches!(link.kind, LinkKind::Inline | LinkKind::Reference(_))
+                || link.image
+                || link.content.ends_with(suffix)
+                || !links_to_transcript(&query.resolve(link), &href)
+            {
+                continue;
+            }
+            link.content = format!("{}{}", link.content, suffix);
+            marked = true;
+        }
+        if marked {
+            res[idx] = parse_markdown::construct_line(nodes);
+        }
+    }
+    Ok(res.join("\n"))
+}
 #[test]
 fn test_eof_1() {
     let file = CorrelatingFile {
         path: PathBuf::new(),
         headlines: vec![0],
         content: String::new(),
+        written_at: HashMap::new(),
     };
     let input_content = "\
 # Hello World
@@ -614,6 +918,7 @@ fn test_eof_1() {
             PathBuf::from_str("hello").unwrap(),
             input_content,
             &DateTime::from_timestamp(1720958400, 0).unwrap(),
+            &HashMap::new(),
         )
         .unwrap();
     println!("{:#?}", actual_result);
@@ -667,6 +972,7 @@ fn test_whitespace_in_link() {
         path: PathBuf::new(),
         headlines: vec![0],
         content: String::new(),
+        written_at: HashMap::new(),
     };
     let input_content = "\
 # Hello World";
@@ -681,6 +987,7 @@ fn test_whitespace_in_link() {
             PathBuf::from_str("hello world").unwrap(),
             input_content,
             &DateTime::from_timestamp(1720958400, 0).unwrap(),
+            &HashMap::new(),
         )
         .unwrap();
     println!("{:#?}", actual_result);
@@ -697,6 +1004,7 @@ fn test_corelating_file_linkage_full() {
         path: PathBuf::new(),
         headlines: vec![0, 4, 17, 21, 25, 28, 31, 35],
         content: String::new(),
+        written_at: HashMap::new(),
     };
 
     let input_content = "\
@@ -807,6 +1115,7 @@ content
             PathBuf::from_str("/assets/transcriptions/asdf.transcript.md").unwrap(),
             input_content,
             &DateTime::from_timestamp(1720958400, 0).unwrap(),
+            &HashMap::new(),
         )
         .unwrap();
     println!("{:#?}", actual_result);
@@ -823,6 +1132,7 @@ fn harder_tests() {
         path: PathBuf::new(),
         headlines: vec![0, 2],
         content: String::new(),
+        written_at: HashMap::new(),
     };
     let input_content = "\
 # Hello world
@@ -851,6 +1161,7 @@ fn harder_tests() {
             PathBuf::from_str("/assets/transcriptions/asdf.transcript.md").unwrap(),
             input_content,
             &DateTime::from_timestamp(1720958400, 0).unwrap(),
+            &HashMap::new(),
         )
         .unwrap();
     println!("{:#?}", actual_result);
@@ -861,6 +1172,383 @@ fn harder_tests() {
     }
 }
 
+#[test]
+fn test_transcript_offsets() {
+    let recording_start = DateTime::from_timestamp(1720958400, 0).unwrap();
+    let file = CorrelatingFile {
+        path: PathBuf::new(),
+        headlines: vec![0, 2],
+        content: String::new(),
+        written_at: HashMap::from([
+            (0, recording_start + chrono::Duration::seconds(70)),
+            (2, recording_start + chrono::Duration::minutes(30)),
+        ]),
+    };
+    let offsets = file.transcript_offsets(&[0., 62.5, 1200.], &recording_start);
+    assert_eq!(offsets, HashMap::from([(0, 62.5), (2, 1200.)]));
+    assert!(file.transcript_offsets(&[], &recording_start).is_empty());
+
+    let input_content = "# Photosynthesis
+light
+# Calvin cycle
+sugar";
+    let expected = "# Photosynthesis
+> _Links
+> 
+> [14.07.2024 12:00](transcript:(62.5):/attachements/biology.mp3.transcript.md)
+
+light
+# Calvin cycle
+> _Links
+> 
+> [14.07.2024 12:00](transcript:(1200):/attachements/biology.mp3.transcript.md)
+
+sugar";
+    let actual_result = file
+        .link_to_transcript(
+            PathBuf::from_str("/attachements/biology.mp3.transcript.md").unwrap(),
+            input_content,
+            &recording_start,
+            &offsets,
+        )
+        .unwrap();
+    assert_eq!(actual_result, expected);
+}
+
+#[test]
+fn test_idempotent_linking() {
+    let time = DateTime::from_timestamp(1720958400, 0).unwrap();
+    let link =
+        |headlines: Vec<u64>, content: &str, transcript: &str, offsets: &HashMap<u64, f64>| {
+            CorrelatingFile {
+                path: PathBuf::new(),
+                headlines,
+                content: String::new(),
+                written_at: HashMap::new(),
+            }
+            .link_to_transcript(
+                PathBuf::from_str(transcript).unwrap(),
+                content,
+                &time,
+                offsets,
+            )
+            .unwrap()
+        };
+    let input_content = "# Photosynthesis
+light
+# Calvin cycle
+> _Links
+>
+> [Example](https://example.com)
+
+sugar";
+    let linked = link(
+        vec![0, 2],
+        input_content,
+        "/attachements/biology.mp3.transcript.md",
+        &HashMap::new(),
+    );
+    assert_eq!(
+        linked,
+        "# Photosynthesis
+> _Links
+> 
+> [14.07.2024 12:00](/attachements/biology.mp3.transcript.md)
+
+light
+# Calvin cycle
+> _Links
+>
+> [Example](https://example.com)
+> [14.07.2024 12:00](/attachements/biology.mp3.transcript.md)
+
+sugar"
+    );
+    // linking the same transcript again doesn't duplicate the links (the headlines moved by the inserted block)
+    assert_eq!(
+        link(
+            vec![0, 6],
+            &linked,
+            "/attachements/biology.mp3.transcript.md",
+            &HashMap::new()
+        ),
+        linked
+    );
+
+    // existing links are updated
+    let updated = link(
+        vec![6],
+        &linked,
+        "/attachements/biology.mp3.transcript.md",
+        &HashMap::from([(6, 62.5)]),
+    );
+    assert_eq!(
+        updated,
+        linked.replace(
+            "> [Example](https://example.com)\n> [14.07.2024 12:00](/attachements",
+            "> [Example](https://example.com)\n> [14.07.2024 12:00](transcript:(62.5):/attachements"
+        )
+    );
+
+    // other transcripts are appended to the generated block
+    assert_eq!(
+        link(
+            vec![0],
+            &updated,
+            "/attachements/chemistry.mp3.transcript.md",
+            &HashMap::new()
+        ),
+        updated.replace(
+            "> [14.07.2024 12:00](/attachements/biology.mp3.transcript.md)\n",
+            "> [14.07.2024 12:00](/attachements/biology.mp3.transcript.md)\n> [14.07.2024 12:00](/attachements/chemistry.mp3.transcript.md)\n"
+        )
+    );
+
+    // reference links to the transcript count as well
+    let referenced = "# Calvin cycle
+> _Links
+>
+> [lecture][bio]
+
+sugar
+
+[bio]: /attachements/biology.mp3.transcript.md";
+    assert_eq!(
+        link(
+            vec![0],
+            referenced,
+            "/attachements/biology.mp3.transcript.md",
+            &HashMap::new()
+        ),
+        referenced
+    );
+
+    // headings opening a blockquote (or a list item inside one) get a nested block, which is found again
+    for (input, expected) in [
+        (
+            "> # Calvin cycle\n> sugar",
+            "> # Calvin cycle
+> > _Links
+> > 
+> > [14.07.2024 12:00](/attachements/biology.mp3.transcript.md)
+>
+> sugar",
+        ),
+        (
+            "> - # Calvin cycle\n>   sugar",
+            "> - # Calvin cycle
+>   > _Links
+>   > 
+>   > [14.07.2024 12:00](/attachements/biology.mp3.transcript.md)
+>
+>   sugar",
+        ),
+    ] {
+        let linked = link(
+            vec![0],
+            input,
+            "/attachements/biology.mp3.transcript.md",
+            &HashMap::new(),
+        );
+        assert_eq!(linked, expected);
+        assert_eq!(
+            link(
+                vec![0],
+                &linked,
+                "/attachements/biology.mp3.transcript.md",
+                &HashMap::new()
+            ),
+            linked
+        );
+        assert_eq!(
+            remove_transcript_links(
+                &linked,
+                Path::new("/attachements/biology.mp3.transcript.md")
+            )
+            .unwrap(),
+            input
+        );
+    }
+}
+
+#[test]
+fn test_setext_linking() {
+    let input_content = "---
+tags: [biology]
+---
+Photosynthesis
+==============
+light
+
+Calvin cycle
+------------
+sugar";
+    let lines = input_content.split("\n").collect_vec();
+    let headlines = (0..lines.len() as u64)
+        .flat_map(|x| get_related_markdown_headings(x, input_content, true).unwrap())
+        .unique()
+        .collect_vec();
+    assert_eq!(headlines, vec![3, 7]);
+    let file = CorrelatingFile {
+        path: PathBuf::new(),
+        headlines,
+        content: String::new(),
+        written_at: HashMap::new(),
+    };
+    let expected = "---
+tags: [biology]
+---
+Photosynthesis
+==============
+> _Links
+> 
+> [14.07.2024 12:00](/attachements/biology.mp3.transcript.md)
+
+light
+
+Calvin cycle
+------------
+> _Links
+> 
+> [14.07.2024 12:00](/attachements/biology.mp3.transcript.md)
+
+sugar";
+    let actual_result = file
+        .link_to_transcript(
+            PathBuf::from_str("/attachements/biology.mp3.transcript.md").unwrap(),
+            input_content,
+            &DateTime::from_timestamp(1720958400, 0).unwrap(),
+            &HashMap::new(),
+        )
+        .unwrap();
+    assert_eq!(actual_result, expected);
+}
+
+#[test]
+fn test_list_linking() {
+    let time = DateTime::from_timestamp(1720958400, 0).unwrap();
+    let link = |headlines: Vec<u64>, content: &str| {
+        CorrelatingFile {
+            path: PathBuf::new(),
+            headlines,
+            content: String::new(),
+            written_at: HashMap::new(),
+        }
+        .link_to_transcript(
+            PathBuf::from_str("/attachements/biology.mp3.transcript.md").unwrap(),
+            content,
+            &time,
+            &HashMap::new(),
+        )
+        .unwrap()
+    };
+    let input_content = "\
+- Biology
+  ## Photosynthesis
+  light
+- ## Calvin cycle
+  sugar
+- next";
+    let expected = "\
+- Biology
+  ## Photosynthesis
+  > _Links
+  > 
+  > [14.07.2024 12:00](/attachements/biology.mp3.transcript.md)
+  
+  light
+- ## Calvin cycle
+  > _Links
+  > 
+  > [14.07.2024 12:00](/attachements/biology.mp3.transcript.md)
+
+  sugar
+- next";
+    let linked = link(vec![1, 3], input_content);
+    assert_eq!(linked, expected);
+    // the list isn't broken: the headings are still found inside the items
+    assert_eq!(
+        get_related_markdown_headings(12, &linked, false).unwrap(),
+        vec![7]
+    );
+    assert_eq!(link(vec![1, 7], &linked), linked);
+
+    // items continued with a tab get a space indented block
+    let input_content = "- Biology\n\t## Photosynthesis\n\tlight";
+    let expected = "- Biology
+\t## Photosynthesis
+    > _Links
+    > 
+    > [14.07.2024 12:00](/attachements/biology.mp3.transcript.md)
+\t
+\tlight";
+    let linked = link(vec![1], input_content);
+    assert_eq!(linked, expected);
+    assert_eq!(link(vec![1], &linked), linked);
+    assert_eq!(
+        remove_transcript_links(
+            &linked,
+            Path::new("/attachements/biology.mp3.transcript.md")
+        )
+        .unwrap(),
+        input_content
+    );
+}
+
+#[test]
+fn test_html_linking() {
+    let input_content = "\
+## Exercise
+<details>
+<summary>Solution</summary>
+
+# not linked
+</details>
+<!--
+# commented out
+-->
+text";
+    let lines = input_content.split("\n").collect_vec();
+    let headlines = (0..lines.len() as u64)
+        .flat_map(|x| get_related_markdown_headings(x, input_content, false).unwrap())
+        .unique()
+        .collect_vec();
+    assert_eq!(headlines, vec![0, 4]);
+    let file = CorrelatingFile {
+        path: PathBuf::new(),
+        headlines: vec![0],
+        content: String::new(),
+        written_at: HashMap::new(),
+    };
+    let expected = "\
+## Exercise
+<details>
+<summary>Solution</summary>
+
+# not linked
+</details>
+<!--
+# commented out
+-->
+> _Links
+> 
+> [14.07.2024 12:00](/attachements/biology.mp3.transcript.md)
+
+text";
+    let link = |content: &str| {
+        file.link_to_transcript(
+            PathBuf::from_str("/attachements/biology.mp3.transcript.md").unwrap(),
+            content,
+            &DateTime::from_timestamp(1720958400, 0).unwrap(),
+            &HashMap::new(),
+        )
+        .unwrap()
+    };
+    let linked = link(input_content);
+    assert_eq!(linked, expected);
+    assert_eq!(link(&linked), linked);
+}
+
 /// gets the nearest (direction: up) heading
 /// when `include_parents == true` then also the next parent headings
 pub(crate) fn get_related_markdown_headings(
@@ -872,22 +1560,56 @@ pub(crate) fn get_related_markdown_headings(
     if line as usize >= lines.len() {
         return Err(eyre!("searchline out of index"));
     }
-    let mut lines = lines
-        .into_iter()
-        .take((line + 1) as usize)
-        .collect::<Vec<_>>();
-    lines.reverse();
-    println!("{:?}", lines);
+    // '#' inside code samples & the front matter isn't a heading
+    let front_matter_end = front_matter::find_front_matter(&lines).map(|(_, end)| end);
+    let mut code = CodeBlockState::default();
+    let mut underlined = false;
+    let mut in_comment = false;
+    let mut levels = Vec::new();
+    for (idx, x) in lines.iter().enumerate().take((line + 1) as usize) {
+        let (markers, rest) = parse_markdown::split_block_markers(x);
+        let quote_level = markers.matches('>').count();
+        if front_matter_end.is_some_and(|end| idx <= end)
+            || std::mem::take(&mut underlined)
+            || code.parse(rest, idx, quote_level).is_some()
+        {
+            levels.push(None);
+            continue;
+        }
+        // '#' inside a multiline html comment isn't a heading either
+        if std::mem::take(&mut in_comment) {
+            in_comment = !x.contains("-->");
+            levels.push(None);
+            continue;
+        }
+        in_comment = x
+            .rfind("<!--")
+            .is_some_and(|start| !x[start..].contains("-->"));
+        levels.push(
+            // headings can start list items
+            match lazy_regex::regex_captures!(
+                r"^[\s>]*(?:(?:[-*+]|[0-9]{1,9}[.)])\s+)?(#{1,})(.*)$",
+                x
+            ) {
+                Some((_, level, _)) => Some(level.len()),
+                None => {
+                    let level = parse_markdown::setext_heading(&lines, idx, rest, quote_level);
+                    underlined = level.is_some();
+                    level
+                }
+            },
+        );
+    }
+    log::trace!("{:?}", levels);
 
     let mut my_level = usize::MAX;
     let mut res = vec![];
-    for (idx, line_str) in lines.into_iter().enumerate() {
-        if let Some((_, level, _)) = lazy_regex::regex_captures!("^[\\s>]*(#{1,})(.*)$", line_str) {
-            println!("{} [{}]", level, my_level);
-            let level = level.len();
+    for (idx, level) in levels.into_iter().enumerate().rev() {
+        if let Some(level) = level {
+            log::trace!("{} [{}]", level, my_level);
             if my_level > level {
                 my_level = level;
-                res.push(line - idx as u64);
+                res.push(idx as u64);
             }
             if !include_parents {
                 break;
@@ -932,3 +1654,92 @@ content
         );
     }
 }
+
+#[test]
+fn test_remove_transcript_links() {
+    let time = DateTime::from_timestamp(1720958400, 0).unwrap();
+    let transcript = PathBuf::from("/attachements/lesson 1.mp3.transcript.md");
+    for (input, headlines) in [
+        ("# Hello World\n", vec![0]),
+        ("# Hello World", vec![0]),
+        ("# Mocking\nmocks are great\n", vec![0]),
+        (
+            "# Mocking\n\nmocks are great\n## Stubs\nstubs too",
+            vec![0, 3],
+        ),
+        (
+            "# Hello world\n<!-- test comment -->\n> Normal callout\ncontent",
+            vec![0],
+        ),
+        (
+            "### Append Test\n>\n> _Links\n>\n> [Example]()\n\ncontent",
+            vec![0],
+        ),
+        ("# Outer\n> # Inner\n> text\n", vec![1]),
+    ] {
+        let file = CorrelatingFile {
+            path: PathBuf::new(),
+            headlines: headlines.clone(),
+            content: String::new(),
+            written_at: HashMap::new(),
+        };
+        let offsets = headlines.iter().map(|x| (*x, 62.5)).collect();
+        for offsets in [HashMap::new(), offsets] {
+            let linked = file
+                .link_to_transcript(transcript.clone(), input, &time, &offsets)
+                .unwrap();
+            assert_ne!(linked, input);
+            assert_eq!(
+                remove_transcript_links(&linked, &transcript).unwrap(),
+                input,
+                "{}",
+                linked
+            );
+        }
+    }
+
+    // other links & blocks stay
+    let input = "\
+# Mocking
+> _Links
+>
+> [14.07.2024 12:00](/attachements/other.mp3.transcript.md)
+> [14.07.2024 12:00](transcript:(62.5):/attachements/lesson%201.mp3.transcript.md)
+
+see [the lesson](/attachements/lesson%201.mp3.transcript.md)
+> _Links
+";
+    let expected = "\
+# Mocking
+> _Links
+>
+> [14.07.2024 12:00](/attachements/other.mp3.transcript.md)
+
+see 
+> _Links
+";
+    assert_eq!(
+        remove_transcript_links(input, &transcript).unwrap(),
+        expected
+    );
+    // reference links, their definitions & links inside html elements
+    let input = "\
+# Mocking
+see [the lesson][lesson] or <mark>[this](<lesson 1.mp3.transcript.md>)</mark>
+[other][]
+
+[lesson]: /attachements/lesson%201.mp3.transcript.md
+[other]: /attachements/other.mp3.transcript.md";
+    let expected = "\
+# Mocking
+see  or <mark></mark>
+[other][]
+
+[other]: /attachements/other.mp3.transcript.md";
+    assert_eq!(
+        remove_transcript_links(input, Path::new("lesson 1.mp3.transcript.md"))
+            .and_then(|x| remove_transcript_links(&x, &transcript))
+            .unwrap(),
+        expected
+    );
+}
@@ -0,0 +1,104 @@
+/// A line of a fenced (```` ``` ````, `~~~`) or indented (4 spaces, tab) code block
+/// Its content isn't parsed, so headlines, links & html inside code samples are ignored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlockNode {
+    pub line: usize,
+    /// opening fence of the block (e.g. "```"); None for indented code blocks
+    pub fence: Option<String>,
+    /// the whole line (without the stripped blockquote prefix)
+    pub content: String,
+    pub stripped: Option<String>,
+}
+impl CodeBlockNode {
+    pub fn new(
+        line: usize,
+        fence: Option<String>,
+        content: String,
+        stripped: Option<String>,
+    ) -> CodeBlockNode {
+        CodeBlockNode {
+            line,
+            fence,
+            content,
+            stripped,
+        }
+    }
+    pub fn construct(&self) -> String {
+        self.content.clone()
+    }
+}
+
+/// Tracks the code blocks while reading a document line by line
+#[derive(Debug, Clone, Default)]
+pub struct CodeBlockState {
+    /// opening fence & blockquote level of the currently open fenced block
+    fence: Option<(String, usize)>,
+    /// the previous line is part of an indented block
+    indented: bool,
+    /// the previous line is empty; an indented block can't interrupt a paragraph
+    after_empty_line: bool,
+    /// the last non-empty line is a list item; indented lines after it continue the item
+    after_list_item: bool,
+}
+impl CodeBlockState {
+    /// whether a fenced block is open; its lines aren't blockquote markers anymore
+    pub fn in_fence(&self) -> bool {
+        self.fence.is_some()
+    }
+    /// `content`: the line without the blockquote prefix; `level`: number of blockquotes the line is in
+    /// returns the node if the line belongs to a code block
+    pub fn parse(&mut self, content: &str, line: usize, level: usize) -> Option<CodeBlockNode> {
+        // the space after '>' belongs to the blockquote
+        let text = match level {
+            0 => content,
+            _ => content.strip_prefix(' ').unwrap_or(content),
+        };
+        let node =
+            |fence: Option<String>| Some(CodeBlockNode::new(line, fence, content.to_owned(), None));
+
+        if let Some((fence, fence_level)) = self.fence.clone() {
+            // the block ends with its blockquote
+            if level >= fence_level {
+                if lazy_regex::regex_captures!(r"^\s{0,3}(`{3,}|~{3,})\s*$", text)
+                    .is_some_and(|(_, x)| x.starts_with(&fence[..1]) && x.len() >= fence.len())
+                {
+                    self.fence = None;
+                }
+                return node(Some(fence));
+            }
+            self.fence = None;
+        }
+
+        if let Some((_, fence, info)) =
+            lazy_regex::regex_captures!(r"^\s{0,3}(`{3,}|~{3,})(.*)$", text)
+        {
+            // backtick fences can't contain backticks in their info string
+            if !(fence.starts_with('`') && info.contains('`')) {
+                self.fence = Some((fence.to_owned(), level));
+                self.indented = false;
+                self.after_empty_line = false;
+                return node(Some(fence.to_owned()));
+            }
+        }
+
+        let is_empty = text.trim().is_empty();
+        if !is_empty
+            && (text.starts_with("    ") || text.starts_with('\t'))
+            && (self.indented || (self.after_empty_line && !self.after_list_item))
+        {
+            self.indented = true;
+            self.after_empty_line = false;
+            return node(None);
+        }
+
+        self.indented = false;
+        self.after_empty_line = is_empty;
+        if !is_empty {
+            // indented lines continue the item
+            self.after_list_item =
+                lazy_regex::regex_is_match!(r"^\s*([-*+]|[0-9]{1,9}[.)])(\s|$)", text)
+                    || (self.after_list_item && text.starts_with(char::is_whitespace));
+        }
+        None
+    }
+}
@@ -0,0 +1,44 @@
+/// A line of the YAML (`---`) or TOML (`+++`) front matter at the top of a note
+/// Its content isn't parsed & nothing is ever linked inside or above it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrontMatterNode {
+    pub line: usize,
+    /// "---" or "+++"
+    pub delimiter: String,
+    /// the whole line
+    pub content: String,
+    pub stripped: Option<String>,
+}
+impl FrontMatterNode {
+    pub fn new(
+        line: usize,
+        delimiter: String,
+        content: String,
+        stripped: Option<String>,
+    ) -> FrontMatterNode {
+        FrontMatterNode {
+            line,
+            delimiter,
+            content,
+            stripped,
+        }
+    }
+    pub fn construct(&self) -> String {
+        self.content.clone()
+    }
+}
+
+/// (delimiter, line of the closing delimiter) if the document starts with a front matter
+pub fn find_front_matter(lines: &[&str]) -> Option<(String, usize)> {
+    let delimiter = lines.first()?.trim_end();
+    let closing: &[&str] = match delimiter {
+        "---" => &["---", "..."],
+        "+++" => &["+++"],
+        _ => return None,
+    };
+    let end = lines
+        .iter()
+        .skip(1)
+        .position(|x| closing.contains(&x.trim_end()))?;
+    Some((delimiter.to_owned(), end + 1))
+}
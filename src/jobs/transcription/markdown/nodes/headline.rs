@@ -1,3 +1,5 @@
+use std::ops::RangeInclusive;
+
 use color_eyre::eyre::OptionExt as _;
 
 use crate::utils::char_stream::ItemStream;
@@ -10,6 +12,8 @@ pub struct HeadlineNode {
     pub content: String,
     pub original: String,
     pub stripped: Option<String>,
+    /// line of the underline of a setext heading (`===` / `---`); None for '#' headings
+    pub underline: Option<usize>,
 }
 impl HeadlineNode {
     pub fn parse(content: &mut ItemStream<char>, line: usize) -> color_eyre::Result<Self>
@@ -32,6 +36,10 @@ impl HeadlineNode {
     pub fn construct(&self) -> String {
         self.original.clone()
     }
+    /// the lines the headline spans (including the underline of a setext heading)
+    pub fn line_span(&self) -> RangeInclusive<usize> {
+        self.line..=self.underline.unwrap_or(self.line)
+    }
     pub fn new(
         line: usize,
         level: usize,
@@ -45,6 +53,52 @@ impl HeadlineNode {
             content,
             original,
             stripped,
+            underline: None,
         }
     }
 }
+
+/// The `===` / `---` line below the text of a setext heading
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnderlineNode {
+    pub line: usize,
+    pub original: String,
+    pub stripped: Option<String>,
+}
+impl UnderlineNode {
+    pub fn new(line: usize, original: String, stripped: Option<String>) -> UnderlineNode {
+        UnderlineNode {
+            line,
+            original,
+            stripped,
+        }
+    }
+    pub fn construct(&self) -> String {
+        self.original.clone()
+    }
+}
+
+/// level of the setext heading, if `text` is underlined by `underline` ('=': 1, '-': 2)
+/// only the first line of a paragraph is accepted; all lines are without their blockquote markers
+pub fn setext_level(previous: Option<&str>, text: &str, underline: &str) -> Option<usize> {
+    let (_, underline) = lazy_regex::regex_captures!(r"^\s{0,3}(=+|-+)\s*$", underline)?;
+    let is_underline = |x: &str| lazy_regex::regex_is_match!(r"^\s{0,3}(=+|-+)\s*$", x);
+    let starts_paragraph = previous.map_or(true, |x| {
+        x.trim().is_empty() || is_underline(x) || lazy_regex::regex_is_match!(r"^\s{0,3}#", x)
+    });
+    // headings, list items, code, html & thematic breaks can't be underlined
+    if !starts_paragraph
+        || text.trim().is_empty()
+        || is_underline(text)
+        || lazy_regex::regex_is_match!(
+            r"^(\s{4}|\t|\s{0,3}(#|[-*+](\s|$)|[0-9]{1,9}[.)](\s|$)|```|~~~|<|([-*_]\s*){3,}$))",
+            text
+        )
+    {
+        return None;
+    }
+    Some(match underline.starts_with('=') {
+        true => 1,
+        false => 2,
+    })
+}
@@ -1,9 +1,55 @@
 use color_eyre::eyre::eyre;
 use itertools::Itertools;
 
-use crate::utils::char_stream::ItemStream;
+use crate::{
+    jobs::transcription::markdown::parse_markdown::MarkdownNode, utils::char_stream::ItemStream,
+};
 
-//NOTE: this is a simple implementation: the content will not be parsed, and multiline html tags cant be parsed
+/// tags, which are never closed
+const VOID_TAGS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+/// tags starting a html block, which may be closed on a later line
+const BLOCK_TAGS: [&str; 22] = [
+    "article",
+    "aside",
+    "blockquote",
+    "center",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "fieldset",
+    "figure",
+    "footer",
+    "form",
+    "header",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "section",
+    "summary",
+    "table",
+    "ul",
+    "li",
+];
+pub const COMMENT_TAG: &str = "<!--...-->";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HtmlKind {
+    /// opened & closed on the same line, or self closing
+    Element,
+    /// opening tag of an element, which is closed on a later line (see `HtmlNode::end_line`)
+    Open,
+    /// a whole line inside a multiline comment
+    Inner,
+    /// closing tag of an element opened on an earlier line
+    Close,
+}
+
+//NOTE: the content of an element on a single line is parsed into `children`, multiline elements are split into an opening & a closing node
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HtmlNode {
     pub line: usize,
@@ -11,15 +57,24 @@ pub struct HtmlNode {
     pub content: String,
     pub attribute: String,
     pub original: String,
+    pub kind: HtmlKind,
+    /// line of the closing tag; `line` unless the element spans several lines
+    pub end_line: usize,
+    /// number of multiline elements this node is nested in
+    pub depth: usize,
+    /// the parsed content of an element on a single line (comments aren't parsed); only `original` is constructed
+    pub children: Vec<MarkdownNode>,
 
     pub stripped: Option<String>,
 }
 impl HtmlNode {
+    /// `open`: tags of the multiline elements, which haven't been closed yet
     pub fn parse(
         stream: &mut ItemStream<char>,
         line: usize,
+        open: &[String],
     ) -> color_eyre::Result<Option<HtmlNode>> {
-        println!("------- [{}]", line);
+        log::trace!("------- [{}]", line);
         let bak = stream.clone();
         if stream.take(1) != vec!['<'] {
             return Err(eyre!("Expected to get link starting with '['"));
@@ -38,7 +93,7 @@ impl HtmlNode {
         original.extend_from_slice(&comment_indicator);
         let mut tag = String::new();
         if comment_indicator == vec!['!'] {
-            println!("-> comment");
+            log::trace!("-> comment");
             // comment
 
             tag = "<!--...-->".into();
@@ -65,8 +120,19 @@ impl HtmlNode {
                         }
                     }
                     None => {
-                        *stream = bak;
-                        return Ok(None);
+                        // closed on a later line
+                        log::trace!("-> multiline comment");
+                        return Ok(Some(HtmlNode {
+                            kind: HtmlKind::Open,
+                            ..HtmlNode::new(
+                                line,
+                                tag,
+                                content.into_iter().join(""),
+                                original.into_iter().join(""),
+                                "".to_string(),
+                                None,
+                            )
+                        }));
                     }
                 }
             }
@@ -78,8 +144,27 @@ impl HtmlNode {
                 "".to_string(),
                 None,
             )));
+        } else if comment_indicator == vec!['/'] {
+            log::trace!("-> closing tag");
+            let name = stream.take_while(|x| x.is_alphanumeric() || x == '-');
+            let name = name.into_iter().collect::<String>();
+            if stream.take(1) != vec!['>'] || !open.iter().any(|x| x.eq_ignore_ascii_case(&name)) {
+                *stream = bak;
+                return Ok(None);
+            }
+            return Ok(Some(HtmlNode {
+                kind: HtmlKind::Close,
+                ..HtmlNode::new(
+                    line,
+                    name.clone(),
+                    "".into(),
+                    format!("</{}>", name),
+                    "".into(),
+                    None,
+                )
+            }));
         } else {
-            println!("-> normal tag");
+            log::trace!("-> normal tag");
             // normal tag
             tag.push_str(&comment_indicator[0].to_string());
             let ttag = stream.take_while(|x| !vec![' ', '/', '>'].contains(&x));
@@ -89,7 +174,7 @@ impl HtmlNode {
             match stream.test(|x| x == ' ') {
                 Some(x) => {
                     if x {
-                        println!("-> attribute");
+                        log::trace!("-> attribute");
                         original.extend_from_slice(&stream.take(1));
                         let attr = stream.take_while(|x| !vec!['/', '>'].contains(&x));
                         original.extend_from_slice(&attr);
@@ -99,7 +184,7 @@ impl HtmlNode {
                     }
                 }
                 None => {
-                    println!("-> attr EOL");
+                    log::trace!("-> attr EOL");
                     // EOL
                     *stream = bak;
                     return Ok(None);
@@ -110,7 +195,7 @@ impl HtmlNode {
             match stream.test_window(vec!['/', '>']) {
                 Some(x) => {
                     if x {
-                        println!("-> self closing");
+                        log::trace!("-> self closing");
                         original.extend_from_slice(&stream.take(2));
                         return Ok(Some(HtmlNode::new(
                             line,
@@ -123,10 +208,12 @@ impl HtmlNode {
                     }
                 }
                 None => {
-                    println!("-> self closing EOL {:?}", stream.preview(stream.len()));
-                    // EOL
-                    *stream = bak;
-                    return Ok(None);
+                    log::trace!("-> self closing EOL {:?}", stream.preview(stream.len()));
+                    // EOL, unless the line ends with the '>' of the tag
+                    if !stream.test(|x| x == '>').is_some_and(|x| x) {
+                        *stream = bak;
+                        return Ok(None);
+                    }
                 }
             }
             match stream.test(|x| x == '>') {
@@ -139,10 +226,10 @@ impl HtmlNode {
                         )));
                     }
                     original.extend_from_slice(&stream.take(1));
-                    println!("-> normal closing");
+                    log::trace!("-> normal closing");
                 }
                 None => {
-                    println!("-> normal closing EOL");
+                    log::trace!("-> normal closing EOL");
                     // EOL
                     *stream = bak;
                     return Ok(None);
@@ -150,8 +237,9 @@ impl HtmlNode {
             }
 
             let window = format!("</{}>", tag).chars().collect_vec();
+            let after_tag = stream.clone();
             let mut content = Vec::new();
-            println!("-> window search: {:?}", content);
+            log::trace!("-> window search: {:?}", content);
             loop {
                 match stream.test_window_custom(window.clone(), |(a, b)| {
                     a.to_ascii_lowercase() == b.to_ascii_lowercase()
@@ -175,8 +263,33 @@ impl HtmlNode {
                     }
                     None => {
                         // EOL
-                        *stream = bak;
-                        return Ok(None);
+                        let at_line_start = bak
+                            .get_history()
+                            .iter()
+                            .all(|x| x.is_whitespace() || *x == '>');
+                        let tag_only = content.iter().all(|x| x.is_whitespace());
+                        let name = tag.to_ascii_lowercase();
+                        if !at_line_start
+                            || VOID_TAGS.contains(&name.as_str())
+                            || !(tag_only || BLOCK_TAGS.contains(&name.as_str()))
+                        {
+                            *stream = bak;
+                            return Ok(None);
+                        }
+                        // closed on a later line; the rest of the line is parsed as usual
+                        log::trace!("-> multiline element");
+                        *stream = after_tag;
+                        return Ok(Some(HtmlNode {
+                            kind: HtmlKind::Open,
+                            ..HtmlNode::new(
+                                line,
+                                tag,
+                                "".into(),
+                                original[..original.len() - content.len()].iter().collect(),
+                                attribute,
+                                None,
+                            )
+                        }));
                     }
                 }
             }
@@ -197,6 +310,10 @@ impl HtmlNode {
             stripped,
             original,
             attribute,
+            kind: HtmlKind::Element,
+            end_line: line,
+            depth: 0,
+            children: Vec::new(),
         }
     }
     pub fn construct(&self) -> String {
@@ -1,6 +1,26 @@
+use std::collections::HashMap;
+
 use color_eyre::eyre::eyre;
+use itertools::Itertools;
+
+use crate::{
+    jobs::transcription::markdown::parse_markdown::MarkdownNode, utils::char_stream::ItemStream,
+};
 
-use crate::utils::char_stream::ItemStream;
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkKind {
+    /// `[content](href "title")`
+    Inline,
+    /// `[content][label]`; the label is "" for `[content][]`
+    Reference(String),
+    /// `<href>`
+    Autolink,
+    /// `[label]: href "title"` on its own line; the label is stored in `content`
+    Definition {
+        /// whitespace between ':' & the href
+        separator: String,
+    },
+}
 
 /// NOTE: The content will not be reparsed!
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -8,8 +28,13 @@ pub struct LinkNode {
     pub line: usize,
     /// can be "" or only whitespace etc. (also linebreaks)
     pub content: String,
-    /// can be "" or only whitespace etc.
+    /// can be "" or only whitespace etc.; "" for unresolved reference links
     pub href: String,
+    pub kind: LinkKind,
+    /// `![content](href)`
+    pub image: bool,
+    /// everything between the href & the end of the link, e.g. ` "title"`
+    pub title: Option<String>,
     pub stripped: Option<String>,
 }
 impl LinkNode {
@@ -18,41 +43,232 @@ impl LinkNode {
         line: usize,
     ) -> color_eyre::Result<Option<LinkNode>> {
         let bak = stream.clone();
+        let image = stream.test(|x| x == '!').is_some_and(|x| x);
+        if image {
+            stream.take(1);
+        }
         if stream.take(1) != vec!['['] {
-            return Err(eyre!("Expected to get link starting with '['"));
+            return Err(eyre!("Expected to get link starting with '[' or '!['"));
         }
         let content = stream
             .take_while(|x| x != ']')
             .into_iter()
             .collect::<String>();
-        let href = if stream.take(2) == vec![']', '('] {
-            stream
-                .take_while(|x| x != ')')
-                .into_iter()
-                .collect::<String>()
-        } else {
-            *stream = bak;
-            return Ok(None);
+        let link = |kind: LinkKind, href: String, title: Option<String>| LinkNode {
+            kind,
+            image,
+            title,
+            ..LinkNode::new(line, content.clone(), href, None)
         };
-
-        let x = stream.take(1); // may be ')' or EOL
-
-        if x != vec![')'] || href.contains(" ") {
+        if stream.take(1) != vec![']'] {
             *stream = bak;
             return Ok(None);
         }
 
-        Ok(Some(LinkNode::new(line, content, href, None)))
+        let res = match stream.take_one() {
+            Some('(') => take_destination(stream).and_then(|href| {
+                let title = take_title(stream)?;
+                (stream.take(1) == vec![')']).then(|| link(LinkKind::Inline, href, title))
+            }),
+            Some('[') => {
+                let label = stream.take_while(|x| x != ']' && x != '[');
+                (stream.take(1) == vec![']']).then(|| {
+                    link(
+                        LinkKind::Reference(label.into_iter().collect()),
+                        "".into(),
+                        None,
+                    )
+                })
+            }
+            // definitions start the line (after the blockquote markers)
+            Some(':')
+                if !image
+                    && !content.trim().is_empty()
+                    && bak
+                        .get_history()
+                        .iter()
+                        .all(|x| x.is_whitespace() || *x == '>') =>
+            {
+                let separator = stream.take_while(|x| x.is_whitespace());
+                take_destination(stream)
+                    .filter(|x| !x.is_empty())
+                    .and_then(|href| {
+                        let title = take_title(stream)?;
+                        stream.is_empty().then(|| {
+                            link(
+                                LinkKind::Definition {
+                                    separator: separator.into_iter().collect(),
+                                },
+                                href,
+                                title,
+                            )
+                        })
+                    })
+            }
+            _ => None,
+        };
+        if res.is_none() {
+            *stream = bak;
+        }
+        Ok(res)
+    }
+    /// `<https://example.com>` or `<mail@example.com>`
+    pub fn parse_autolink(stream: &mut ItemStream<char>, line: usize) -> Option<LinkNode> {
+        let bak = stream.clone();
+        if stream.take(1) != vec!['<'] {
+            *stream = bak;
+            return None;
+        }
+        let href = stream
+            .take_while(|x| !x.is_whitespace() && x != '<' && x != '>')
+            .into_iter()
+            .collect::<String>();
+        if stream.take(1) != vec!['>']
+            || !(lazy_regex::regex_is_match!(r"^[a-zA-Z][a-zA-Z0-9+.-]{1,31}:", &href)
+                || lazy_regex::regex_is_match!(
+                    r"^[a-zA-Z0-9.!#$%&'*+/=?^_`{|}~-]+@[a-zA-Z0-9](?:[a-zA-Z0-9-]*[a-zA-Z0-9])?(?:\.[a-zA-Z0-9](?:[a-zA-Z0-9-]*[a-zA-Z0-9])?)*$",
+                    &href
+                ))
+        {
+            *stream = bak;
+            return None;
+        }
+        Some(LinkNode {
+            kind: LinkKind::Autolink,
+            ..LinkNode::new(line, "".into(), href, None)
+        })
     }
     pub fn new(line: usize, content: String, href: String, stripped: Option<String>) -> LinkNode {
         LinkNode {
             line,
             content,
             href,
+            kind: LinkKind::Inline,
+            image: false,
+            title: None,
             stripped,
         }
     }
     pub fn construct(&self) -> String {
-        format!("[{}]({})", self.content, self.href)
+        let image = if self.image { "!" } else { "" };
+        let title = self.title.clone().unwrap_or_default();
+        match &self.kind {
+            LinkKind::Inline => format!("{}[{}]({}{})", image, self.content, self.href, title),
+            LinkKind::Reference(label) => format!("{}[{}][{}]", image, self.content, label),
+            LinkKind::Autolink => format!("<{}>", self.href),
+            LinkKind::Definition { separator } => {
+                format!("[{}]:{}{}{}", self.content, separator, self.href, title)
+            }
+        }
     }
+    /// the label of the definition this link refers to, or the label it defines
+    pub fn label(&self) -> Option<String> {
+        let label = match &self.kind {
+            LinkKind::Reference(x) if x.is_empty() => &self.content,
+            LinkKind::Reference(x) => x,
+            LinkKind::Definition { .. } => &self.content,
+            _ => return None,
+        };
+        // labels are case insensitive & whitespace is collapsed
+        Some(label.split_whitespace().join(" ").to_lowercase())
+    }
+}
+
+/// `<href with spaces>` or the href up to the next whitespace
+/// balanced parentheses belong to the href, e.g. 'transcript:(62.5):/a.md'
+fn take_destination(stream: &mut ItemStream<char>) -> Option<String> {
+    if stream.test(|x| x == '<').is_some_and(|x| x) {
+        let mut href = stream.take(1);
+        href.extend(stream.take_while(|x| x != '>' && x != '<'));
+        if stream.test(|x| x == '>') != Some(true) {
+            return None;
+        }
+        href.extend(stream.take(1));
+        return Some(href.into_iter().collect());
+    }
+    let mut href = String::new();
+    let mut depth = 0;
+    while let Some(x) = stream.preview(1).first().copied() {
+        match x {
+            ')' if depth == 0 => break,
+            ')' => depth -= 1,
+            '(' => depth += 1,
+            x if x.is_whitespace() => break,
+            _ => {}
+        }
+        href.push(x);
+        stream.take(1);
+    }
+    Some(href)
+}
+/// the whitespace & the quoted title after a href, e.g. ` "title" `; None if there is something else
+fn take_title(stream: &mut ItemStream<char>) -> Option<Option<String>> {
+    let mut title = stream.take_while(|x| x.is_whitespace());
+    let closing = match stream.preview(1).first() {
+        Some('"') => '"',
+        Some('\'') => '\'',
+        Some('(') => ')',
+        _ => return Some((!title.is_empty()).then(|| title.into_iter().collect())),
+    };
+    if title.is_empty() {
+        return None;
+    }
+    title.extend(stream.take(1));
+    title.extend(stream.take_while(|x| x != closing));
+    if stream.test(|x| x == closing) != Some(true) {
+        return None;
+    }
+    title.extend(stream.take(1));
+    title.extend(stream.take_while(|x| x.is_whitespace()));
+    Some(Some(title.into_iter().collect()))
+}
+
+/// Finds the links of a document & resolves its reference links
+#[derive(Debug, Clone, Default)]
+pub struct LinkQuery {
+    /// normalized label -> definition
+    definitions: HashMap<String, LinkNode>,
+}
+impl LinkQuery {
+    pub fn new(nodes: &[MarkdownNode]) -> LinkQuery {
+        let mut definitions = HashMap::new();
+        for link in find_links(nodes) {
+            if let (LinkKind::Definition { .. }, Some(label)) = (&link.kind, link.label()) {
+                // the first definition of a label wins
+                definitions.entry(label).or_insert(link);
+            }
+        }
+        LinkQuery { definitions }
+    }
+    /// the link with the href & title of its definition, if it is a reference link
+    pub fn resolve(&self, link: &LinkNode) -> LinkNode {
+        let LinkKind::Reference(_) = link.kind else {
+            return link.clone();
+        };
+        match link.label().and_then(|x| self.definitions.get(&x)) {
+            Some(definition) => LinkNode {
+                href: definition.href.clone(),
+                title: definition.title.clone(),
+                ..link.clone()
+            },
+            None => link.clone(),
+        }
+    }
+    /// every link (including the definitions & the links inside html elements) with its reference resolved
+    pub fn links(&self, nodes: &[MarkdownNode]) -> Vec<LinkNode> {
+        find_links(nodes)
+            .into_iter()
+            .map(|x| self.resolve(&x))
+            .collect()
+    }
+}
+fn find_links(nodes: &[MarkdownNode]) -> Vec<LinkNode> {
+    nodes
+        .iter()
+        .flat_map(|x| match x {
+            MarkdownNode::LinkNode(x) => vec![x.clone()],
+            MarkdownNode::HtmlNode(x) => find_links(&x.children),
+            _ => vec![],
+        })
+        .collect()
 }
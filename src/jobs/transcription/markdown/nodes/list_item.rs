@@ -0,0 +1,91 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListMarker {
+    /// '-', '*' or '+'
+    Bullet(char),
+    /// e.g. '1.' or '2)'
+    Ordered { index: u64, delimiter: char },
+}
+
+/// The marker at the start of a list item (e.g. '  - ' or '1. '); the rest of the line is parsed as usual
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListItemNode {
+    pub line: usize,
+    pub marker: ListMarker,
+    /// whitespace in front of the marker
+    pub indentation: String,
+    /// 1 for top level items
+    pub level: usize,
+    /// indentation, marker & the whitespace after it
+    pub original: String,
+    pub stripped: Option<String>,
+}
+impl ListItemNode {
+    /// returns None if the line doesn't start a list item
+    pub fn parse(content: &str, line: usize, level: usize) -> Option<ListItemNode> {
+        // '- - -' & '***' are thematic breaks
+        if lazy_regex::regex_is_match!(r"^\s*([-*_])(\s*[-*_]){2,}\s*$", content) {
+            return None;
+        }
+        let (original, indentation, marker, _) =
+            lazy_regex::regex_captures!(r"^(\s*)([-*+]|[0-9]{1,9}[.)])(\s+|$)", content)?;
+        let marker = match marker {
+            "-" | "*" | "+" => ListMarker::Bullet(marker.chars().next()?),
+            _ => ListMarker::Ordered {
+                index: marker[..marker.len() - 1].parse().ok()?,
+                delimiter: marker.chars().last()?,
+            },
+        };
+        Some(ListItemNode::new(
+            line,
+            marker,
+            indentation.to_owned(),
+            level,
+            original.to_owned(),
+            None,
+        ))
+    }
+    pub fn new(
+        line: usize,
+        marker: ListMarker,
+        indentation: String,
+        level: usize,
+        original: String,
+        stripped: Option<String>,
+    ) -> ListItemNode {
+        ListItemNode {
+            line,
+            marker,
+            indentation,
+            level,
+            original,
+            stripped,
+        }
+    }
+    pub fn construct(&self) -> String {
+        self.original.clone()
+    }
+    /// indentation of the lines continuing the item
+    pub fn content_indentation(&self) -> usize {
+        width(&self.original)
+    }
+}
+
+/// width of the text (a tab counts as 4 spaces)
+pub fn width(content: &str) -> usize {
+    content.chars().map(|x| if x == '\t' { 4 } else { 1 }).sum()
+}
+/// splits off up to `indentation` columns of leading whitespace
+pub fn split_indentation(content: &str, indentation: usize) -> (&str, &str) {
+    let mut current = 0;
+    let end = content
+        .char_indices()
+        .find(|(_, x)| {
+            if current >= indentation || !x.is_whitespace() {
+                return true;
+            }
+            current += width(&x.to_string());
+            false
+        })
+        .map_or(content.len(), |(idx, _)| idx);
+    content.split_at(end)
+}
@@ -1,5 +1,8 @@
 pub mod block;
+pub mod code_block;
+pub mod front_matter;
 pub mod headline;
 pub mod html;
 pub mod link;
+pub mod list_item;
 pub mod paragraph;
@@ -4,7 +4,13 @@ use itertools::Itertools;
 use crate::utils::{char_stream::ItemStream, string};
 
 use super::nodes::{
-    block::BlockNode, headline::HeadlineNode, html::HtmlNode, link::LinkNode,
+    block::BlockNode,
+    code_block::{CodeBlockNode, CodeBlockState},
+    front_matter::{self, FrontMatterNode},
+    headline::{self, HeadlineNode, UnderlineNode},
+    html::{self, HtmlKind, HtmlNode},
+    link::LinkNode,
+    list_item::{self, ListItemNode},
     paragraph::ParagraphNode,
 };
 
@@ -16,6 +22,10 @@ pub enum MarkdownNode {
     BlockEnd(BlockNode),
     LinkNode(LinkNode),
     HtmlNode(HtmlNode),
+    CodeBlock(CodeBlockNode),
+    FrontMatter(FrontMatterNode),
+    HeadlineUnderline(UnderlineNode),
+    ListItem(ListItemNode),
 }
 impl MarkdownNode {
     pub fn get_line(&self) -> usize {
@@ -26,6 +36,10 @@ impl MarkdownNode {
             MarkdownNode::BlockEnd(x) => x.line,
             MarkdownNode::LinkNode(x) => x.line,
             MarkdownNode::HtmlNode(x) => x.line,
+            MarkdownNode::CodeBlock(x) => x.line,
+            MarkdownNode::FrontMatter(x) => x.line,
+            MarkdownNode::HeadlineUnderline(x) => x.line,
+            MarkdownNode::ListItem(x) => x.line,
         }
     }
     fn construct(&self) -> String {
@@ -36,9 +50,13 @@ impl MarkdownNode {
             MarkdownNode::BlockEnd(_) => String::new(),
             MarkdownNode::LinkNode(x) => x.construct(),
             MarkdownNode::HtmlNode(x) => x.construct(),
+            MarkdownNode::CodeBlock(x) => x.construct(),
+            MarkdownNode::FrontMatter(x) => x.construct(),
+            MarkdownNode::HeadlineUnderline(x) => x.construct(),
+            MarkdownNode::ListItem(x) => x.construct(),
         }
     }
-    fn set_stripped(&mut self, stripped: Option<String>) {
+    pub fn set_stripped(&mut self, stripped: Option<String>) {
         match self {
             MarkdownNode::Headline(x) => x.stripped = stripped,
             MarkdownNode::ParagraphNode(x) => x.stripped = stripped,
@@ -46,6 +64,10 @@ impl MarkdownNode {
             MarkdownNode::BlockEnd(x) => x.stripped = stripped,
             MarkdownNode::LinkNode(x) => x.stripped = stripped,
             MarkdownNode::HtmlNode(x) => x.stripped = stripped,
+            MarkdownNode::CodeBlock(x) => x.stripped = stripped,
+            MarkdownNode::FrontMatter(x) => x.stripped = stripped,
+            MarkdownNode::HeadlineUnderline(x) => x.stripped = stripped,
+            MarkdownNode::ListItem(x) => x.stripped = stripped,
         }
     }
     pub fn get_stripped(&self) -> Option<String> {
@@ -56,6 +78,21 @@ impl MarkdownNode {
             MarkdownNode::BlockEnd(x) => x.stripped.clone(),
             MarkdownNode::LinkNode(x) => x.stripped.clone(),
             MarkdownNode::HtmlNode(x) => x.stripped.clone(),
+            MarkdownNode::CodeBlock(x) => x.stripped.clone(),
+            MarkdownNode::FrontMatter(x) => x.stripped.clone(),
+            MarkdownNode::HeadlineUnderline(x) => x.stripped.clone(),
+            MarkdownNode::ListItem(x) => x.stripped.clone(),
+        }
+    }
+    /// prefix of the lines continuing this line; for list items including the indentation of their content
+    pub fn get_prefix(&self) -> Option<String> {
+        match self {
+            MarkdownNode::ListItem(x) => Some(format!(
+                "{}{}",
+                x.stripped.clone().unwrap_or_default(),
+                " ".repeat(x.content_indentation())
+            )),
+            x => x.get_stripped(),
         }
     }
     pub fn get_html(&self) -> Option<HtmlNode> {
@@ -96,20 +133,41 @@ impl MarkdownNode {
     }
     pub fn increment_line_by(&mut self, offset: usize) {
         match self {
-            MarkdownNode::Headline(x) => x.line += offset,
+            MarkdownNode::Headline(x) => {
+                x.line += offset;
+                x.underline = x.underline.map(|y| y + offset);
+            }
             MarkdownNode::ParagraphNode(x) => x.line += offset,
             MarkdownNode::BlockStart(x) => x.line += offset,
             MarkdownNode::BlockEnd(x) => x.line += offset,
             MarkdownNode::LinkNode(x) => x.line += offset,
-            MarkdownNode::HtmlNode(x) => x.line += offset,
+            MarkdownNode::HtmlNode(x) => {
+                x.line += offset;
+                x.end_line += offset;
+                x.children
+                    .iter_mut()
+                    .for_each(|y| y.increment_line_by(offset));
+            }
+            MarkdownNode::CodeBlock(x) => x.line += offset,
+            MarkdownNode::FrontMatter(x) => x.line += offset,
+            MarkdownNode::HeadlineUnderline(x) => x.line += offset,
+            MarkdownNode::ListItem(x) => x.line += offset,
         }
     }
 }
 
 pub(crate) fn parse_markdown(content: &str) -> color_eyre::Result<Vec<MarkdownNode>> {
     let mut pre: Vec<String> = Vec::new();
+    let mut code = CodeBlockState::default();
     let mut res = Vec::new();
     let lines = content.split("\n").collect_vec();
+    let front_matter = front_matter::find_front_matter(&lines);
+    // the next line underlines a setext heading
+    let mut underline = false;
+    // open list items: (blockquote level, indentation of their content)
+    let mut lists: Vec<(usize, usize)> = Vec::new();
+    // multiline html elements, which haven't been closed yet: (tag, index of the opening node)
+    let mut html: Vec<(String, usize)> = Vec::new();
     for (idx, original_line) in lines.clone().into_iter().enumerate() {
         let mut stripped = String::new();
         let mut line = original_line.to_string();
@@ -139,9 +197,137 @@ pub(crate) fn parse_markdown(content: &str) -> color_eyre::Result<Vec<MarkdownNo
             }
         }
 
-        let mut line_stream = ItemStream::new(&line.chars().collect_vec());
+        // blockquotes opened on this line (inside a fenced code block '>' is just text)
+        let (markers, mut rest) = match code.in_fence() {
+            true => ("", line.as_str()),
+            false => split_block_markers(&line),
+        };
+        let level = pre.len() + markers.matches('>').count();
+        let in_front_matter = front_matter.as_ref().is_some_and(|(_, end)| idx <= *end);
+        let in_comment = html.last().is_some_and(|(tag, _)| tag == html::COMMENT_TAG);
+        let open = html.iter().map(|(tag, _)| tag.clone()).collect_vec();
 
-        let mut line_res = parse_line(&mut line_stream, idx, &mut pre)?;
+        // list items & the lines continuing them
+        let mut item = None;
+        if !in_front_matter && !in_comment && !underline && !rest.trim().is_empty() {
+            // the blockquotes of the items have ended
+            lists.retain(|(quote, _)| *quote <= level);
+            let indentation = list_item::width(&rest[..rest.len() - rest.trim_start().len()]);
+            while lists
+                .last()
+                .is_some_and(|(quote, x)| *quote == level && *x > indentation)
+            {
+                lists.pop();
+            }
+            let list_level = lists.iter().filter(|(quote, _)| *quote == level).count();
+            item = match code.in_fence() {
+                true => None,
+                false => ListItemNode::parse(rest, idx, list_level + 1),
+            };
+            // the indentation continuing an item is stripped like the '>' of a blockquote
+            if item.is_none() && markers.is_empty() {
+                if let Some((_, x)) = lists.last().filter(|(quote, _)| *quote == level) {
+                    let (continuation, remaining) = list_item::split_indentation(rest, *x);
+                    stripped.push_str(continuation);
+                    rest = remaining;
+                }
+            }
+        }
+
+        let mut line_res = match &front_matter {
+            Some((delimiter, _)) if in_front_matter => vec![MarkdownNode::FrontMatter(
+                FrontMatterNode::new(idx, delimiter.clone(), line.clone(), None),
+            )],
+            // the content of a multiline comment isn't parsed
+            _ if in_comment => {
+                let text = format!("{}{}", markers, rest);
+                match text.find("-->") {
+                    Some(end) => {
+                        let mut line_res = vec![MarkdownNode::HtmlNode(HtmlNode {
+                            kind: HtmlKind::Close,
+                            ..HtmlNode::new(
+                                idx,
+                                html::COMMENT_TAG.to_owned(),
+                                text[..end].to_owned(),
+                                text[..end + 3].to_owned(),
+                                "".to_owned(),
+                                None,
+                            )
+                        })];
+                        line_res.extend(parse_line(
+                            &mut ItemStream::new(&text[end + 3..].chars().collect_vec()),
+                            idx,
+                            &mut pre,
+                            &open,
+                        )?);
+                        line_res
+                    }
+                    None => vec![MarkdownNode::HtmlNode(HtmlNode {
+                        kind: HtmlKind::Inner,
+                        ..HtmlNode::new(
+                            idx,
+                            html::COMMENT_TAG.to_owned(),
+                            text.clone(),
+                            text,
+                            "".to_owned(),
+                            None,
+                        )
+                    })],
+                }
+            }
+            _ if std::mem::take(&mut underline) => {
+                let mut line_res = parse_block_markers(markers, idx, &mut pre);
+                line_res.push(MarkdownNode::HeadlineUnderline(UnderlineNode::new(
+                    idx,
+                    rest.to_owned(),
+                    None,
+                )));
+                line_res
+            }
+            _ => match code.parse(rest, idx, level) {
+                Some(x) => {
+                    let mut line_res = parse_block_markers(markers, idx, &mut pre);
+                    line_res.push(MarkdownNode::CodeBlock(x));
+                    line_res
+                }
+                None => match (setext_heading(&lines, idx, rest, level), item) {
+                    (Some(heading_level), _) => {
+                        underline = true;
+                        let mut line_res = parse_block_markers(markers, idx, &mut pre);
+                        line_res.push(MarkdownNode::Headline(HeadlineNode {
+                            underline: Some(idx + 1),
+                            ..HeadlineNode::new(
+                                idx,
+                                heading_level,
+                                rest.trim().to_owned(),
+                                rest.to_owned(),
+                                None,
+                            )
+                        }));
+                        line_res
+                    }
+                    (None, Some(item)) => {
+                        lists.push((level, item.content_indentation()));
+                        let mut line_res = parse_block_markers(markers, idx, &mut pre);
+                        let remaining = &rest[item.original.len()..];
+                        line_res.push(MarkdownNode::ListItem(item));
+                        line_res.extend(parse_line(
+                            &mut ItemStream::new(&remaining.chars().collect_vec()),
+                            idx,
+                            &mut pre,
+                            &open,
+                        )?);
+                        line_res
+                    }
+                    (None, None) => parse_line(
+                        &mut ItemStream::new(&markers.chars().chain(rest.chars()).collect_vec()),
+                        idx,
+                        &mut pre,
+                        &open,
+                    )?,
+                },
+            },
+        };
         if line_res.is_empty() {
             // newline
             line_res.push(MarkdownNode::ParagraphNode(ParagraphNode::new(
@@ -151,6 +337,32 @@ pub(crate) fn parse_markdown(content: &str) -> color_eyre::Result<Vec<MarkdownNo
             )));
         }
 
+        // nesting of the multiline html elements
+        for (i, node) in line_res.iter_mut().enumerate() {
+            let MarkdownNode::HtmlNode(x) = node else {
+                continue;
+            };
+            x.depth = html.len();
+            match x.kind {
+                HtmlKind::Open => html.push((x.tag.to_ascii_lowercase(), res.len() + i)),
+                HtmlKind::Close => {
+                    // closing an element closes the elements nested in it as well
+                    if let Some(pos) = html
+                        .iter()
+                        .rposition(|(tag, _)| tag.eq_ignore_ascii_case(&x.tag))
+                    {
+                        for (_, opening) in html.drain(pos..) {
+                            if let Some(MarkdownNode::HtmlNode(opening)) = res.get_mut(opening) {
+                                opening.end_line = idx;
+                            }
+                        }
+                        x.depth = pos;
+                    }
+                }
+                HtmlKind::Element | HtmlKind::Inner => {}
+            }
+        }
+
         // Last line cleanup
         if idx + 1 == lines.len() {
             // close all blocks
@@ -177,10 +389,72 @@ pub(crate) fn parse_markdown(content: &str) -> color_eyre::Result<Vec<MarkdownNo
 
     Ok(res)
 }
+/// (blockquote markers, rest of the line), e.g. '> > text' -> ('> >', ' text')
+pub(crate) fn split_block_markers(line: &str) -> (&str, &str) {
+    lazy_regex::regex_captures!(r"^((?:[ \t]*>)*)(.*)$", line)
+        .map(|(_, markers, rest)| (markers, rest))
+        .unwrap_or(("", line))
+}
+/// level of the setext heading on line `idx`, whose text (without blockquote markers) is `rest`
+pub(crate) fn setext_heading(
+    lines: &[&str],
+    idx: usize,
+    rest: &str,
+    level: usize,
+) -> Option<usize> {
+    let (markers, underline) = split_block_markers(lines.get(idx + 1)?);
+    // the underline has to be inside the same blockquote
+    if markers.matches('>').count() != level {
+        return None;
+    }
+    let previous = match idx {
+        0 => None,
+        _ => Some(split_block_markers(lines[idx - 1]).1),
+    };
+    // the space after '>' belongs to the blockquote
+    fn strip(x: &str, level: usize) -> &str {
+        match level {
+            0 => x,
+            _ => x.strip_prefix(' ').unwrap_or(x),
+        }
+    }
+    headline::setext_level(
+        previous.map(|x| strip(x, level)),
+        strip(rest, level),
+        strip(underline, level),
+    )
+}
+/// the nodes of the blockquote markers in front of a code block, like `parse_line` would create them
+fn parse_block_markers(markers: &str, index: usize, pre: &mut Vec<String>) -> Vec<MarkdownNode> {
+    let mut res = Vec::new();
+    let mut whitespace = String::new();
+    for c in markers.chars() {
+        if c != '>' {
+            whitespace.push(c);
+            continue;
+        }
+        if !whitespace.is_empty() {
+            res.push(MarkdownNode::ParagraphNode(ParagraphNode::new(
+                index,
+                std::mem::take(&mut whitespace),
+                None,
+            )));
+        }
+        res.push(MarkdownNode::BlockStart(BlockNode::new(
+            index,
+            pre.len() + 1,
+            None,
+        )));
+        pre.push(">".to_owned());
+    }
+    res
+}
+/// `open`: tags of the multiline html elements, which haven't been closed yet
 fn parse_stream(
     line_stream: &mut ItemStream<char>,
     index: usize,
     pre: &mut Vec<String>,
+    open: &[String],
 ) -> color_eyre::Result<Vec<MarkdownNode>> {
     let mut res = Vec::new();
     if line_stream.test(|x| x == '#').is_some_and(|x| x) {
@@ -203,13 +477,33 @@ fn parse_stream(
             pre.push(">".to_owned());
         }
     }
-    if line_stream.test(|x| x == '[').is_some_and(|x| x) {
+    if line_stream.test(|x| x == '[').is_some_and(|x| x)
+        || line_stream.test_window(vec!['!', '[']).is_some_and(|x| x)
+    {
         if let Some(x) = LinkNode::parse(line_stream, index)? {
             res.push(MarkdownNode::LinkNode(x));
         }
     }
     if line_stream.test(|x| x == '<').is_some_and(|x| x) {
-        if let Some(x) = HtmlNode::parse(line_stream, index)? {
+        if let Some(x) = LinkNode::parse_autolink(line_stream, index) {
+            res.push(MarkdownNode::LinkNode(x));
+        } else if let Some(mut x) = HtmlNode::parse(line_stream, index, open)? {
+            if x.kind == HtmlKind::Element && x.tag != html::COMMENT_TAG {
+                // content, which can't be parsed (e.g. '#tag'), is kept as text
+                x.children = parse_line(
+                    &mut ItemStream::new(&x.content.chars().collect_vec()),
+                    index,
+                    &mut Vec::new(),
+                    open,
+                )
+                .unwrap_or_else(|_| {
+                    vec![MarkdownNode::ParagraphNode(ParagraphNode::new(
+                        index,
+                        x.content.clone(),
+                        None,
+                    ))]
+                });
+            }
             res.push(MarkdownNode::HtmlNode(x));
         }
     }
@@ -220,10 +514,11 @@ fn parse_line(
     line_stream: &mut ItemStream<char>,
     index: usize,
     pre: &mut Vec<String>,
+    open: &[String],
 ) -> color_eyre::Result<Vec<MarkdownNode>> {
     let mut res = Vec::new();
 
-    res.extend_from_slice(&parse_stream(line_stream, index, pre)?);
+    res.extend_from_slice(&parse_stream(line_stream, index, pre, open)?);
 
     let mut current = vec![];
 
@@ -242,7 +537,7 @@ fn parse_line(
             )));
             break;
         }
-        let test = parse_stream(line_stream, index, pre)?;
+        let test = parse_stream(line_stream, index, pre, open)?;
         if !test.is_empty() || line_stream.is_empty() {
             // Paragraph stuff
             let p = current.clone().into_iter().collect::<String>();
@@ -282,16 +577,44 @@ pub(crate) fn construct_markdown(nodes: Vec<MarkdownNode>) -> color_eyre::Result
     }
 
     let mut result = Vec::new();
-    for (idx, line) in lines {
-        let mut res = String::new();
-        for (_, l) in line.iter().enumerate() {
-            if let Some(x) = l.get_stripped() {
-                res.push_str(&x);
+    for (_, line) in lines {
+        result.push(construct_line(&line));
+    }
+    Ok(result.join("\n"))
+}
+/// prefix of the lines continuing a line: its stripped prefix & the blockquote markers and list items
+/// (as spaces) in front of its content, e.g. '> - # h' -> '>   '
+pub(crate) fn line_prefix(nodes: &[MarkdownNode]) -> Option<String> {
+    // tabs are expanded, a tab in front of '>' would make the line an indented code block
+    let mut prefix = nodes
+        .first()?
+        .get_stripped()
+        .map(|x| x.replace('\t', &" ".repeat(list_item::width("\t"))));
+    let mut marked = false;
+    for node in nodes {
+        let marker = match node {
+            MarkdownNode::BlockStart(x) => x.construct(),
+            MarkdownNode::ListItem(x) => " ".repeat(x.content_indentation()),
+            // whitespace in front of the first marker is spacing, not part of the prefix
+            MarkdownNode::ParagraphNode(x) if x.content.trim().is_empty() && marked => {
+                x.content.clone()
             }
-            res.push_str(&l.construct());
+            MarkdownNode::ParagraphNode(x) if x.content.trim().is_empty() => continue,
+            _ => break,
+        };
+        marked = true;
+        prefix = Some(format!("{}{}", prefix.unwrap_or_default(), marker));
+    }
+    prefix
+}
+/// constructs the nodes of a single line
+pub(crate) fn construct_line(nodes: &[MarkdownNode]) -> String {
+    let mut res = String::new();
+    for l in nodes {
+        if let Some(x) = l.get_stripped() {
+            res.push_str(&x);
         }
-
-        result.push(res);
+        res.push_str(&l.construct());
     }
-    Ok(result.join("\n"))
+    res
 }
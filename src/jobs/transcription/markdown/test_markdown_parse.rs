@@ -3,14 +3,20 @@ use itertools::Itertools as _;
 use crate::{
     jobs::transcription::markdown::{
         nodes::{
-            block::BlockNode, headline::HeadlineNode, link::LinkNode, paragraph::ParagraphNode,
+            block::BlockNode,
+            code_block::CodeBlockNode,
+            front_matter::FrontMatterNode,
+            headline::{HeadlineNode, UnderlineNode},
+            link::{LinkKind, LinkNode, LinkQuery},
+            list_item::{ListItemNode, ListMarker},
+            paragraph::ParagraphNode,
         },
         parse_markdown::MarkdownNode,
     },
     utils::char_stream::ItemStream,
 };
 
-use super::nodes::html::HtmlNode;
+use super::nodes::html::{HtmlKind, HtmlNode};
 
 #[test]
 fn test_headline_node() {
@@ -220,6 +226,98 @@ fn test_parse_broken_links() {
     }
 }
 #[test]
+fn test_link_with_parentheses() {
+    let input = "\
+[12:00](transcript:(62.5):/a.md)
+[12:00](a(b)";
+    let expected = vec![
+        MarkdownNode::LinkNode(LinkNode::new(
+            0,
+            "12:00".into(),
+            "transcript:(62.5):/a.md".into(),
+            None,
+        )),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(1, "[12:00](a(b)".into(), None)),
+    ];
+    let res = super::parse_markdown::parse_markdown(&input).unwrap();
+    assert_eq!(res, expected);
+    assert_eq!(
+        super::parse_markdown::construct_markdown(res).unwrap(),
+        input
+    );
+}
+#[test]
+fn test_link_kinds() {
+    let input = "\
+![diagram](img.png) [a](<b c> \"title\")
+[text][Ref] and [Ref][] <https://example.com>
+> [ref]:  /a.md 'title'
+[b](c d)";
+    let link = |line: usize, content: &str, href: &str| {
+        LinkNode::new(line, content.into(), href.into(), None)
+    };
+    let expected = vec![
+        MarkdownNode::LinkNode(LinkNode {
+            image: true,
+            ..link(0, "diagram", "img.png")
+        }),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(0, " ".into(), None)),
+        MarkdownNode::LinkNode(LinkNode {
+            title: Some(" \"title\"".into()),
+            ..link(0, "a", "<b c>")
+        }),
+        MarkdownNode::LinkNode(LinkNode {
+            kind: LinkKind::Reference("Ref".into()),
+            ..link(1, "text", "")
+        }),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(1, " and ".into(), None)),
+        MarkdownNode::LinkNode(LinkNode {
+            kind: LinkKind::Reference("".into()),
+            ..link(1, "Ref", "")
+        }),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(1, " ".into(), None)),
+        MarkdownNode::LinkNode(LinkNode {
+            kind: LinkKind::Autolink,
+            ..link(1, "", "https://example.com")
+        }),
+        MarkdownNode::BlockStart(BlockNode::new(2, 1, None)),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(2, " ".into(), None)),
+        MarkdownNode::LinkNode(LinkNode {
+            kind: LinkKind::Definition {
+                separator: "  ".into(),
+            },
+            title: Some(" 'title'".into()),
+            ..link(2, "ref", "/a.md")
+        }),
+        MarkdownNode::BlockEnd(BlockNode::new(2, 1, None)),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(3, "[b](c d)".into(), None)),
+    ];
+    let res = super::parse_markdown::parse_markdown(input).unwrap();
+    assert_eq!(res, expected);
+
+    // reference links get the href of their definition
+    let resolved = LinkQuery::new(&res)
+        .links(&res)
+        .into_iter()
+        .map(|x| (x.content, x.href))
+        .collect_vec();
+    assert_eq!(
+        resolved,
+        vec![
+            ("diagram".into(), "img.png".into()),
+            ("a".into(), "<b c>".into()),
+            ("text".into(), "/a.md".into()),
+            ("Ref".into(), "/a.md".into()),
+            ("".into(), "https://example.com".into()),
+            ("ref".into(), "/a.md".into()),
+        ]
+    );
+    assert_eq!(
+        super::parse_markdown::construct_markdown(res).unwrap(),
+        input
+    );
+}
+#[test]
 fn construction_test() {
     let mut inputs = vec![];
 
@@ -369,14 +467,21 @@ br/>
             None,
         )),
         MarkdownNode::ParagraphNode(ParagraphNode::new(2, "text".into(), None)),
-        MarkdownNode::HtmlNode(HtmlNode::new(
-            3,
-            "start".into(),
-            "content".into(),
-            "<start>content</start>".into(),
-            "".into(),
-            None,
-        )),
+        MarkdownNode::HtmlNode(HtmlNode {
+            children: vec![MarkdownNode::ParagraphNode(ParagraphNode::new(
+                3,
+                "content".into(),
+                None,
+            ))],
+            ..HtmlNode::new(
+                3,
+                "start".into(),
+                "content".into(),
+                "<start>content</start>".into(),
+                "".into(),
+                None,
+            )
+        }),
         MarkdownNode::HtmlNode(HtmlNode::new(
             4,
             "br".into(),
@@ -403,14 +508,21 @@ br/>
             "src=\"hello\" ".into(),
             None,
         )),
-        MarkdownNode::HtmlNode(HtmlNode::new(
-            7,
-            "img".into(),
-            "content".into(),
-            "<img asdf asdf >content</img>".into(),
-            "asdf asdf ".into(),
-            None,
-        )),
+        MarkdownNode::HtmlNode(HtmlNode {
+            children: vec![MarkdownNode::ParagraphNode(ParagraphNode::new(
+                7,
+                "content".into(),
+                None,
+            ))],
+            ..HtmlNode::new(
+                7,
+                "img".into(),
+                "content".into(),
+                "<img asdf asdf >content</img>".into(),
+                "asdf asdf ".into(),
+                None,
+            )
+        }),
         MarkdownNode::ParagraphNode(ParagraphNode::new(8, "<a>content</b>".into(), None)),
     ];
     let parsed = super::parse_markdown::parse_markdown(&input).unwrap();
@@ -421,3 +533,282 @@ br/>
         assert_eq!(x, expected[i], "Line: {}", i);
     }
 }
+#[test]
+fn test_code_block() {
+    let input = "\
+# Setup
+```bash
+# install
+[not](a link) <b>bold</b>
+```
+> ~~~
+> # quoted
+> ~~~
+
+    # indented
+text";
+    let code = |line: usize, fence: Option<&str>, content: &str| {
+        MarkdownNode::CodeBlock(CodeBlockNode::new(
+            line,
+            fence.map(|x| x.to_owned()),
+            content.into(),
+            None,
+        ))
+    };
+    let expected = vec![
+        MarkdownNode::Headline(HeadlineNode::new(
+            0,
+            1,
+            "Setup".into(),
+            "# Setup".into(),
+            None,
+        )),
+        code(1, Some("```"), "```bash"),
+        code(2, Some("```"), "# install"),
+        code(3, Some("```"), "[not](a link) <b>bold</b>"),
+        code(4, Some("```"), "```"),
+        MarkdownNode::BlockStart(BlockNode::new(5, 1, None)),
+        code(5, Some("~~~"), " ~~~"),
+        MarkdownNode::CodeBlock(CodeBlockNode::new(
+            6,
+            Some("~~~".into()),
+            " # quoted".into(),
+            Some(">".into()),
+        )),
+        MarkdownNode::CodeBlock(CodeBlockNode::new(
+            7,
+            Some("~~~".into()),
+            " ~~~".into(),
+            Some(">".into()),
+        )),
+        MarkdownNode::BlockEnd(BlockNode::new(7, 1, None)),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(8, "".into(), None)),
+        code(9, None, "    # indented"),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(10, "text".into(), None)),
+    ];
+    let res = super::parse_markdown::parse_markdown(input).unwrap();
+    assert_eq!(res, expected);
+    assert_eq!(
+        super::parse_markdown::construct_markdown(res).unwrap(),
+        input
+    );
+    assert_eq!(
+        super::get_related_markdown_headings(9, input, true).unwrap(),
+        vec![0]
+    );
+
+    // an indented line continues a paragraph or list item
+    let input = "\
+text
+    # heading
+- item
+
+    # heading";
+    let res = super::parse_markdown::parse_markdown(input).unwrap();
+    assert!(
+        !res.iter().any(|x| matches!(x, MarkdownNode::CodeBlock(_))),
+        "{:#?}",
+        res
+    );
+}
+#[test]
+fn test_setext_and_front_matter() {
+    let input = "\
+---
+title: Photosynthesis
+# not a heading
+---
+Photosynthesis
+==============
+> Light reactions
+> ---
+
+- item
+---";
+    let front_matter = |line: usize, content: &str| {
+        MarkdownNode::FrontMatter(FrontMatterNode::new(
+            line,
+            "---".into(),
+            content.into(),
+            None,
+        ))
+    };
+    let expected = vec![
+        front_matter(0, "---"),
+        front_matter(1, "title: Photosynthesis"),
+        front_matter(2, "# not a heading"),
+        front_matter(3, "---"),
+        MarkdownNode::Headline(HeadlineNode {
+            underline: Some(5),
+            ..HeadlineNode::new(4, 1, "Photosynthesis".into(), "Photosynthesis".into(), None)
+        }),
+        MarkdownNode::HeadlineUnderline(UnderlineNode::new(5, "==============".into(), None)),
+        MarkdownNode::BlockStart(BlockNode::new(6, 1, None)),
+        MarkdownNode::Headline(HeadlineNode {
+            underline: Some(7),
+            ..HeadlineNode::new(
+                6,
+                2,
+                "Light reactions".into(),
+                " Light reactions".into(),
+                None,
+            )
+        }),
+        MarkdownNode::HeadlineUnderline(UnderlineNode::new(7, " ---".into(), Some(">".into()))),
+        MarkdownNode::BlockEnd(BlockNode::new(7, 1, None)),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(8, "".into(), None)),
+        MarkdownNode::ListItem(ListItemNode::new(
+            9,
+            ListMarker::Bullet('-'),
+            "".into(),
+            1,
+            "- ".into(),
+            None,
+        )),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(9, "item".into(), None)),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(10, "---".into(), None)),
+    ];
+    let res = super::parse_markdown::parse_markdown(input).unwrap();
+    assert_eq!(res, expected);
+    assert_eq!(
+        super::parse_markdown::construct_markdown(res).unwrap(),
+        input
+    );
+    for (line, expected) in [(2, vec![]), (5, vec![4]), (7, vec![6, 4]), (10, vec![6, 4])] {
+        assert_eq!(
+            super::get_related_markdown_headings(line, input, true).unwrap(),
+            expected,
+            "line {}",
+            line
+        );
+    }
+}
+#[test]
+fn test_list_item_node() {
+    let input = "\
+- a
+  continued [x](y)
+  2) sub
+     deep
+* b";
+    let expected = vec![
+        MarkdownNode::ListItem(ListItemNode::new(
+            0,
+            ListMarker::Bullet('-'),
+            "".into(),
+            1,
+            "- ".into(),
+            None,
+        )),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(0, "a".into(), None)),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(
+            1,
+            "continued ".into(),
+            Some("  ".into()),
+        )),
+        MarkdownNode::LinkNode(LinkNode::new(1, "x".into(), "y".into(), None)),
+        MarkdownNode::ListItem(ListItemNode::new(
+            2,
+            ListMarker::Ordered {
+                index: 2,
+                delimiter: ')',
+            },
+            "  ".into(),
+            2,
+            "  2) ".into(),
+            None,
+        )),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(2, "sub".into(), None)),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(3, "deep".into(), Some("     ".into()))),
+        MarkdownNode::ListItem(ListItemNode::new(
+            4,
+            ListMarker::Bullet('*'),
+            "".into(),
+            1,
+            "* ".into(),
+            None,
+        )),
+        MarkdownNode::ParagraphNode(ParagraphNode::new(4, "b".into(), None)),
+    ];
+    let res = super::parse_markdown::parse_markdown(input).unwrap();
+    assert_eq!(res, expected);
+    assert_eq!(
+        super::parse_markdown::construct_markdown(res).unwrap(),
+        input
+    );
+
+    // thematic breaks & emphasis aren't list items
+    let res = super::parse_markdown::parse_markdown("- - -\n*bold*").unwrap();
+    assert!(
+        !res.iter().any(|x| matches!(x, MarkdownNode::ListItem(_))),
+        "{:#?}",
+        res
+    );
+}
+
+#[test]
+fn test_multiline_html() {
+    let input = "\
+<details>
+<summary>Solution <mark>[1](a)</mark></summary>
+
+> <div>
+> [x](y)
+</details>
+<!-- a
+# not a headline
+-->text";
+    let res = super::parse_markdown::parse_markdown(input).unwrap();
+    let htmls = res
+        .iter()
+        .filter_map(|x| x.get_html())
+        .map(|x| (x.line, x.tag, x.kind, x.end_line, x.depth))
+        .collect_vec();
+    assert_eq!(
+        htmls,
+        vec![
+            (0, "details".into(), HtmlKind::Open, 5, 0),
+            (1, "summary".into(), HtmlKind::Element, 1, 1),
+            (3, "div".into(), HtmlKind::Open, 5, 1),
+            (5, "details".into(), HtmlKind::Close, 5, 0),
+            (6, "<!--...-->".into(), HtmlKind::Open, 8, 0),
+            (7, "<!--...-->".into(), HtmlKind::Inner, 7, 1),
+            (8, "<!--...-->".into(), HtmlKind::Close, 8, 0),
+        ]
+    );
+    // the content of single line elements is parsed
+    let summary = res[1].get_html().unwrap();
+    let mark = summary.children[1].get_html().unwrap();
+    assert_eq!(mark.tag, "mark");
+    assert_eq!(
+        mark.children,
+        vec![MarkdownNode::LinkNode(LinkNode::new(
+            1,
+            "1".into(),
+            "a".into(),
+            None
+        ))]
+    );
+    // comments aren't parsed
+    assert!(
+        !res.iter().any(|x| x.get_headline().is_some()),
+        "{:#?}",
+        res
+    );
+    assert_eq!(
+        res.last(),
+        Some(&MarkdownNode::ParagraphNode(ParagraphNode::new(
+            8,
+            "text".into(),
+            None
+        )))
+    );
+    assert_eq!(
+        super::parse_markdown::construct_markdown(res).unwrap(),
+        input
+    );
+
+    // unclosed inline tags & unmatched closing tags stay text
+    let res = super::parse_markdown::parse_markdown("a <b>c\n</i>").unwrap();
+    assert!(res.iter().all(|x| x.get_html().is_none()), "{:#?}", res);
+}
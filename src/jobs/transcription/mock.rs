@@ -0,0 +1,54 @@
+use crate::utils::config::Config;
+
+use super::{
+    backend::{Paragraph, Sentence, Topic, TranscriptionBackend, TranscriptionResult},
+    link::Link,
+};
+
+/// Returns the same transcript for every link without reading the audio
+/// Used to run the pipeline without network access or costs
+pub(crate) struct MockBackend {}
+
+#[async_trait::async_trait]
+impl TranscriptionBackend for MockBackend {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+    async fn transcribe(
+        &self,
+        _link: &Link,
+        _conf: &Config,
+    ) -> color_eyre::Result<TranscriptionResult> {
+        let sentence = |text: &str, start: f64, end: f64| Sentence {
+            text: text.to_owned(),
+            start,
+            end,
+        };
+        Ok(TranscriptionResult {
+            paragraphs: vec![
+                Paragraph {
+                    speaker: Some(0),
+                    start: 0.,
+                    end: 4.,
+                    sentences: vec![
+                        sentence("Good morning everyone.", 0., 2.),
+                        sentence("Today we talk about mocks.", 2., 4.),
+                    ],
+                },
+                Paragraph {
+                    speaker: Some(1),
+                    start: 5.,
+                    end: 6.,
+                    sentences: vec![sentence("Sounds great!", 5., 6.)],
+                },
+            ],
+            summary: "A mocked lesson about mocks.".to_owned(),
+            topics: vec![Topic {
+                topic: "Mocks".to_owned(),
+                confidence_score: 1.,
+            }],
+            request_id: Some("mock".to_owned()),
+            duration: Some(60.),
+        })
+    }
+}
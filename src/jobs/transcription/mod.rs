@@ -1,51 +1,102 @@
-use std::path::{Path, PathBuf};
+use std::{
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
 
-use crate::utils::git;
+use crate::utils::git::{self, blame::BlamedFile};
+use backend::{TranscriptionBackend, TranscriptionResult};
 use chrono::{DateTime, Duration, TimeZone, Utc};
 use color_eyre::eyre::{eyre, Context, OptionExt};
 use itertools::Itertools;
 use link::Link;
 use markdown::CorrelatingFile;
+use state::RecordingState;
 
-use crate::utils::config::Config;
+use crate::utils::config::{Config, TranscriptionConfig};
 
+mod backend;
 mod deepgram;
 mod file_discovery;
 mod file_meta;
-mod link;
+pub(crate) mod link;
+mod local;
 pub mod markdown;
+#[cfg(test)]
+mod mock;
+mod orphan;
+pub mod relink;
+mod source;
+mod state;
+pub mod status;
 mod template;
+pub mod unlink;
 
 pub async fn transcribe_audio(conf: &Config) -> color_eyre::Result<()> {
+    let backend = backend::get_backend(conf).await?;
+    transcribe_audio_with_backend(conf, backend.as_ref()).await
+}
+
+/// Creates the configured backend without using it; fails e.g. if its api key is missing
+/// returns the name of the backend
+pub async fn check_backend(conf: &Config) -> color_eyre::Result<&'static str> {
+    Ok(backend::get_backend(conf).await?.name())
+}
+
+/// Runs the transcription pipeline using the given backend instead of the configured one
+pub(crate) async fn transcribe_audio_with_backend(
+    conf: &Config,
+    backend: &dyn TranscriptionBackend,
+) -> color_eyre::Result<()> {
     let transcription_conf = conf
         .transcription
         .clone()
         .ok_or_eyre("Expected transcription conf to be initialized")?;
 
+    if conf.dry_run && !git::branch_exists(&transcription_conf.git_source_branch, &conf)? {
+        println!(
+            "[dry-run] branch '{}' doesn't exist yet; nothing to transcribe",
+            transcription_conf.git_source_branch
+        );
+        return Ok(());
+    }
     git::check_out_create_branch(&transcription_conf.git_source_branch, &conf)?;
 
-    let files_to_transcribe = file_discovery::discover_files(conf)?;
-
-    let deepgram = ::deepgram::Deepgram::new(transcription_conf.deepgram_key);
-    let credentials =
-        crate::utils::credentials::get_onedrive_credentials(&conf.credentials).await?;
-
-    let graph = graph_rs_sdk::GraphClient::new(credentials.token);
+    let file_discovery::Discovery {
+        links,
+        duplicates,
+        renamed,
+        orphaned,
+        mut state,
+    } = file_discovery::discover_files(conf)?;
 
-    git::check_out_create_branch(&transcription_conf.git_source_branch, &conf)?;
-
-    let mut links = Vec::new();
-    for file in files_to_transcribe {
-        match Link::from_path(&file, &conf) {
-            Ok(x) => links.push((file, x)),
-            Err(why) => {
-                log::error!("Failed to parse link for file {:?}: {:?}", file, why);
-            }
-        }
+    if links.is_empty()
+        && duplicates.is_empty()
+        && renamed.is_empty()
+        && (orphaned.is_empty() || !transcription_conf.mark_orphaned)
+    {
+        log::info!("didn't get any (new) links");
+        return Ok(());
     }
 
-    if links.is_empty() {
-        log::info!("didn't get any (new) links");
+    if conf.dry_run && !git::branch_exists(&transcription_conf.git_target_branch, &conf)? {
+        println!(
+            "[dry-run] would create branch '{}'",
+            transcription_conf.git_target_branch
+        );
+        for discovered in links {
+            println!(
+                "[dry-run] would transcribe {:?} -> {:?} using {}",
+                to_repo_path(&discovered.path, conf),
+                to_repo_path(&get_transcript_path(&discovered.path, conf)?, conf),
+                backend.name()
+            );
+        }
+        for (discovered, original) in duplicates {
+            println!(
+                "[dry-run] would use the transcript of {} for its duplicate {}",
+                original, discovered.key
+            );
+        }
         return Ok(());
     }
 
@@ -53,12 +104,30 @@ pub async fn transcribe_audio(conf: &Config) -> color_eyre::Result<()> {
 
     let (blamed_files, _) =
         git::blame::BlamedFile::blame_all(&conf).wrap_err("Failed to blame directory tree")?;
-    println!("blamed_files: {:?}", blamed_files);
+    log::debug!("blamed_files: {:?}", blamed_files);
     let mut files_to_link = Vec::new();
-    for (file, link) in links {
-        match process_file(conf, file.clone(), link.clone(), &deepgram, &graph).await {
-            Ok(x) => {
-                files_to_link.push(x);
+    for discovered in links {
+        if conf.dry_run {
+            let path = get_transcript_path(&discovered.path, conf)?;
+            println!(
+                "[dry-run] would transcribe {:?} -> {:?} using {}",
+                to_repo_path(&discovered.path, conf),
+                to_repo_path(&path, conf),
+                backend.name()
+            );
+            files_to_link.push((discovered, path, None));
+            continue;
+        }
+        match process_file(
+            conf,
+            discovered.path.clone(),
+            discovered.link.clone(),
+            backend,
+        )
+        .await
+        {
+            Ok((_, path, result)) => {
+                files_to_link.push((discovered, path, Some(result)));
             }
             Err(why) => {
                 log::error!("Failed to proccess link: {:?}", why);
@@ -69,31 +138,133 @@ pub async fn transcribe_audio(conf: &Config) -> color_eyre::Result<()> {
     let mut processed = Vec::new();
     // link transcripts to correlating files
     // TODO: make more efficient to not read all files multiple times
-    for (link, transcript_path) in files_to_link {
-        let cut_of_date = link.last_modified - transcription_conf.time_window;
-        let correlating_files = blamed_files
-            .clone()
-            .into_iter()
-            .map(|x| x.to_correlating_file(&conf, cut_of_date.clone()))
-            .collect::<Result<Vec<_>, _>>();
-        match handle_correlating_files(
-            correlating_files,
-            transcript_path.clone(),
+    for (discovered, transcript_path, result) in files_to_link {
+        let link = discovered.link.clone();
+        // the length of the audio is more reliable than the metadata of the storage
+        let duration = result
+            .as_ref()
+            .and_then(|x| x.duration)
+            .or(link.metadata.duration);
+        let linked_notes = link_transcript(
+            conf,
+            &blamed_files,
+            &transcript_path,
             &link.last_modified,
-        ) {
-            Ok(_) => processed.push(transcript_path.clone()),
+            &recording_span(
+                link.last_modified,
+                link.metadata.start,
+                link.metadata.end,
+                duration,
+                &transcription_conf,
+            ),
+            link.metadata.subject.as_deref(),
+        );
+        let Some(result) = result else {
+            continue; // dry run
+        };
+        // the transcription is recorded even if linking failed, so it isn't paid for twice
+        state.recordings.insert(
+            discovered.key.clone(),
+            RecordingState {
+                link_hash: discovered.hash.clone(),
+                recording: Some(discovered.recording.clone()),
+                provider: backend.name().to_owned(),
+                request_id: result.request_id.clone(),
+                cost: transcription_conf
+                    .cost_per_minute
+                    .zip(result.duration)
+                    .map(|(price, duration)| price * duration / 60.),
+                recorded_at: link.last_modified,
+                recording_start: link.metadata.start,
+                recording_end: link.metadata.end,
+                subject: link.metadata.subject.clone(),
+                duration,
+                transcribed_at: Utc::now(),
+                transcript: to_repo_path(&transcript_path, conf),
+                linked_notes,
+                content_hash: discovered.content_hash.clone(),
+                duplicate_of: None,
+                item_id: discovered.link.item_id(),
+                orphaned: false,
+            },
+        );
+        processed.push(transcript_path.clone());
+    }
+
+    // duplicates get the transcript of the recording they duplicate
+    let mut aliased = Vec::new();
+    for (discovered, original) in duplicates {
+        if conf.dry_run {
+            println!(
+                "[dry-run] would use the transcript of {} for its duplicate {}",
+                original, discovered.key
+            );
+            continue;
+        }
+        let Some(original_state) = state.recordings.get(&original).cloned() else {
+            log::warn!(
+                "Skipped the duplicate {}, because {} hasn't been transcribed",
+                discovered.key,
+                original
+            );
+            continue;
+        };
+        log::info!(
+            "Using the transcript {:?} for the duplicate {}",
+            original_state.transcript,
+            discovered.key
+        );
+        state.recordings.insert(
+            discovered.key.clone(),
+            RecordingState {
+                link_hash: discovered.hash.clone(),
+                recording: Some(discovered.recording.clone()),
+                provider: original_state.provider,
+                request_id: None,
+                cost: None,
+                recorded_at: discovered.link.last_modified,
+                recording_start: discovered.link.metadata.start,
+                recording_end: discovered.link.metadata.end,
+                subject: discovered.link.metadata.subject.clone(),
+                duration: original_state.duration,
+                transcribed_at: Utc::now(),
+                transcript: original_state.transcript,
+                linked_notes: vec![],
+                content_hash: discovered.content_hash.clone(),
+                duplicate_of: Some(original),
+                item_id: discovered.link.item_id(),
+                orphaned: false,
+            },
+        );
+        aliased.push(discovered.key);
+    }
+
+    // the recordings of these transcripts have been deleted
+    let mut marked = Vec::new();
+    for key in orphaned {
+        if !transcription_conf.mark_orphaned {
+            log::warn!("The link of {} is gone; its transcript is kept", key);
+            continue;
+        }
+        let Some(recording) = state.recordings.get_mut(&key) else {
+            continue;
+        };
+        match orphan::mark_orphaned(recording, conf) {
+            Ok(_) => {
+                recording.orphaned = true;
+                marked.push(key);
+            }
             Err(why) => {
-                log::error!(
-                    "Failed to link_correlating_files for transcript {:?}: {:?}",
-                    transcript_path,
-                    why
-                );
+                log::error!("Failed to mark {} as orphaned: {:?}", key, why);
             }
         }
     }
 
     // commit changes
-    if processed.len() > 0 {
+    if !conf.dry_run
+        && (processed.len() > 0 || aliased.len() > 0 || renamed.len() > 0 || marked.len() > 0)
+    {
+        state.write(conf)?;
         git::wrap_git_command_error(&git::git_command_wrapper(
             &["add", "*"],
             &conf.git_directory,
@@ -103,17 +274,33 @@ pub async fn transcribe_audio(conf: &Config) -> color_eyre::Result<()> {
             &[
                 "commit",
                 "-m",
-                &format!(
-                    "transcribed: {}",
-                    processed
-                        .iter()
-                        .map(|x| x
-                            .file_name()
-                            .unwrap_or_default()
-                            .to_str()
-                            .unwrap_or_default())
-                        .join(",")
-                ),
+                &[
+                    (
+                        "transcribed",
+                        processed
+                            .iter()
+                            .map(|x| {
+                                x.file_name()
+                                    .unwrap_or_default()
+                                    .to_string_lossy()
+                                    .to_string()
+                            })
+                            .collect_vec(),
+                    ),
+                    ("duplicates", aliased),
+                    (
+                        "renamed",
+                        renamed
+                            .iter()
+                            .map(|(old, new)| format!("{} -> {}", old, new))
+                            .collect_vec(),
+                    ),
+                    ("orphaned", marked),
+                ]
+                .into_iter()
+                .filter(|(_, x)| !x.is_empty())
+                .map(|(action, x)| format!("{}: {}", action, x.join(",")))
+                .join("; "),
                 "--author",
                 git::GIT_AUTHOR,
             ],
@@ -128,19 +315,34 @@ async fn process_file(
     conf: &Config,
     file_to_transcribe: PathBuf,
     link: Link,
-    deepgram: &::deepgram::Deepgram,
-    graph: &graph_rs_sdk::GraphClient,
-) -> color_eyre::Result<(Link, PathBuf)> {
+    backend: &dyn TranscriptionBackend,
+) -> color_eyre::Result<(Link, PathBuf, TranscriptionResult)> {
+    log::info!(
+        "Proccessing link '{:?}' using {}",
+        file_to_transcribe,
+        backend.name()
+    );
+    let transcription_result = backend
+        .transcribe(&link, conf)
+        .await
+        .wrap_err(eyre!("Failed to transcribe file"))?;
+    let file_content = template::get_transcription_file(&transcription_result, &link)?;
+    let path = get_transcript_path(&file_to_transcribe, conf)?;
+
+    if let Some(x) = path.parent() {
+        std::fs::create_dir_all(x)?;
+    }
+    std::fs::write(path.clone(), file_content)?;
+
+    Ok((link, path, transcription_result))
+}
+/// absolute path of the transcript belonging to the given (absolute) .link file
+fn get_transcript_path(link_file: &Path, conf: &Config) -> color_eyre::Result<PathBuf> {
     let transcription_config = conf
         .transcription
         .clone()
         .ok_or_eyre("Expected transcription config to be set")?;
-    log::info!("Proccessing link '{:?}'", file_to_transcribe);
-    let transcription_result = deepgram::transcribe_link(&link, conf, deepgram, graph)
-        .await
-        .wrap_err(eyre!("Failed to transcribe file"))?;
-    let file_content = template::get_transcription_file(&transcription_result, &link)?;
-    let file_without_link_extension = file_to_transcribe
+    let file_without_link_extension = link_file
         .file_name()
         .ok_or_eyre("expected to get filename")?
         .to_str()
@@ -150,33 +352,164 @@ async fn process_file(
         .ok_or_eyre("Infallible")?
         .to_string();
     let target_file_name = format!("{}.transcript.md", file_without_link_extension);
-    let dir = conf.git_directory.join(
-        transcription_config
-            .transcription_target_path
-            .strip_prefix("/")
-            .unwrap_or(&transcription_config.transcription_target_path),
+    let dir = from_repo_path(&transcription_config.transcription_target_path, conf);
+    // links in subdirectories of the audio dir get transcripts in the same subdirectories
+    let sub_dir = state::link_key(link_file, conf)?;
+    let sub_dir = Path::new(&sub_dir).parent().unwrap_or(Path::new(""));
+    Ok(dir.join(sub_dir).join(target_file_name))
+}
+/// Time span, in which the recording ran
+/// The explicit start & end of the link header are used as they are; a missing one is derived from the other
+/// and the length of the recording: `duration` seconds (`time_window` if unknown). Without both it ended at `recorded_at`
+fn recording_span(
+    recorded_at: DateTime<Utc>,
+    recording_start: Option<DateTime<Utc>>,
+    recording_end: Option<DateTime<Utc>>,
+    duration: Option<f64>,
+    transcription_conf: &TranscriptionConfig,
+) -> RangeInclusive<DateTime<Utc>> {
+    let length = duration
+        .map(|x| Duration::milliseconds((x * 1000.).round() as i64))
+        .unwrap_or(transcription_conf.time_window);
+    match (recording_start, recording_end) {
+        (Some(start), Some(end)) => start..=end,
+        (Some(start), None) => start..=(start + length),
+        (None, Some(end)) => (end - length)..=end,
+        (None, None) => (recorded_at - length)..=recorded_at,
+    }
+}
+/// Time span (including its bounds), in which changed notes are linked to a recording:
+/// the recording span widened by the configured padding
+fn correlation_window(
+    span: &RangeInclusive<DateTime<Utc>>,
+    transcription_conf: &TranscriptionConfig,
+) -> RangeInclusive<DateTime<Utc>> {
+    (*span.start() - transcription_conf.padding_before)
+        ..=(*span.end() + transcription_conf.padding_after)
+}
+/// Links the transcript (absolute path) to all notes of the subject changed inside the correlation window of the recording span
+/// Each heading links to the paragraph recorded closest to the time its section was written
+/// returns the linked notes relative to the repository root
+fn link_transcript(
+    conf: &Config,
+    blamed_files: &[BlamedFile],
+    transcript_path: &Path,
+    recorded_at: &DateTime<Utc>,
+    span: &RangeInclusive<DateTime<Utc>>,
+    subject: Option<&str>,
+) -> Vec<PathBuf> {
+    let Some(transcription_conf) = conf.transcription.clone() else {
+        log::error!("Expected transcription conf to be initialized");
+        return vec![];
+    };
+    let window = correlation_window(span, &transcription_conf);
+    // the transcript doesn't exist on a dry run
+    let paragraphs = std::fs::read_to_string(transcript_path)
+        .map(|x| template::paragraph_offsets(&x))
+        .unwrap_or_default();
+    log::debug!(
+        "Linking {:?} to the notes changed between {} and {}",
+        transcript_path,
+        window.start(),
+        window.end()
     );
-
-    std::fs::create_dir_all(dir.clone())?;
-    let path = dir.join(target_file_name.clone());
-    std::fs::write(path.clone(), file_content)?;
-
-    Ok((link, path))
+    let correlating_files = subject_notes(blamed_files, subject, &conf.git_directory)
+        .into_iter()
+        .map(|x| x.to_correlating_file(&conf, &window))
+        .collect::<Result<Vec<_>, _>>();
+    match handle_correlating_files(
+        correlating_files,
+        transcript_path.to_path_buf(),
+        recorded_at,
+        (&paragraphs, span.start()),
+        conf,
+    ) {
+        Ok(x) => x.iter().map(|x| to_repo_path(x, conf)).collect_vec(),
+        Err(why) => {
+            log::error!(
+                "Failed to link_correlating_files for transcript {:?}: {:?}",
+                transcript_path,
+                why
+            );
+            vec![]
+        }
+    }
+}
+/// The notes inside a folder named like the subject (ignoring case), e.g. '/notes/Biology/cells.md' for 'biology'
+/// all notes, if there is no subject or none of the notes belongs to it
+fn subject_notes<'a>(
+    blamed_files: &'a [BlamedFile],
+    subject: Option<&str>,
+    git_directory: &Path,
+) -> Vec<&'a BlamedFile> {
+    let Some(subject) = subject.map(|x| x.trim()).filter(|x| !x.is_empty()) else {
+        return blamed_files.iter().collect_vec();
+    };
+    let notes = blamed_files
+        .iter()
+        .filter(|x| {
+            let path = x.file.strip_prefix(git_directory).unwrap_or(&x.file);
+            path.parent().is_some_and(|x| {
+                x.components().any(|x| {
+                    x.as_os_str()
+                        .to_string_lossy()
+                        .eq_ignore_ascii_case(subject)
+                })
+            })
+        })
+        .collect_vec();
+    if notes.is_empty() {
+        log::warn!(
+            "No notes inside a folder of the subject '{}'; linking all notes",
+            subject
+        );
+        return blamed_files.iter().collect_vec();
+    }
+    notes
+}
+/// Converts an absolute path inside the repository to a path relative to the repository root
+/// starting with '/'
+fn to_repo_path(path: &Path, conf: &Config) -> PathBuf {
+    Path::new("/").join(path.strip_prefix(&conf.git_directory).unwrap_or(path))
+}
+/// Inverse of `to_repo_path`
+fn from_repo_path(path: &Path, conf: &Config) -> PathBuf {
+    conf.git_directory
+        .join(path.strip_prefix("/").unwrap_or(path))
 }
+/// returns the absolute paths of all files, which have been linked
+/// `paragraphs`: starts of the transcript's paragraphs & the start of the recording
+/// on a dry run the changes are only printed as a diff
 fn handle_correlating_files(
     files: color_eyre::Result<Vec<Option<CorrelatingFile>>>,
     transcript: PathBuf,
     time: &DateTime<Utc>,
-) -> color_eyre::Result<()> {
+    paragraphs: (&[f64], &DateTime<Utc>),
+    conf: &Config,
+) -> color_eyre::Result<Vec<PathBuf>> {
     let files = files?;
     let files = files.into_iter().filter_map(|x| x).collect_vec();
     log::info!("Got {} files to link", files.len());
 
+    let mut linked = Vec::new();
     for file in files {
-        match file.link_to_transcript(transcript.clone(), &file.content, time) {
+        let offsets = file.transcript_offsets(paragraphs.0, paragraphs.1);
+        match file.link_to_transcript(transcript.clone(), &file.content, time, &offsets) {
+            Ok(x) if conf.dry_run => {
+                print!(
+                    "{}",
+                    crate::utils::diff::unified_diff(
+                        &to_repo_path(&file.path, conf),
+                        &file.content,
+                        &x
+                    )
+                );
+                linked.push(file.path.clone());
+            }
             Ok(x) => match std::fs::write(file.path.clone(), x) {
                 Ok(_) => {
                     log::info!("Successfully linked {:?} -> {:?}", transcript, file.path);
+                    linked.push(file.path.clone());
                 }
                 Err(why) => {
                     log::error!(
@@ -197,5 +530,91 @@ fn handle_correlating_files(
             }
         }
     }
-    Ok(())
+    Ok(linked)
+}
+
+#[test]
+fn test_subject_notes() {
+    let notes =
+        ["notes/Biology/cells.md", "notes/chemistry.md", "biology.md"].map(|x| BlamedFile {
+            file: Path::new("/repo").join(x),
+            blame: vec![],
+        });
+    let selected = |subject: Option<&str>| {
+        subject_notes(&notes, subject, Path::new("/repo"))
+            .into_iter()
+            .map(|x| x.file.to_string_lossy().to_string())
+            .collect_vec()
+    };
+    assert_eq!(
+        selected(Some("biology")),
+        vec!["/repo/notes/Biology/cells.md"]
+    );
+    // no folder of the subject
+    assert_eq!(selected(Some("chemistry")).len(), 3);
+    assert_eq!(selected(None).len(), 3);
+}
+
+#[test]
+fn test_correlation_window() {
+    let vars = crate::utils::config_file::Variables::parse(
+        r#"
+[transcription]
+script_search_path = "/notes/"
+target_path = "/attachements/"
+git_branch = "master"
+audio_branch = "audio"
+audio_source_dir = "/audio_files/"
+time_window = 100
+padding_after = 30
+"#,
+        Path::new("korosensei.toml"),
+        None,
+    )
+    .unwrap();
+    let conf = TranscriptionConfig::from_environment(&vars).unwrap();
+    let at = |x: &str| DateTime::parse_from_rfc3339(x).unwrap().to_utc();
+    let ended = at("2024-07-14T12:00:00Z");
+
+    // a 45 minute lesson
+    assert_eq!(
+        correlation_window(
+            &recording_span(ended, None, None, Some(2700.), &conf),
+            &conf
+        ),
+        at("2024-07-14T11:00:00Z")..=at("2024-07-14T12:30:00Z")
+    );
+    // unknown duration
+    assert_eq!(
+        correlation_window(&recording_span(ended, None, None, None, &conf), &conf),
+        at("2024-07-14T10:05:00Z")..=at("2024-07-14T12:30:00Z")
+    );
+    // explicit start & end
+    let start = at("2024-07-14T11:30:00Z");
+    assert_eq!(
+        correlation_window(
+            &recording_span(ended, Some(start), Some(ended), Some(2700.), &conf),
+            &conf
+        ),
+        at("2024-07-14T11:15:00Z")..=at("2024-07-14T12:30:00Z")
+    );
+    // only the start is known (independent of the timestamp of the link)
+    let start = at("2024-07-14T11:00:00Z");
+    for recorded_at in [start, ended] {
+        assert_eq!(
+            correlation_window(
+                &recording_span(recorded_at, Some(start), None, Some(2700.), &conf),
+                &conf
+            ),
+            at("2024-07-14T10:45:00Z")..=at("2024-07-14T12:15:00Z")
+        );
+    }
+    // only the end is known
+    assert_eq!(
+        correlation_window(
+            &recording_span(ended, None, Some(ended), Some(2700.), &conf),
+            &conf
+        ),
+        at("2024-07-14T11:00:00Z")..=at("2024-07-14T12:30:00Z")
+    );
 }
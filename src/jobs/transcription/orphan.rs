@@ -0,0 +1,105 @@
+use crate::utils::config::Config;
+
+use super::state::RecordingState;
+
+/// text of the notice on top of an orphaned transcript
+const TRANSCRIPT_NOTICE: &str = "> **Orphaned**: the recording of this transcript has been deleted";
+/// appended to the text of links to an orphaned transcript
+const LINK_SUFFIX: &str = " (orphaned)";
+
+/// Marks the transcript of a deleted recording & the links to it inside the linked notes as orphaned
+/// Expects the transcription target branch to be checked out; on a dry run the changes are only printed as a diff
+pub(crate) fn mark_orphaned(recording: &RecordingState, conf: &Config) -> color_eyre::Result<()> {
+    let transcript_path = super::from_repo_path(&recording.transcript, conf);
+    let mut changes = vec![];
+    if transcript_path.is_file() {
+        let content = std::fs::read_to_string(&transcript_path)?;
+        if !content.starts_with(TRANSCRIPT_NOTICE) {
+            changes.push((
+                transcript_path.clone(),
+                format!("{}\n\n{}", TRANSCRIPT_NOTICE, content),
+                content,
+            ));
+        }
+    }
+
+    for note in recording.linked_notes.iter() {
+        let path = super::from_repo_path(note, conf);
+        if !path.is_file() {
+            log::warn!("Linked note {:?} doesn't exist anymore", note);
+            continue;
+        }
+        let content = std::fs::read_to_string(&path)?;
+        let marked =
+            super::markdown::mark_transcript_links(&content, &transcript_path, LINK_SUFFIX)?;
+        if marked != content {
+            changes.push((path, marked, content));
+        }
+    }
+
+    for (path, new, old) in changes {
+        if conf.dry_run {
+            print!(
+                "{}",
+                crate::utils::diff::unified_diff(&super::to_repo_path(&path, conf), &old, &new)
+            );
+            continue;
+        }
+        std::fs::write(&path, new)?;
+        log::info!("Marked {:?} as orphaned", path);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_mark_links() {
+    let content = "\
+# Mocking
+> _Links
+>
+> [14.07.2024 12:00](/attachements/lesson%201.mp3.transcript.md)
+> [14.07.2024 12:00](/attachements/other.mp3.transcript.md)
+
+## Stubs
+> _Links
+>
+> [14.07.2024 12:00](transcript:(62.5):/attachements/lesson%201.mp3.transcript.md)
+
+mocks are great [lecture][bio], [again](</attachements/lesson 1.mp3.transcript.md> \"title\")
+```
+[14.07.2024 12:00](/attachements/lesson%201.mp3.transcript.md)
+```
+
+[bio]: /attachements/lesson%201.mp3.transcript.md
+";
+    let expected = "\
+# Mocking
+> _Links
+>
+> [14.07.2024 12:00 (orphaned)](/attachements/lesson%201.mp3.transcript.md)
+> [14.07.2024 12:00](/attachements/other.mp3.transcript.md)
+
+## Stubs
+> _Links
+>
+> [14.07.2024 12:00 (orphaned)](transcript:(62.5):/attachements/lesson%201.mp3.transcript.md)
+
+mocks are great [lecture (orphaned)][bio], [again (orphaned)](</attachements/lesson 1.mp3.transcript.md> \"title\")
+```
+[14.07.2024 12:00](/attachements/lesson%201.mp3.transcript.md)
+```
+
+[bio]: /attachements/lesson%201.mp3.transcript.md
+";
+    let mark = |content: &str| {
+        super::markdown::mark_transcript_links(
+            content,
+            std::path::Path::new("/attachements/lesson 1.mp3.transcript.md"),
+            LINK_SUFFIX,
+        )
+        .unwrap()
+    };
+    assert_eq!(mark(content), expected);
+    // already marked links stay unchanged
+    assert_eq!(mark(expected), expected);
+}
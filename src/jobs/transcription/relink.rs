@@ -0,0 +1,146 @@
+use color_eyre::eyre::{eyre, Context as _, OptionExt as _};
+use itertools::Itertools as _;
+
+use crate::utils::{
+    config::Config,
+    git::{self, blame::BlamedFile},
+};
+
+use super::state::{RecordingState, State};
+
+/// Links an already existing transcript to the notes again (without transcribing it)
+/// The transcript is looked up in the state file, see `State::find`
+pub fn relink_transcript(conf: &Config, transcript: &str) -> color_eyre::Result<()> {
+    let mut state = read_state(conf)?;
+    let (key, recording) = state
+        .find(transcript)
+        .ok_or_eyre(format!("Unknown transcript '{}'", transcript))?;
+    let transcript_path = super::from_repo_path(&recording.transcript, conf);
+    if !transcript_path.is_file() {
+        return Err(eyre!(
+            "Transcript {:?} of '{}' doesn't exist",
+            recording.transcript,
+            key
+        ));
+    }
+
+    let (blamed_files, _) =
+        BlamedFile::blame_all(conf).wrap_err("Failed to blame directory tree")?;
+    let recording = relink(conf, &blamed_files, recording)?;
+    if conf.dry_run {
+        return Ok(());
+    }
+    state.recordings.insert(key, recording.clone());
+    state.write(conf)?;
+
+    let message = format!("relinked: {}", file_name(&recording));
+    if !git::commit_all(&message, conf)? {
+        log::info!("Nothing changed while relinking {:?}", transcript_path);
+    }
+    Ok(())
+}
+
+/// Links all existing transcripts to the notes again, e.g. after the notes have changed
+/// Links, which already exist, are only updated; duplicates & orphaned transcripts are skipped
+pub fn relink_all_transcripts(conf: &Config) -> color_eyre::Result<()> {
+    let mut state = read_state(conf)?;
+    let (blamed_files, _) =
+        BlamedFile::blame_all(conf).wrap_err("Failed to blame directory tree")?;
+
+    let mut relinked = Vec::new();
+    for (key, recording) in state.recordings.clone() {
+        if recording.duplicate_of.is_some() || recording.orphaned {
+            continue;
+        }
+        if !super::from_repo_path(&recording.transcript, conf).is_file() {
+            log::warn!(
+                "Skipped '{}', because its transcript {:?} doesn't exist",
+                key,
+                recording.transcript
+            );
+            continue;
+        }
+        let recording = relink(conf, &blamed_files, recording)?;
+        relinked.push(file_name(&recording));
+        state.recordings.insert(key, recording);
+    }
+    log::info!("Relinked {} transcripts", relinked.len());
+    if conf.dry_run || relinked.is_empty() {
+        return Ok(());
+    }
+    state.write(conf)?;
+
+    let message = format!("relinked: {}", relinked.join(","));
+    if !git::commit_all(&message, conf)? {
+        log::info!("Nothing changed while relinking all transcripts");
+    }
+    Ok(())
+}
+
+/// Checks out the transcription target branch & reads the state file
+fn read_state(conf: &Config) -> color_eyre::Result<State> {
+    let transcription_conf = conf
+        .transcription
+        .clone()
+        .ok_or_eyre("Expected transcription conf to be initialized")?;
+
+    if !git::branch_exists(&transcription_conf.git_target_branch, conf)? {
+        return Err(eyre!(
+            "Branch '{}' doesn't exist; nothing has been transcribed yet",
+            transcription_conf.git_target_branch
+        ));
+    }
+    git::check_out_create_branch(&transcription_conf.git_target_branch, conf)?;
+
+    State::read(conf)?.ok_or_eyre("No state file found; run the transcription first")
+}
+
+/// Links the transcript of the recording to the notes; returns the recording with the updated linked notes
+fn relink(
+    conf: &Config,
+    blamed_files: &[BlamedFile],
+    recording: RecordingState,
+) -> color_eyre::Result<RecordingState> {
+    let transcription_conf = conf
+        .transcription
+        .clone()
+        .ok_or_eyre("Expected transcription conf to be initialized")?;
+    let transcript_path = super::from_repo_path(&recording.transcript, conf);
+    let linked_notes = super::link_transcript(
+        conf,
+        blamed_files,
+        &transcript_path,
+        &recording.recorded_at,
+        &super::recording_span(
+            recording.recorded_at,
+            recording.recording_start,
+            recording.recording_end,
+            recording.duration,
+            &transcription_conf,
+        ),
+        recording.subject.as_deref(),
+    );
+    log::info!(
+        "Relinked {:?} to {} notes",
+        transcript_path,
+        linked_notes.len()
+    );
+
+    let mut recording = recording;
+    recording.linked_notes = recording
+        .linked_notes
+        .into_iter()
+        .chain(linked_notes)
+        .unique()
+        .collect_vec();
+    Ok(recording)
+}
+
+fn file_name(recording: &RecordingState) -> String {
+    recording
+        .transcript
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
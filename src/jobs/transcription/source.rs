@@ -0,0 +1,166 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::OptionExt as _;
+use reqwest::{header::HeaderValue, Url};
+use tokio::io::AsyncWriteExt as _;
+
+use crate::utils::{
+    cloud_storage::{get_storage, RemoteFile},
+    config::Config,
+};
+
+use super::link::{Link, LinkType};
+
+/// Where the audio of a link can actually be read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum AudioLocation {
+    /// absolute path on this machine
+    Local(PathBuf),
+    /// publicly downloadable url
+    Remote(Url),
+    /// url, which needs the authorization header to be downloaded
+    AuthenticatedRemote(Url, HeaderValue),
+}
+
+/// Resolves a link to a location, which can be handed over to a transcription backend
+/// NOTE: FileSytemLinks never cause any network requests
+pub(crate) async fn resolve_link(link: &Link, conf: &Config) -> color_eyre::Result<AudioLocation> {
+    Ok(match &link.link_target {
+        LinkType::FileSytemLink(rel_path) => AudioLocation::Local(
+            conf.git_directory
+                .join(rel_path.strip_prefix("/").unwrap_or(&rel_path)),
+        ),
+        LinkType::WebLink(url) => AudioLocation::Remote(url.clone()),
+        LinkType::OneDriveLink(_)
+        | LinkType::GoogleDriveLink(_)
+        | LinkType::DropboxLink(_)
+        | LinkType::WebDavLink(_) => {
+            let (provider, file) = link
+                .link_target
+                .cloud_file()?
+                .ok_or_eyre("Expected a cloud storage link")?;
+            let storage = get_storage(provider, &conf.credentials).await?;
+            match storage.download_location(&file).await? {
+                RemoteFile {
+                    url,
+                    authorization: None,
+                } => AudioLocation::Remote(url),
+                RemoteFile {
+                    url,
+                    authorization: Some(x),
+                } => AudioLocation::AuthenticatedRemote(url, x),
+            }
+        }
+    })
+}
+
+/// Downloads a remote location into a temporary file
+/// The caller is responsible for deleting the file
+pub(crate) async fn download_to_temp_file(
+    url: &Url,
+    authorization: Option<&HeaderValue>,
+) -> color_eyre::Result<PathBuf> {
+    let extension = url
+        .path_segments()
+        .and_then(|x| x.last())
+        .and_then(|x| x.rsplit_once("."))
+        .map(|x| format!(".{}", x.1))
+        .unwrap_or_default();
+    let path =
+        std::env::temp_dir().join(format!("korosensei-{}{}", rand::random::<u64>(), extension));
+
+    let mut req = reqwest::Client::new().get(url.clone());
+    if let Some(x) = authorization {
+        req = req.header(reqwest::header::AUTHORIZATION, x.clone());
+    }
+    let mut res = req.send().await?.error_for_status()?;
+    // recordings can be large, so they are streamed to disk instead of being buffered
+    let mut file = tokio::fs::File::create(&path).await?;
+    let written: color_eyre::Result<()> = async {
+        while let Some(chunk) = res.chunk().await? {
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        Ok(())
+    }
+    .await;
+    if let Err(why) = written {
+        if let Err(why) = tokio::fs::remove_file(&path).await {
+            log::warn!("Failed to remove partial download {:?}: {:?}", path, why);
+        }
+        return Err(why);
+    }
+    Ok(path)
+}
+
+#[tokio::test]
+async fn test_resolve_cloud_links() {
+    use wiremock::{
+        matchers::{body_json, method, path},
+        Mock, ResponseTemplate,
+    };
+
+    let mut env =
+        crate::jobs::test_pipeline::TestEnvironment::new(&[("notes/lesson.md", "# Mocking\n")])
+            .await;
+    env.mount_cloud_credentials().await;
+    Mock::given(method("POST"))
+        .and(path("/dropbox/2/files/get_temporary_link"))
+        .and(body_json(
+            serde_json::json!({ "path": "/Recordings/late.mp3" }),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "link": "https://dl.dropboxusercontent.com/late.mp3",
+        })))
+        .mount(&env.server)
+        .await;
+    let link = |target| Link {
+        link_target: target,
+        last_modified: chrono::DateTime::from_timestamp(1720958400, 0).unwrap(),
+        metadata: Default::default(),
+    };
+
+    assert_eq!(
+        resolve_link(
+            &link(LinkType::GoogleDriveLink("f1".to_owned())),
+            &env.config
+        )
+        .await
+        .unwrap(),
+        AudioLocation::AuthenticatedRemote(
+            Url::parse(&format!(
+                "{}/gdrive/drive/v3/files/f1?alt=media&supportsAllDrives=true",
+                env.server.uri()
+            ))
+            .unwrap(),
+            HeaderValue::from_static("Bearer gdrive-token")
+        )
+    );
+    assert_eq!(
+        resolve_link(
+            &link(LinkType::DropboxLink(PathBuf::from("/Recordings/late.mp3"))),
+            &env.config
+        )
+        .await
+        .unwrap(),
+        AudioLocation::Remote(Url::parse("https://dl.dropboxusercontent.com/late.mp3").unwrap())
+    );
+
+    env.configure_webdav();
+    assert_eq!(
+        resolve_link(
+            &link(LinkType::WebDavLink(PathBuf::from("/school/lesson 1.mp3"))),
+            &env.config
+        )
+        .await
+        .unwrap(),
+        AudioLocation::AuthenticatedRemote(
+            Url::parse(&format!(
+                "{}/remote.php/dav/files/nagisa/school/lesson%201.mp3",
+                env.server.uri()
+            ))
+            .unwrap(),
+            HeaderValue::from_static("Basic bmFnaXNhOmFwcC1wYXNzd29yZA==")
+        )
+    );
+}
@@ -0,0 +1,265 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context as _, OptionExt as _};
+use serde::{Deserialize, Serialize};
+use sha2::Digest as _;
+
+use crate::utils::config::Config;
+
+/// version of the state file format written by this build
+pub(crate) const STATE_VERSION: u32 = 1;
+
+/// Ledger of all processed recordings, committed to the transcription branch
+/// It decides which links still have to be transcribed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct State {
+    pub version: u32,
+    /// key: path of the .link file relative to the audio source dir
+    pub recordings: BTreeMap<String, RecordingState>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RecordingState {
+    /// sha256 of the .link file content
+    pub link_hash: String,
+    /// identifies the recording independent of the rest of the .link file (see `file_discovery::recording_identity`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recording: Option<String>,
+    /// transcription provider, 'unknown' for transcripts created before the state file existed
+    pub provider: String,
+    pub request_id: Option<String>,
+    /// estimated cost of the transcription (see TRANSCRIPTION_COST_PER_MINUTE)
+    pub cost: Option<f64>,
+    pub recorded_at: DateTime<Utc>,
+    /// explicit start of the recording from the link header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recording_start: Option<DateTime<Utc>>,
+    /// explicit end of the recording from the link header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recording_end: Option<DateTime<Utc>>,
+    /// subject from the link header; decides which notes are linked (see `subject_notes`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// length of the recording in seconds; decides which notes are linked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    pub transcribed_at: DateTime<Utc>,
+    /// relative to the repository root, starting with '/'
+    pub transcript: PathBuf,
+    /// relative to the repository root, starting with '/'
+    pub linked_notes: Vec<PathBuf>,
+    /// content hash of the recording (see the link header)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    /// key of the recording with the same content, whose transcript is used instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<String>,
+    /// id of the recording inside its cloud storage; used to follow renamed links
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item_id: Option<String>,
+    /// the recording has been deleted & its transcript marked as orphaned
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub orphaned: bool,
+}
+
+impl State {
+    pub fn new() -> State {
+        State {
+            version: STATE_VERSION,
+            recordings: BTreeMap::new(),
+        }
+    }
+    /// absolute path of the state file
+    pub fn path(conf: &Config) -> color_eyre::Result<PathBuf> {
+        let transcription_conf = conf
+            .transcription
+            .clone()
+            .ok_or_eyre("Expected transcription conf to be initialized")?;
+        Ok(conf.git_directory.join(
+            transcription_conf
+                .state_file
+                .strip_prefix("/")
+                .unwrap_or(&transcription_conf.state_file),
+        ))
+    }
+    /// Expects to be in the transcription target branch
+    /// returns None if there is no state file yet
+    pub fn read(conf: &Config) -> color_eyre::Result<Option<State>> {
+        let path = State::path(conf)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        let state: State = serde_json::from_str(&content)
+            .wrap_err(format!("Failed to parse state file {:?}", path))?;
+        if state.version > STATE_VERSION {
+            return Err(eyre!(
+                "State file {:?} has version {}, but only versions up to {} are supported",
+                path,
+                state.version,
+                STATE_VERSION
+            ));
+        }
+        Ok(Some(state))
+    }
+    /// Expects to be in the transcription target branch
+    pub fn write(&self, conf: &Config) -> color_eyre::Result<()> {
+        let path = State::path(conf)?;
+        if let Some(x) = path.parent() {
+            std::fs::create_dir_all(x)?;
+        }
+        std::fs::write(&path, format!("{}\n", serde_json::to_string_pretty(&self)?))?;
+        Ok(())
+    }
+    /// Finds a recording by its transcript (path relative to the repository root or file name)
+    /// or by its .link file (key or file name); recordings are preferred over their duplicates
+    pub fn find(&self, query: &str) -> Option<(String, RecordingState)> {
+        let query = query
+            .trim()
+            .trim_start_matches("./")
+            .trim_start_matches("/");
+        let query = Path::new(query);
+        self.recordings
+            .iter()
+            .filter(|(key, recording)| {
+                let transcript = recording
+                    .transcript
+                    .strip_prefix("/")
+                    .unwrap_or(&recording.transcript);
+                let key = Path::new(key.as_str());
+                transcript == query
+                    || recording.transcript.file_name() == Some(query.as_os_str())
+                    || key == query
+                    || key.file_name() == Some(query.as_os_str())
+            })
+            .min_by_key(|(_, recording)| recording.duplicate_of.is_some())
+            .map(|(key, recording)| (key.clone(), recording.clone()))
+    }
+    /// a link has to be transcribed if it is unknown or points to another recording since;
+    /// edits of the rest of the .link file (e.g. its title) don't count
+    /// recordings stored without their identity fall back to their content hash, item id or the hash of the .link file
+    pub fn needs_transcription(&self, key: &str, link_hash: &str, recording: &str) -> bool {
+        let Some(x) = self.recordings.get(key) else {
+            return true;
+        };
+        match x
+            .recording
+            .as_ref()
+            .or(x.content_hash.as_ref())
+            .or(x.item_id.as_ref())
+        {
+            Some(known) => known != recording,
+            None => x.link_hash != link_hash,
+        }
+    }
+}
+
+/// key of a link inside the state file
+pub(crate) fn link_key(link_file: &Path, conf: &Config) -> color_eyre::Result<String> {
+    let transcription_conf = conf
+        .transcription
+        .clone()
+        .ok_or_eyre("Expected transcription conf to be initialized")?;
+    let source_path = conf.git_directory.join(
+        transcription_conf
+            .git_source_path
+            .strip_prefix("/")
+            .unwrap_or(&transcription_conf.git_source_path),
+    );
+    let key = link_file.strip_prefix(&source_path).unwrap_or(link_file);
+    Ok(key
+        .to_str()
+        .ok_or_eyre(format!("Expected link path {:?} to be parsable", key))?
+        .to_owned())
+}
+
+/// sha256 of the file content
+pub(crate) fn hash_file(path: &Path) -> color_eyre::Result<String> {
+    let content = std::fs::read(path)?;
+    Ok(format!("{:x}", sha2::Sha256::digest(content)))
+}
+
+#[test]
+fn test_state_roundtrip() {
+    let mut state = State::new();
+    state.recordings.insert(
+        "lesson.mp3.link".to_owned(),
+        RecordingState {
+            link_hash: "abc".to_owned(),
+            recording: None,
+            provider: "deepgram".to_owned(),
+            request_id: Some("4f2c".to_owned()),
+            cost: Some(0.43),
+            recorded_at: DateTime::from_timestamp(1720958400, 0).unwrap(),
+            recording_start: None,
+            recording_end: None,
+            subject: Some("Biology".to_owned()),
+            duration: Some(2712.5),
+            transcribed_at: DateTime::from_timestamp(1720962000, 0).unwrap(),
+            transcript: PathBuf::from("/attachements/lesson.mp3.transcript.md"),
+            linked_notes: vec![PathBuf::from("/notes/lesson.md")],
+            content_hash: Some("sha256:e3b0".to_owned()),
+            duplicate_of: None,
+            item_id: Some("01BYE5RZ6QN3ZWBTUFOFD3GSPGOHDJD36K".to_owned()),
+            orphaned: true,
+        },
+    );
+    let json = serde_json::to_string_pretty(&state).unwrap();
+    assert_eq!(serde_json::from_str::<State>(&json).unwrap(), state);
+
+    // the content hash identifies the recording, the hash of the .link file doesn't matter
+    assert!(!state.needs_transcription("lesson.mp3.link", "abc", "sha256:e3b0"));
+    assert!(!state.needs_transcription("lesson.mp3.link", "edited", "sha256:e3b0"));
+    assert!(state.needs_transcription("lesson.mp3.link", "abc", "sha256:changed"));
+    assert!(state.needs_transcription("other.mp3.link", "abc", "sha256:e3b0"));
+
+    for query in [
+        "/attachements/lesson.mp3.transcript.md",
+        "attachements/lesson.mp3.transcript.md",
+        "lesson.mp3.transcript.md",
+        "lesson.mp3.link",
+    ] {
+        assert_eq!(
+            state.find(query).map(|x| x.0),
+            Some("lesson.mp3.link".to_owned()),
+            "{}",
+            query
+        );
+    }
+    assert_eq!(state.find("other.mp3.transcript.md"), None);
+
+    // without any identity the .link file decides
+    let mut legacy = state.recordings["lesson.mp3.link"].clone();
+    legacy.content_hash = None;
+    legacy.item_id = None;
+    state
+        .recordings
+        .insert("legacy.mp3.link".to_owned(), legacy);
+    assert!(!state.needs_transcription("legacy.mp3.link", "abc", "https://a.mp3"));
+    assert!(state.needs_transcription("legacy.mp3.link", "edited", "https://a.mp3"));
+    state
+        .recordings
+        .get_mut("legacy.mp3.link")
+        .unwrap()
+        .recording = Some("https://a.mp3".into());
+    assert!(!state.needs_transcription("legacy.mp3.link", "edited", "https://a.mp3"));
+    state.recordings.remove("legacy.mp3.link");
+
+    let mut duplicate = state.recordings["lesson.mp3.link"].clone();
+    duplicate.duplicate_of = Some("lesson.mp3.link".to_owned());
+    state
+        .recordings
+        .insert("copy.mp3.link".to_owned(), duplicate);
+    assert_eq!(
+        state.find("lesson.mp3.transcript.md").map(|x| x.0),
+        Some("lesson.mp3.link".to_owned())
+    );
+    assert_eq!(
+        state.find("copy.mp3.link").map(|x| x.0),
+        Some("copy.mp3.link".to_owned())
+    );
+}
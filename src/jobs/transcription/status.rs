@@ -0,0 +1,86 @@
+use color_eyre::eyre::OptionExt as _;
+
+use crate::utils::{config::Config, git};
+
+use super::file_discovery;
+
+/// Prints every .link file of the audio branch & whether it has been transcribed,
+/// followed by recordings in the state file whose .link file is gone
+pub fn print_status(conf: &Config) -> color_eyre::Result<()> {
+    let transcription_conf = conf
+        .transcription
+        .clone()
+        .ok_or_eyre("Expected transcription conf to be initialized")?;
+
+    for branch in [
+        &transcription_conf.git_source_branch,
+        &transcription_conf.git_target_branch,
+    ] {
+        if !git::branch_exists(branch, conf)? {
+            println!("Branch '{}' doesn't exist yet", branch);
+            return Ok(());
+        }
+    }
+
+    let (links, state) = file_discovery::discover_links(conf)?;
+
+    let mut pending = 0;
+    for link in links.iter() {
+        let line = match state.recordings.get(&link.key) {
+            None if link.link.metadata.skip_transcription => "skipped".to_owned(),
+            None => {
+                pending += 1;
+                "pending".to_owned()
+            }
+            Some(x) if state.needs_transcription(&link.key, &link.hash, &link.recording) => {
+                pending += 1;
+                format!("changed (transcript: {:?})", x.transcript)
+            }
+            Some(x) if x.duplicate_of.is_some() => format!(
+                "duplicate of {} -> {:?}",
+                x.duplicate_of.clone().unwrap_or_default(),
+                x.transcript
+            ),
+            Some(x) => format!(
+                "transcribed {} using {} -> {:?} ({} notes linked)",
+                x.transcribed_at
+                    .with_timezone(&conf.timezone)
+                    .format("%d.%m.%Y %H:%M"),
+                x.provider,
+                x.transcript,
+                x.linked_notes.len()
+            ),
+        };
+        println!(
+            "{} [{}]: {}",
+            link.key,
+            link.link
+                .last_modified
+                .with_timezone(&conf.timezone)
+                .format("%d.%m.%Y %H:%M"),
+            line
+        );
+    }
+
+    for (key, recording) in state.recordings.iter() {
+        let matches_filter = transcription_conf
+            .link_filter
+            .as_ref()
+            .map_or(true, |x| x.is_match(key));
+        if matches_filter && links.iter().all(|x| &x.key != key) {
+            println!(
+                "{}: link missing (transcript: {:?})",
+                key, recording.transcript
+            );
+        }
+    }
+
+    let cost: f64 = state.recordings.values().filter_map(|x| x.cost).sum();
+    println!(
+        "{} recordings, {} to transcribe; transcriptions so far cost {:.2}",
+        links.len(),
+        pending,
+        cost
+    );
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use color_eyre::eyre::{eyre, OptionExt};
 use itertools::Itertools;
 
-use super::{deepgram::TranscriptionResult, link::Link};
+use super::{backend::TranscriptionResult, link::Link};
 
 pub(crate) fn get_transcription_file(
     transcription: &TranscriptionResult,
@@ -9,7 +9,7 @@ pub(crate) fn get_transcription_file(
 ) -> color_eyre::Result<String> {
     Ok(format!(
         "\
-# Transcript '{}'
+# Transcript '{}'{}
 
 > _Links
 >
@@ -22,6 +22,11 @@ pub(crate) fn get_transcription_file(
 {}
 ",
         link.last_modified.format("%d.%m.%Y %H:%M"),
+        link.metadata
+            .title
+            .as_ref()
+            .map(|x| format!(": {}", x))
+            .unwrap_or_default(),
         format_tags(&transcription, &link)?,
         transcription.summary,
         format_paragraphs(&transcription, &link)?
@@ -40,6 +45,16 @@ fn format_tags(res: &TranscriptionResult, link: &Link) -> color_eyre::Result<Str
             )
         })
         .collect_vec();
+    if let Some(x) = &link.metadata.subject {
+        res.insert(
+            0,
+            format!(
+                "> [{}](subject://{})",
+                x,
+                url_escape::encode_component(x).to_string()
+            ),
+        );
+    }
     res.push(format!("> [Source File]({})", format_link(link, None)?));
     Ok(res.join("\n"))
 }
@@ -73,14 +88,55 @@ fn format_link(link: &Link, offset: Option<f64>) -> color_eyre::Result<String> {
             }
             format!("onedrive:{}", url_escape::encode_path(&link).to_string())
         }
+        crate::jobs::transcription::link::LinkType::GoogleDriveLink(id) => format!(
+            "https://drive.google.com/file/d/{}/view",
+            url_escape::encode_component(id)
+        ),
+        crate::jobs::transcription::link::LinkType::DropboxLink(dropbox) => {
+            let mut link = dropbox
+                .to_str()
+                .ok_or_eyre(eyre!(
+                    "Expected Link path to be parsable; got {:?}",
+                    dropbox
+                ))?
+                .to_owned();
+            if !link.starts_with("/") {
+                link = format!("/{}", link);
+            }
+            format!("dropbox:{}", url_escape::encode_path(&link).to_string())
+        }
+        crate::jobs::transcription::link::LinkType::WebDavLink(webdav) => {
+            let mut link = webdav
+                .to_str()
+                .ok_or_eyre(eyre!("Expected Link path to be parsable; got {:?}", webdav))?
+                .to_owned();
+            if !link.starts_with("/") {
+                link = format!("/{}", link);
+            }
+            format!("webdav:{}", url_escape::encode_path(&link).to_string())
+        }
     };
     if let Some(x) = offset {
         res = format!("transcript:({}):{}", x, res)
     }
     Ok(res)
 }
+/// starts (in seconds) of the paragraphs of a transcript, read from their speaker links
+pub(crate) fn paragraph_offsets(transcript: &str) -> Vec<f64> {
+    lazy_regex::regex!(r"\]\(transcript:\(([0-9.]+)\):")
+        .captures_iter(transcript)
+        .filter_map(|x| x[1].parse::<f64>().ok())
+        .dedup()
+        .collect_vec()
+}
 fn format_paragraphs(res: &TranscriptionResult, link: &Link) -> color_eyre::Result<String> {
-    let speakers = res.paragraphs.iter().map(|x| x.speaker).dedup().count();
+    let speakers = res
+        .paragraphs
+        .iter()
+        .filter_map(|x| x.speaker)
+        .max()
+        .map(|x| x + 1)
+        .unwrap_or_default();
     let mut speaker_colors = Vec::new();
     for _ in 0..speakers {
         let color = generate_random_color();
@@ -92,10 +148,15 @@ fn format_paragraphs(res: &TranscriptionResult, link: &Link) -> color_eyre::Resu
         let speaker = x
             .speaker
             .ok_or_eyre(format!("Expected speaker to be set, got {:?}", x))?;
+        // speakers named in the link header
+        let name = match link.metadata.speakers.get(speaker) {
+            Some(x) => x.clone(),
+            None => format!("Person {:2>0}", speaker),
+        };
         result.push_str(&format!(
-            "<mark style=\"background-color:{}\">[**Person {:2>0}**]({})</mark>: {}<br/>\n",
+            "<mark style=\"background-color:{}\">[**{}**]({})</mark>: {}<br/>\n",
             speaker_colors[speaker],
-            speaker,
+            name,
             format_link(link, Some(x.start))?,
             x.sentences.iter().map(|x| x.clone().text).join(" ")
         ));
@@ -137,3 +198,87 @@ fn generate_random_color() -> (u8, u8, u8) {
     let h = (rand + phi) % 1.;
     hsv_to_rgb(h, 0.5, 0.95)
 }
+
+#[test]
+fn test_transcription_file() {
+    use super::backend::{Paragraph, Sentence, Topic};
+
+    let paragraph = |speaker: usize, start: f64, text: &str| Paragraph {
+        speaker: Some(speaker),
+        start,
+        end: start + 1.,
+        sentences: vec![Sentence {
+            text: text.to_owned(),
+            start,
+            end: start + 1.,
+        }],
+    };
+    let transcription = TranscriptionResult {
+        paragraphs: vec![
+            paragraph(0, 0., "Today we talk about mocks."),
+            paragraph(1, 5., "Sounds great!"),
+            paragraph(2, 9.5, "Me too."),
+        ],
+        summary: "A mocked lesson about mocks.".to_owned(),
+        topics: vec![Topic {
+            topic: "Test doubles".to_owned(),
+            confidence_score: 1.,
+        }],
+        request_id: None,
+        duration: Some(60.),
+    };
+    let file = |link: &str| {
+        get_transcription_file(
+            &transcription,
+            &Link::parse_link_file(link, &chrono_tz::UTC).unwrap(),
+        )
+        .unwrap()
+    };
+
+    let transcript = file(
+        "+++\ntitle = \"Mocking 101\"\nsubject = \"testing\"\nspeakers = [\"Koro-Sensei\", \"Nagisa\"]\n+++\nonedrive:(1720958400):/school/lesson 1.mp3",
+    );
+    assert!(transcript.starts_with("# Transcript '14.07.2024 12:00': Mocking 101\n"));
+    assert!(transcript.contains(
+        "> [testing](subject://testing)\n> [Test doubles](topic://Test%20doubles)\n> [Source File](onedrive:/school/lesson%201.mp3)\n"
+    ));
+    assert!(transcript.contains("[**Koro-Sensei**](transcript:(0):onedrive:/school/lesson%201.mp3)</mark>: Today we talk about mocks."));
+    assert!(transcript.contains("[**Nagisa**](transcript:(5):onedrive:/school/lesson%201.mp3)"));
+    // speakers without a name
+    assert!(transcript.contains("[**Person 2**](transcript:(9.5):"));
+
+    // the one-line format has no title
+    assert!(file("onedrive:(1720958400):/school/lesson 1.mp3")
+        .starts_with("# Transcript '14.07.2024 12:00'\n"));
+}
+
+#[test]
+fn test_format_link() {
+    use super::link::LinkType;
+
+    let link = |target| Link {
+        link_target: target,
+        last_modified: chrono::DateTime::from_timestamp(1720958400, 0).unwrap(),
+        metadata: Default::default(),
+    };
+    assert_eq!(
+        format_link(
+            &link(LinkType::OneDriveLink("/school/lesson 1.mp3".into())),
+            None
+        )
+        .unwrap(),
+        "onedrive:/school/lesson%201.mp3"
+    );
+    assert_eq!(
+        format_link(&link(LinkType::GoogleDriveLink("f2".to_owned())), None).unwrap(),
+        "https://drive.google.com/file/d/f2/view"
+    );
+    assert_eq!(
+        format_link(
+            &link(LinkType::DropboxLink("/Recordings/late.mp3".into())),
+            Some(9.5)
+        )
+        .unwrap(),
+        "transcript:(9.5):dropbox:/Recordings/late.mp3"
+    );
+}
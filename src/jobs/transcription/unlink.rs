@@ -0,0 +1,138 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, OptionExt as _};
+use itertools::Itertools as _;
+
+use crate::utils::{config::Config, git};
+
+use super::state::State;
+
+/// Deletes a transcript, the links to it inside the notes, its entry in the state file & the .link file of the recording
+/// For a duplicate only its entry & .link file are deleted; the duplicates of a deleted transcript are transcribed again
+pub fn unlink_transcript(conf: &Config, transcript: &str) -> color_eyre::Result<()> {
+    let transcription_conf = conf
+        .transcription
+        .clone()
+        .ok_or_eyre("Expected transcription conf to be initialized")?;
+
+    if !git::branch_exists(&transcription_conf.git_target_branch, conf)? {
+        return Err(eyre!(
+            "Branch '{}' doesn't exist; nothing has been transcribed yet",
+            transcription_conf.git_target_branch
+        ));
+    }
+    git::check_out_create_branch(&transcription_conf.git_target_branch, conf)?;
+
+    let mut state =
+        State::read(conf)?.ok_or_eyre("No state file found; run the transcription first")?;
+    let (key, recording) = state
+        .find(transcript)
+        .ok_or_eyre(format!("Unknown transcript '{}'", transcript))?;
+    let transcript_path = super::from_repo_path(&recording.transcript, conf);
+    let link_path = super::from_repo_path(&transcription_conf.git_source_path.join(&key), conf);
+
+    // the original still uses the transcript
+    let notes = match recording.duplicate_of {
+        Some(_) => vec![],
+        None => remove_note_links(&recording.transcript, conf)?,
+    };
+
+    if conf.dry_run {
+        for (path, new, old) in notes {
+            print!(
+                "{}",
+                crate::utils::diff::unified_diff(&super::to_repo_path(&path, conf), &old, &new)
+            );
+        }
+        println!(
+            "[dry-run] would delete {:?} ('{}') & {:?} ('{}')",
+            recording.transcript,
+            transcription_conf.git_target_branch,
+            super::to_repo_path(&link_path, conf),
+            transcription_conf.git_source_branch
+        );
+        return Ok(());
+    }
+
+    let duplicates = state
+        .recordings
+        .iter()
+        .filter(|(_, x)| x.duplicate_of.as_ref() == Some(&key))
+        .map(|(x, _)| x.clone())
+        .collect_vec();
+    if recording.duplicate_of.is_none() {
+        if transcript_path.is_file() {
+            std::fs::remove_file(&transcript_path)?;
+        }
+        for (path, new, _) in notes {
+            std::fs::write(&path, new)?;
+            log::info!(
+                "Removed the links to {:?} from {:?}",
+                recording.transcript,
+                path
+            );
+        }
+        for x in duplicates {
+            log::warn!(
+                "{} has to be transcribed again, as it duplicated {}",
+                x,
+                key
+            );
+            state.recordings.remove(&x);
+        }
+    }
+    state.recordings.remove(&key);
+    state.write(conf)?;
+    git::commit_all(
+        &format!(
+            "unlinked: {}",
+            recording
+                .transcript
+                .file_name()
+                .unwrap_or_default()
+                .to_str()
+                .unwrap_or_default()
+        ),
+        conf,
+    )?;
+
+    if git::branch_exists(&transcription_conf.git_source_branch, conf)? {
+        git::check_out_create_branch(&transcription_conf.git_source_branch, conf)?;
+        if link_path.is_file() {
+            std::fs::remove_file(&link_path)?;
+            git::commit_all(&format!("remove: {}", key), conf)?;
+        }
+    }
+    Ok(())
+}
+/// Removes the links to the transcript (relative to the repository root) from all notes below the search path
+/// returns (absolute path, new content, old content) of the notes, which have changed
+fn remove_note_links(
+    transcript: &Path,
+    conf: &Config,
+) -> color_eyre::Result<Vec<(PathBuf, String, String)>> {
+    let transcription_conf = conf
+        .transcription
+        .clone()
+        .ok_or_eyre("Expected transcription conf to be initialized")?;
+    let search_path =
+        super::from_repo_path(&transcription_conf.transcription_script_search_path, conf);
+    let transcript_path = super::from_repo_path(transcript, conf);
+
+    let mut res = Vec::new();
+    for entry in walkdir::WalkDir::new(search_path) {
+        let path = entry?.into_path();
+        if !path.is_file()
+            || path.extension().map_or(true, |x| x != "md")
+            || path == transcript_path
+        {
+            continue;
+        }
+        let content = std::fs::read_to_string(&path)?;
+        let removed = super::markdown::remove_transcript_links(&content, &transcript_path)?;
+        if removed != content {
+            res.push((path, removed, content));
+        }
+    }
+    Ok(res)
+}
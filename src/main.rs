@@ -1,4 +1,6 @@
 use clap::Parser;
+use color_eyre::eyre::OptionExt as _;
+use utils::commandline::{Command, ConfigCommand};
 
 mod jobs;
 mod utils;
@@ -16,16 +18,72 @@ async fn main() -> color_eyre::Result<()> {
             log::info!("the .env file will be skipped: {}", x);
         }
     }
+    let vars = crate::utils::config_file::Variables::load(
+        args.config.as_deref(),
+        args.profile.as_deref(),
+    )?;
+    let (audio_sync, transcription) = args.command.required_config();
     let mut config =
-        crate::utils::config::Config::from_environment(args.audio_linker, args.transcription)?;
+        crate::utils::config::Config::from_environment(&vars, audio_sync, transcription)?;
+    config.dry_run = args.dry_run;
+    if let Some(x) = config.audio_sync.as_mut() {
+        args.command.apply_audio_sync_overrides(x);
+    }
+    if let Some(x) = config.transcription.as_mut() {
+        args.command.apply_transcription_overrides(x);
+    }
 
     crate::utils::prerequisits::check_prerequisits(&mut config)?;
 
-    if args.audio_linker {
-        crate::jobs::audio_linker::link_audio(&config).await?;
-    }
-    if args.transcription {
-        crate::jobs::transcription::transcribe_audio(&config).await?;
+    match args.command {
+        Command::Link { .. } => {
+            crate::jobs::audio_linker::link_audio(&config).await?;
+        }
+        Command::Transcribe { .. } => {
+            crate::jobs::transcription::transcribe_audio(&config).await?;
+        }
+        Command::Relink {
+            transcript: Some(transcript),
+            ..
+        } => {
+            crate::jobs::transcription::relink::relink_transcript(&config, &transcript)?;
+        }
+        Command::Relink { .. } => {
+            crate::jobs::transcription::relink::relink_all_transcripts(&config)?;
+        }
+        Command::Status { .. } => {
+            crate::jobs::transcription::status::print_status(&config)?;
+        }
+        Command::Config {
+            command: ConfigCommand::Check,
+        } => {
+            let backend = crate::jobs::transcription::check_backend(&config).await?;
+            match vars.path() {
+                Some(x) => println!(
+                    "config file: {:?} (profile: {})",
+                    x,
+                    vars.profile().unwrap_or("-")
+                ),
+                None => println!("config file: -"),
+            }
+            println!("git: {:?} ({:?})", config.git_directory, config.git_exec);
+            println!("credential provider: {:?}", config.credentials.provider);
+            println!("transcription provider: {}", backend);
+            println!("configuration ok");
+        }
+        Command::Login => {
+            let oauth = config.credentials.oauth.as_ref().ok_or_eyre(
+                "Logging in is only needed with ONEDRIVE_CREDENTIAL_PROVIDER=device_code",
+            )?;
+            let token = crate::utils::credentials::oauth::login(oauth).await?;
+            println!(
+                "Logged in; the token (scope: {}) was stored in {:?}",
+                token.scope, oauth.token_cache
+            );
+        }
+        Command::Unlink { transcript, .. } => {
+            crate::jobs::transcription::unlink::unlink_transcript(&config, &transcript)?;
+        }
     }
     return Ok(());
 }
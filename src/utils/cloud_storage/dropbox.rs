@@ -0,0 +1,138 @@
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context as _};
+use reqwest::{header::HeaderValue, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::utils::config::{RefreshTokenConfig, StorageProvider};
+
+use super::{CloudEntry, CloudStorage, RemoteFile};
+
+#[derive(Debug, Deserialize)]
+struct ListFolderResult {
+    entries: Vec<Metadata>,
+    cursor: String,
+    has_more: bool,
+}
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    #[serde(rename = ".tag")]
+    tag: String,
+    /// 'id:...', not set for deleted entries
+    id: Option<String>,
+    name: String,
+    path_display: Option<String>,
+    /// only set for files
+    server_modified: Option<DateTime<Utc>>,
+    /// only set for files; https://www.dropbox.com/developers/reference/content-hash
+    content_hash: Option<String>,
+}
+#[derive(Debug, Deserialize)]
+struct TemporaryLink {
+    link: String,
+}
+
+/// Dropbox api v2; files & folders are referenced by their path
+pub(super) struct DropboxStorage {
+    client: reqwest::Client,
+    authorization: HeaderValue,
+    api_base_url: Url,
+}
+impl DropboxStorage {
+    pub(super) async fn new(conf: &RefreshTokenConfig) -> color_eyre::Result<DropboxStorage> {
+        let token = crate::utils::credentials::refresh::get_access_token(conf).await?;
+        Ok(DropboxStorage {
+            client: reqwest::Client::new(),
+            authorization: HeaderValue::from_str(&format!("Bearer {}", token))?,
+            api_base_url: conf.api_base_url.clone(),
+        })
+    }
+    /// calls an rpc endpoint, e.g. 'files/list_folder'
+    async fn call<T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        body: serde_json::Value,
+    ) -> color_eyre::Result<T> {
+        let url = Url::parse(&format!(
+            "{}/2/{}",
+            self.api_base_url.as_str().trim_end_matches('/'),
+            endpoint
+        ))?;
+        let res = self
+            .client
+            .post(url)
+            .header(reqwest::header::AUTHORIZATION, self.authorization.clone())
+            .json(&body)
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            return Err(eyre!(
+                "Dropbox {} failed ({}): {:?}",
+                endpoint,
+                res.status().as_u16(),
+                res.text().await
+            ));
+        }
+        res.json().await.wrap_err(format!(
+            "Failed to read the response of Dropbox {}",
+            endpoint
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl CloudStorage for DropboxStorage {
+    fn provider(&self) -> StorageProvider {
+        StorageProvider::Dropbox
+    }
+    async fn list_folder(&self, folder: &str) -> color_eyre::Result<Vec<CloudEntry>> {
+        // the root is ''
+        let folder = super::normalize_path(folder);
+        let mut res: ListFolderResult = self
+            .call("files/list_folder", serde_json::json!({ "path": folder }))
+            .await?;
+        let mut entries = vec![];
+        loop {
+            entries.extend(res.entries.into_iter().filter_map(|x| {
+                let is_folder = match x.tag.as_str() {
+                    "file" => false,
+                    "folder" => true,
+                    // deleted
+                    _ => return None,
+                };
+                Some(CloudEntry {
+                    reference: x
+                        .path_display
+                        .unwrap_or_else(|| format!("{}/{}", folder, x.name)),
+                    name: x.name,
+                    is_folder,
+                    last_modified: x.server_modified,
+                    hash: x.content_hash.map(|x| format!("dropbox:{}", x)),
+                    id: x.id,
+                    duration: None,
+                })
+            }));
+            if !res.has_more {
+                break;
+            }
+            res = self
+                .call(
+                    "files/list_folder/continue",
+                    serde_json::json!({ "cursor": res.cursor }),
+                )
+                .await?;
+        }
+        Ok(entries)
+    }
+    async fn download_location(&self, file: &str) -> color_eyre::Result<RemoteFile> {
+        let res: TemporaryLink = self
+            .call(
+                "files/get_temporary_link",
+                serde_json::json!({ "path": super::normalize_path(file) }),
+            )
+            .await?;
+        Ok(RemoteFile {
+            url: res.link.parse()?,
+            authorization: None,
+        })
+    }
+}
@@ -0,0 +1,132 @@
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context as _};
+use reqwest::{header::HeaderValue, Url};
+use serde::Deserialize;
+
+use crate::utils::config::{RefreshTokenConfig, StorageProvider};
+
+use super::{CloudEntry, CloudStorage, RemoteFile};
+
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileList {
+    next_page_token: Option<String>,
+    files: Vec<File>,
+}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct File {
+    id: String,
+    name: String,
+    mime_type: String,
+    modified_time: Option<DateTime<Utc>>,
+    /// only set for binary files
+    sha256_checksum: Option<String>,
+    md5_checksum: Option<String>,
+}
+
+/// Google Drive api v3; folders & files are referenced by their id
+pub(super) struct GoogleDriveStorage {
+    client: reqwest::Client,
+    authorization: HeaderValue,
+    api_base_url: Url,
+}
+impl GoogleDriveStorage {
+    pub(super) async fn new(conf: &RefreshTokenConfig) -> color_eyre::Result<GoogleDriveStorage> {
+        let token = crate::utils::credentials::refresh::get_access_token(conf).await?;
+        Ok(GoogleDriveStorage {
+            client: reqwest::Client::new(),
+            authorization: HeaderValue::from_str(&format!("Bearer {}", token))?,
+            api_base_url: conf.api_base_url.clone(),
+        })
+    }
+    /// e.g. https://www.googleapis.com/drive/v3/files/<id>
+    fn files_url(&self, id: Option<&str>) -> color_eyre::Result<Url> {
+        let base = format!(
+            "{}/drive/v3/files",
+            self.api_base_url.as_str().trim_end_matches('/')
+        );
+        Ok(Url::parse(&match id {
+            Some(id) => format!("{}/{}", base, url_escape::encode_component(id)),
+            None => base,
+        })?)
+    }
+}
+
+#[async_trait::async_trait]
+impl CloudStorage for GoogleDriveStorage {
+    fn provider(&self) -> StorageProvider {
+        StorageProvider::GoogleDrive
+    }
+    async fn list_folder(&self, folder: &str) -> color_eyre::Result<Vec<CloudEntry>> {
+        let query = format!(
+            "'{}' in parents and trashed = false",
+            folder.replace("'", "\\'")
+        );
+        let mut entries = vec![];
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut req = self
+                .client
+                .get(self.files_url(None)?)
+                .header(reqwest::header::AUTHORIZATION, self.authorization.clone())
+                .query(&[
+                    ("q", query.as_str()),
+                    (
+                        "fields",
+                        "nextPageToken,files(id,name,mimeType,modifiedTime,sha256Checksum,md5Checksum)",
+                    ),
+                    ("pageSize", "1000"),
+                    ("supportsAllDrives", "true"),
+                    ("includeItemsFromAllDrives", "true"),
+                ]);
+            if let Some(x) = &page_token {
+                req = req.query(&[("pageToken", x)]);
+            }
+            let res = req.send().await?;
+            if !res.status().is_success() {
+                return Err(eyre!(
+                    "Failed to list the Google Drive folder {} ({}): {:?}",
+                    folder,
+                    res.status().as_u16(),
+                    res.text().await
+                ));
+            }
+            let res: FileList = res
+                .json()
+                .await
+                .wrap_err(format!("Failed to read the files of folder {}", folder))?;
+            entries.extend(res.files.into_iter().map(|x| CloudEntry {
+                is_folder: x.mime_type == FOLDER_MIME_TYPE,
+                name: x.name,
+                last_modified: x.modified_time,
+                id: Some(x.id.clone()),
+                reference: x.id,
+                hash: match (x.sha256_checksum, x.md5_checksum) {
+                    (Some(x), _) => Some(format!("sha256:{}", x)),
+                    (None, Some(x)) => Some(format!("md5:{}", x)),
+                    (None, None) => None,
+                },
+                // only videos have media metadata
+                duration: None,
+            }));
+            match res.next_page_token {
+                Some(x) => page_token = Some(x),
+                None => break,
+            }
+        }
+        Ok(entries)
+    }
+    async fn download_location(&self, file: &str) -> color_eyre::Result<RemoteFile> {
+        let mut url = self.files_url(Some(file))?;
+        url.query_pairs_mut()
+            .append_pair("alt", "media")
+            .append_pair("supportsAllDrives", "true");
+        Ok(RemoteFile {
+            url,
+            authorization: Some(self.authorization.clone()),
+        })
+    }
+}
@@ -0,0 +1,373 @@
+//! Cloud storages, whose recordings are linked by the audio linker & downloaded for the transcription
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::OptionExt as _;
+use reqwest::{header::HeaderValue, Url};
+
+use super::config::{CredentialConfig, StorageProvider};
+
+mod dropbox;
+mod gdrive;
+mod onedrive;
+mod webdav;
+
+/// A file or folder inside a cloud storage folder
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloudEntry {
+    pub name: String,
+    pub is_folder: bool,
+    /// None for folders of storages, which don't track it
+    pub last_modified: Option<DateTime<Utc>>,
+    /// what a link stores after the timestamp & what `list_folder` expects: the path, or the id for Google Drive
+    pub reference: String,
+    /// content hash as '<algorithm>:<value>' (e.g. 'sha256:<hex>'), if the storage provides one
+    pub hash: Option<String>,
+    /// stable id, which is kept when the file is renamed or moved; None if the storage has none
+    pub id: Option<String>,
+    /// length of the recording in seconds, if the storage knows it
+    pub duration: Option<f64>,
+}
+
+/// Where the content of a file can be downloaded
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteFile {
+    pub url: Url,
+    /// None: the url can be downloaded without authentication (e.g. a temporary link)
+    pub authorization: Option<HeaderValue>,
+}
+
+#[async_trait::async_trait]
+pub trait CloudStorage: Send + Sync {
+    fn provider(&self) -> StorageProvider;
+    /// lists the files & folders directly inside a folder (reference as in `CloudEntry::reference`)
+    async fn list_folder(&self, folder: &str) -> color_eyre::Result<Vec<CloudEntry>>;
+    async fn download_location(&self, file: &str) -> color_eyre::Result<RemoteFile>;
+}
+
+/// Creates a client for the storage, authenticated with the configured credentials
+pub async fn get_storage(
+    provider: StorageProvider,
+    conf: &CredentialConfig,
+) -> color_eyre::Result<Box<dyn CloudStorage>> {
+    Ok(match provider {
+        StorageProvider::OneDrive => Box::new(onedrive::OneDriveStorage::new(conf).await?),
+        StorageProvider::GoogleDrive => Box::new(
+            gdrive::GoogleDriveStorage::new(
+                conf.gdrive
+                    .as_ref()
+                    .ok_or_eyre("Expected GDRIVE_REFRESH_TOKEN to be set")?,
+            )
+            .await?,
+        ),
+        StorageProvider::Dropbox => Box::new(
+            dropbox::DropboxStorage::new(
+                conf.dropbox
+                    .as_ref()
+                    .ok_or_eyre("Expected DROPBOX_REFRESH_TOKEN to be set")?,
+            )
+            .await?,
+        ),
+        StorageProvider::WebDav => Box::new(webdav::WebDavStorage::new(
+            conf.webdav
+                .as_ref()
+                .ok_or_eyre("Expected WEBDAV_URL to be set")?,
+        )?),
+    })
+}
+
+/// Lists the files of a folder & of its subfolders up to `depth` levels deep
+/// returns (subfolder relative to `folder` joined by '/', '' for the folder itself; file)
+/// & the references of the subfolders, which haven't been listed because of the depth
+pub async fn list_files(
+    storage: &dyn CloudStorage,
+    folder: &str,
+    depth: usize,
+) -> color_eyre::Result<(Vec<(String, CloudEntry)>, Vec<String>)> {
+    let mut files = vec![];
+    let mut skipped = vec![];
+    let mut folders = vec![(String::new(), folder.to_owned(), 0)];
+    while let Some((subfolder, reference, level)) = folders.pop() {
+        for entry in storage.list_folder(&reference).await? {
+            if !entry.is_folder {
+                files.push((subfolder.clone(), entry));
+            } else if level < depth {
+                let name = match subfolder.as_str() {
+                    "" => entry.name,
+                    x => format!("{}/{}", x, entry.name),
+                };
+                folders.push((name, entry.reference, level + 1));
+            } else {
+                log::debug!("Skipped folder {} (recursion depth)", entry.reference);
+                skipped.push(entry.reference);
+            }
+        }
+    }
+    Ok((files, skipped))
+}
+
+/// '/a/b/' -> '/a/b'; '' & '/' -> ''
+fn normalize_path(path: &str) -> String {
+    let path = path.trim().trim_matches('/');
+    match path {
+        "" => String::new(),
+        x => format!("/{}", x),
+    }
+}
+
+#[tokio::test]
+async fn test_list_files() {
+    use itertools::Itertools as _;
+    use wiremock::{
+        matchers::{body_json, header, method, path, query_param},
+        Mock, ResponseTemplate,
+    };
+
+    let mut env =
+        crate::jobs::test_pipeline::TestEnvironment::new(&[("notes/lesson.md", "# Mocking\n")])
+            .await;
+    env.mount_cloud_credentials().await;
+    env.configure_webdav();
+    let modified = "2024-07-14T12:00:00Z";
+    let list = |storage: StorageProvider, folder: &'static str, depth: usize| {
+        let conf = env.config.credentials.clone();
+        async move {
+            let storage = get_storage(storage, &conf).await.unwrap();
+            let (files, skipped) = list_files(storage.as_ref(), folder, depth).await.unwrap();
+            let files = files
+                .into_iter()
+                .sorted_by_key(|x| x.1.reference.clone())
+                .collect_vec();
+            (files, skipped)
+        }
+    };
+    let file = |reference: &str| CloudEntry {
+        name: reference.rsplit('/').next().unwrap().to_owned(),
+        is_folder: false,
+        last_modified: Some(DateTime::parse_from_rfc3339(modified).unwrap().to_utc()),
+        reference: reference.to_owned(),
+        hash: None,
+        id: None,
+        duration: None,
+    };
+
+    // OneDrive lists folders by their path
+    let recorded = DateTime::parse_from_rfc3339(modified).unwrap().to_utc();
+    Mock::given(method("GET"))
+        .and(path("/v1.0/me/drive/root:/school/recordings:/children"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "value": [
+                {
+                    "id": "item-1",
+                    "name": "lesson.mp3",
+                    "file": { "hashes": { "sha256Hash": "AB12", "quickXorHash": "AAAA" } },
+                    "audio": { "duration": 2700000 },
+                    "lastModifiedDateTime": modified,
+                },
+                { "name": "week1", "folder": { "childCount": 2 }, "lastModifiedDateTime": modified },
+            ],
+        })))
+        .mount(&env.server)
+        .await;
+    env.mount_onedrive_folder(
+        "/school/recordings/week1",
+        &[("monday.mp3", recorded), ("extra/", recorded)],
+    )
+    .await;
+    env.mount_onedrive_folder("/school/recordings/week1/extra", &[("deep.mp3", recorded)])
+        .await;
+    let lesson = CloudEntry {
+        hash: Some("sha256:ab12".to_owned()),
+        id: Some("item-1".to_owned()),
+        duration: Some(2700.),
+        ..file("/school/recordings/lesson.mp3")
+    };
+    assert_eq!(
+        list(StorageProvider::OneDrive, "/school/recordings/", 0).await,
+        (
+            vec![(String::new(), lesson.clone())],
+            vec!["/school/recordings/week1".to_owned()]
+        )
+    );
+    assert_eq!(
+        list(StorageProvider::OneDrive, "/school/recordings/", 1).await,
+        (
+            vec![
+                (String::new(), lesson.clone()),
+                (
+                    "week1".to_owned(),
+                    file("/school/recordings/week1/monday.mp3")
+                ),
+            ],
+            vec!["/school/recordings/week1/extra".to_owned()]
+        )
+    );
+    assert_eq!(
+        list(StorageProvider::OneDrive, "/school/recordings/", 2).await,
+        (
+            vec![
+                (String::new(), lesson.clone()),
+                (
+                    "week1/extra".to_owned(),
+                    file("/school/recordings/week1/extra/deep.mp3")
+                ),
+                (
+                    "week1".to_owned(),
+                    file("/school/recordings/week1/monday.mp3")
+                ),
+            ],
+            vec![]
+        )
+    );
+
+    // Google Drive references everything by its id
+    for (folder, files) in [
+        (
+            "root-id",
+            serde_json::json!([
+                { "id": "f1", "name": "lesson.mp3", "mimeType": "audio/mpeg", "modifiedTime": modified },
+                { "id": "d1", "name": "week1", "mimeType": "application/vnd.google-apps.folder" },
+            ]),
+        ),
+        (
+            "d1",
+            serde_json::json!([
+                { "id": "f2", "name": "extra.mp3", "mimeType": "audio/mpeg", "modifiedTime": modified, "md5Checksum": "cd34" },
+            ]),
+        ),
+    ] {
+        Mock::given(method("GET"))
+            .and(path("/gdrive/drive/v3/files"))
+            .and(query_param(
+                "q",
+                format!("'{}' in parents and trashed = false", folder),
+            ))
+            .and(header("authorization", "Bearer gdrive-token"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "files": files })),
+            )
+            .mount(&env.server)
+            .await;
+    }
+    let gdrive_file = |id: &str, name: &str| CloudEntry {
+        name: name.to_owned(),
+        id: Some(id.to_owned()),
+        ..file(id)
+    };
+    assert_eq!(
+        list(StorageProvider::GoogleDrive, "root-id", 1).await,
+        (
+            vec![
+                (String::new(), gdrive_file("f1", "lesson.mp3")),
+                (
+                    "week1".to_owned(),
+                    CloudEntry {
+                        hash: Some("md5:cd34".to_owned()),
+                        ..gdrive_file("f2", "extra.mp3")
+                    }
+                ),
+            ],
+            vec![]
+        )
+    );
+    assert_eq!(
+        list(StorageProvider::GoogleDrive, "root-id", 0).await,
+        (
+            vec![(String::new(), gdrive_file("f1", "lesson.mp3"))],
+            vec!["d1".to_owned()]
+        )
+    );
+
+    // Dropbox pages its listings & reports deleted files
+    Mock::given(method("POST"))
+        .and(path("/dropbox/2/files/list_folder"))
+        .and(body_json(serde_json::json!({ "path": "/Recordings" })))
+        .and(header("authorization", "Bearer dropbox-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "entries": [{
+                ".tag": "file",
+                "name": "dropped.mp3",
+                "path_display": "/Recordings/dropped.mp3",
+                "server_modified": modified,
+            }],
+            "cursor": "page-2",
+            "has_more": true,
+        })))
+        .mount(&env.server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/dropbox/2/files/list_folder/continue"))
+        .and(body_json(serde_json::json!({ "cursor": "page-2" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "entries": [
+                { ".tag": "file", "name": "late.mp3", "path_display": "/Recordings/late.mp3", "server_modified": modified },
+                { ".tag": "deleted", "name": "gone.mp3" },
+            ],
+            "cursor": "page-3",
+            "has_more": false,
+        })))
+        .mount(&env.server)
+        .await;
+    assert_eq!(
+        list(StorageProvider::Dropbox, "/Recordings/", 1).await,
+        (
+            vec![
+                (String::new(), file("/Recordings/dropped.mp3")),
+                (String::new(), file("/Recordings/late.mp3")),
+            ],
+            vec![]
+        )
+    );
+
+    // WebDAV lists the folder itself as well
+    let response = |href: &str, folder: bool| {
+        format!(
+            "<d:response><d:href>{}</d:href><d:propstat><d:prop>{}</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+            href,
+            match folder {
+                true => "<d:resourcetype><d:collection/></d:resourcetype>".to_owned(),
+                false => "<d:resourcetype/><d:getlastmodified>Sun, 14 Jul 2024 12:00:00 GMT</d:getlastmodified>".to_owned(),
+            }
+        )
+    };
+    for (folder, responses) in [
+        (
+            "school/",
+            vec![
+                response("/remote.php/dav/files/nagisa/school/", true),
+                response("/remote.php/dav/files/nagisa/school/lesson%201.mp3", false),
+                response("/remote.php/dav/files/nagisa/school/week1/", true),
+            ],
+        ),
+        (
+            "school/week1/",
+            vec![
+                response("/remote.php/dav/files/nagisa/school/week1/", true),
+                response("/remote.php/dav/files/nagisa/school/week1/extra.mp3", false),
+            ],
+        ),
+    ] {
+        Mock::given(method("PROPFIND"))
+            .and(path(format!("/remote.php/dav/files/nagisa/{}", folder)))
+            .and(header("depth", "1"))
+            .and(header(
+                "authorization",
+                "Basic bmFnaXNhOmFwcC1wYXNzd29yZA==",
+            ))
+            .respond_with(ResponseTemplate::new(207).set_body_string(format!(
+                "<?xml version=\"1.0\"?><d:multistatus xmlns:d=\"DAV:\">{}</d:multistatus>",
+                responses.join("")
+            )))
+            .mount(&env.server)
+            .await;
+    }
+    assert_eq!(
+        list(StorageProvider::WebDav, "/school", 1).await,
+        (
+            vec![
+                (String::new(), file("/school/lesson 1.mp3")),
+                ("week1".to_owned(), file("/school/week1/extra.mp3")),
+            ],
+            vec![]
+        )
+    );
+}
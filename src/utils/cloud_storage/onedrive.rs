@@ -0,0 +1,159 @@
+use color_eyre::eyre::eyre;
+use graph_rs_sdk::{http::HttpResponseExt as _, GraphClient, ODataQuery as _};
+use serde::Deserialize;
+
+use crate::utils::config::{CredentialConfig, StorageProvider};
+
+use super::{CloudEntry, CloudStorage, RemoteFile};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OneDriveChildren {
+    id: Option<String>,
+    name: String,
+    folder: Option<OneDriveFolder>,
+    file: Option<OneDriveFile>,
+    audio: Option<OneDriveMedia>,
+    video: Option<OneDriveMedia>,
+    last_modified_date_time: String,
+}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OneDriveChildrenVec {
+    value: Vec<OneDriveChildren>,
+}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OneDriveFolder {}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OneDriveFile {
+    hashes: Option<OneDriveHashes>,
+}
+/// audio & video facets
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OneDriveMedia {
+    /// milliseconds
+    duration: Option<i64>,
+}
+/// OneDrive for Business only provides the quickXorHash, personal drives the sha1 & sha256 hashes as well
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OneDriveHashes {
+    sha256_hash: Option<String>,
+    sha1_hash: Option<String>,
+    quick_xor_hash: Option<String>,
+}
+impl OneDriveHashes {
+    /// the strongest available hash
+    fn best(self) -> Option<String> {
+        if let Some(x) = self.sha256_hash {
+            return Some(format!("sha256:{}", x.to_lowercase()));
+        }
+        if let Some(x) = self.sha1_hash {
+            return Some(format!("sha1:{}", x.to_lowercase()));
+        }
+        self.quick_xor_hash.map(|x| format!("quickXorHash:{}", x))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphResponse {
+    #[serde(rename = "@microsoft.graph.downloadUrl")]
+    download_url: String,
+}
+
+pub(super) struct OneDriveStorage {
+    graph: GraphClient,
+}
+impl OneDriveStorage {
+    pub(super) async fn new(conf: &CredentialConfig) -> color_eyre::Result<OneDriveStorage> {
+        let token = crate::utils::credentials::get_onedrive_credentials(conf).await?;
+        if !token.scope.contains("Files.Read") {
+            return Err(eyre!("Access token didn't cover the scrope 'Files.Read'"));
+        }
+        Ok(OneDriveStorage {
+            graph: crate::utils::credentials::new_graph_client(conf, token.token),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl CloudStorage for OneDriveStorage {
+    fn provider(&self) -> StorageProvider {
+        StorageProvider::OneDrive
+    }
+    async fn list_folder(&self, folder: &str) -> color_eyre::Result<Vec<CloudEntry>> {
+        let folder = super::normalize_path(folder);
+        if folder.is_empty() {
+            return Err(eyre!("Due to technical limitations the onedrive source_dir cant be located at the drives root"));
+        }
+
+        let children = self
+            .graph
+            .me()
+            .drive()
+            .item_by_path(format!(":{}:", folder))
+            .list_children()
+            .select(&[
+                "id",
+                "name",
+                "folder",
+                "file",
+                "audio",
+                "video",
+                "lastModifiedDateTime",
+            ])
+            .paging()
+            .json::<OneDriveChildrenVec>()
+            .await?;
+
+        let mut entries = vec![];
+        for res in children.into_iter() {
+            if !res.status().is_success() {
+                return Err(eyre!(
+                    "Failed to read url {:?}: ({})",
+                    res.url(),
+                    res.status().as_u16()
+                ));
+            }
+            for res in res.into_body()?.value {
+                entries.push(CloudEntry {
+                    reference: format!("{}/{}", folder, res.name),
+                    is_folder: res.folder.is_some(),
+                    hash: res.file.and_then(|x| x.hashes).and_then(|x| x.best()),
+                    id: res.id,
+                    duration: res
+                        .audio
+                        .or(res.video)
+                        .and_then(|x| x.duration)
+                        .map(|x| x as f64 / 1000.),
+                    last_modified: Some(
+                        chrono::DateTime::parse_from_rfc3339(&res.last_modified_date_time)?
+                            .to_utc(),
+                    ),
+                    name: res.name,
+                });
+            }
+        }
+        Ok(entries)
+    }
+    async fn download_location(&self, file: &str) -> color_eyre::Result<RemoteFile> {
+        let file = self
+            .graph
+            .me()
+            .drive()
+            .item_by_path(format!(":{}:", super::normalize_path(file)))
+            .get_items()
+            .select(&["@microsoft.graph.downloadUrl"])
+            .send()
+            .await?
+            .json::<GraphResponse>()
+            .await?;
+        Ok(RemoteFile {
+            url: file.download_url.parse()?,
+            authorization: None,
+        })
+    }
+}
@@ -0,0 +1,230 @@
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context as _, OptionExt as _};
+use reqwest::{header::HeaderValue, Method, Url};
+
+use crate::utils::config::{StorageProvider, WebDavConfig};
+
+use super::{CloudEntry, CloudStorage, RemoteFile};
+
+const DAV_NAMESPACE: &str = "DAV:";
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:">
+  <d:prop>
+    <d:resourcetype/>
+    <d:getlastmodified/>
+  </d:prop>
+</d:propfind>"#;
+
+/// WebDAV server (e.g. Nextcloud); files & folders are referenced by their path relative to the configured url
+pub(super) struct WebDavStorage {
+    client: reqwest::Client,
+    url: Url,
+    /// basic auth, None if no username is configured
+    authorization: Option<HeaderValue>,
+}
+impl WebDavStorage {
+    pub(super) fn new(conf: &WebDavConfig) -> color_eyre::Result<WebDavStorage> {
+        let client = reqwest::Client::new();
+        // let reqwest encode the credentials
+        let authorization = match &conf.username {
+            Some(username) => client
+                .get(conf.url.clone())
+                .basic_auth(username, conf.password.as_ref())
+                .build()?
+                .headers()
+                .get(reqwest::header::AUTHORIZATION)
+                .cloned(),
+            None => None,
+        };
+        Ok(WebDavStorage {
+            client,
+            url: conf.url.clone(),
+            authorization,
+        })
+    }
+    /// '/school/a b.mp3' -> https://cloud.example.com/remote.php/dav/files/user/school/a%20b.mp3
+    fn file_url(&self, path: &str) -> color_eyre::Result<Url> {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .map_err(|_| eyre!("Expected WEBDAV_URL {} to be a base url", self.url))?
+            .pop_if_empty()
+            .extend(path.split("/").filter(|x| !x.is_empty()));
+        Ok(url)
+    }
+    /// the path of a href in a PROPFIND response relative to the configured url; None if it is outside of it
+    fn relative_path(&self, href: &str) -> Option<String> {
+        // hrefs may be absolute urls or absolute paths
+        let href = match Url::parse(href) {
+            Ok(x) => x.path().to_owned(),
+            Err(_) => href.to_owned(),
+        };
+        let href = url_escape::decode(&href).to_string();
+        let base = url_escape::decode(self.url.path()).to_string();
+        let path = href.strip_prefix(base.trim_end_matches('/'))?;
+        if !path.is_empty() && !path.starts_with("/") {
+            return None;
+        }
+        Some(super::normalize_path(path))
+    }
+}
+
+#[async_trait::async_trait]
+impl CloudStorage for WebDavStorage {
+    fn provider(&self) -> StorageProvider {
+        StorageProvider::WebDav
+    }
+    async fn list_folder(&self, folder: &str) -> color_eyre::Result<Vec<CloudEntry>> {
+        let folder = super::normalize_path(folder);
+        let mut url = self.file_url(&folder)?;
+        // collections are addressed with a trailing '/'
+        url.path_segments_mut()
+            .map_err(|_| eyre!("Expected WEBDAV_URL {} to be a base url", self.url))?
+            .push("");
+        let mut req = self
+            .client
+            .request(Method::from_bytes(b"PROPFIND")?, url.clone())
+            .header("Depth", "1")
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                "application/xml; charset=utf-8",
+            )
+            .body(PROPFIND_BODY);
+        if let Some(x) = &self.authorization {
+            req = req.header(reqwest::header::AUTHORIZATION, x.clone());
+        }
+        let res = req.send().await?;
+        if !res.status().is_success() {
+            return Err(eyre!(
+                "Failed to list the WebDAV folder {} ({})",
+                url,
+                res.status().as_u16()
+            ));
+        }
+        let body = res.text().await?;
+        parse_multistatus(&body)
+            .wrap_err(format!("Failed to parse the PROPFIND response of {}", url))?
+            .into_iter()
+            .filter_map(|(href, is_folder, last_modified)| {
+                let path = self.relative_path(&href)?;
+                // the folder itself is part of the response
+                if path == folder {
+                    return None;
+                }
+                Some((path, is_folder, last_modified))
+            })
+            .map(|(path, is_folder, last_modified)| {
+                Ok(CloudEntry {
+                    name: path
+                        .rsplit("/")
+                        .next()
+                        .ok_or_eyre(format!("Expected {} to have a file name", path))?
+                        .to_owned(),
+                    is_folder,
+                    last_modified,
+                    reference: path,
+                    // etags change with the content, but aren't comparable between files
+                    hash: None,
+                    id: None,
+                    duration: None,
+                })
+            })
+            .collect()
+    }
+    async fn download_location(&self, file: &str) -> color_eyre::Result<RemoteFile> {
+        Ok(RemoteFile {
+            url: self.file_url(file)?,
+            authorization: self.authorization.clone(),
+        })
+    }
+}
+
+/// (href, is collection, last modified) of every response of a multistatus document
+fn parse_multistatus(body: &str) -> color_eyre::Result<Vec<(String, bool, Option<DateTime<Utc>>)>> {
+    let doc = roxmltree::Document::parse(body)?;
+    let mut res = vec![];
+    for response in doc.descendants().filter(|x| {
+        x.tag_name().namespace() == Some(DAV_NAMESPACE) && x.tag_name().name() == "response"
+    }) {
+        let href = child(response, "href")
+            .and_then(|x| x.text())
+            .ok_or_eyre("Expected every response to have a href")?
+            .trim()
+            .to_owned();
+        let is_folder =
+            child(response, "resourcetype").is_some_and(|x| child(x, "collection").is_some());
+        let last_modified = match child(response, "getlastmodified").and_then(|x| x.text()) {
+            Some(x) => Some(
+                DateTime::parse_from_rfc2822(x.trim())
+                    .wrap_err(format!("Failed to parse getlastmodified of {}", href))?
+                    .to_utc(),
+            ),
+            None => None,
+        };
+        res.push((href, is_folder, last_modified));
+    }
+    Ok(res)
+}
+/// first descendant element of the DAV: namespace
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.descendants()
+        .find(|x| x.tag_name().namespace() == Some(DAV_NAMESPACE) && x.tag_name().name() == name)
+}
+
+#[test]
+fn test_parse_multistatus() {
+    let body = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+  <d:response>
+    <d:href>/remote.php/dav/files/nagisa/school/</d:href>
+    <d:propstat>
+      <d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/remote.php/dav/files/nagisa/school/lesson%201.mp3</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:resourcetype/>
+        <d:getlastmodified>Sun, 14 Jul 2024 12:00:00 GMT</d:getlastmodified>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#;
+    assert_eq!(
+        parse_multistatus(body).unwrap(),
+        vec![
+            (
+                "/remote.php/dav/files/nagisa/school/".to_owned(),
+                true,
+                None
+            ),
+            (
+                "/remote.php/dav/files/nagisa/school/lesson%201.mp3".to_owned(),
+                false,
+                Some(DateTime::from_timestamp(1720958400, 0).unwrap())
+            ),
+        ]
+    );
+
+    let storage = WebDavStorage::new(&WebDavConfig {
+        url: Url::parse("https://cloud.example.com/remote.php/dav/files/nagisa/").unwrap(),
+        username: Some("nagisa".to_owned()),
+        password: Some("app-password".to_owned()),
+    })
+    .unwrap();
+    assert_eq!(
+        storage.relative_path("/remote.php/dav/files/nagisa/school/lesson%201.mp3"),
+        Some("/school/lesson 1.mp3".to_owned())
+    );
+    assert_eq!(storage.relative_path("/remote.php/dav/files/other/"), None);
+    assert_eq!(
+        storage.file_url("/school/lesson 1.mp3").unwrap().as_str(),
+        "https://cloud.example.com/remote.php/dav/files/nagisa/school/lesson%201.mp3"
+    );
+    assert!(storage.authorization.is_some());
+}
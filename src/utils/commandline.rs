@@ -1,10 +1,229 @@
-use clap::Parser;
+use std::path::PathBuf;
+
+use clap::{Args as ClapArgs, Parser, Subcommand};
+
+use super::config::{AudioSyncConfig, TranscriptionConfig};
 
 #[derive(Debug, Parser)]
 #[command(version, about, arg_required_else_help = true)]
 pub struct Args {
-    #[arg(short, long)]
-    pub audio_linker: bool,
-    #[arg(short, long)]
-    pub transcription: bool,
+    /// Only print what would be done (links, transcripts & a diff of the changed notes);
+    /// nothing is written, committed or sent to a paid api
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// Config file to use instead of korosensei.toml in the current directory or GIT_DIRECTORY
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Profile of the config file to use ([profiles.<name>])
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Create .link files for new recordings in the audio branch
+    Link {
+        /// Overrides AUDIO_GIT_BRANCH
+        #[arg(long)]
+        branch: Option<String>,
+    },
+    /// Transcribe new .link files & link the transcripts to the notes
+    Transcribe {
+        #[command(flatten)]
+        branches: BranchOverrides,
+        #[command(flatten)]
+        linking: LinkingOverrides,
+        /// Only consider .link files whose path (relative to the audio dir) matches this regex
+        #[arg(long)]
+        filter: Option<regex::Regex>,
+        /// Transcribe the (matching) links again, even if their recording has already been transcribed
+        #[arg(long)]
+        force: bool,
+    },
+    /// Link an existing transcript to the notes again, without transcribing it
+    Relink {
+        /// The transcript (path or file name) or the .link file of the recording
+        #[arg(required_unless_present = "all")]
+        transcript: Option<String>,
+        /// Relink all transcripts, e.g. after the notes have changed
+        #[arg(long, conflicts_with = "transcript")]
+        all: bool,
+        #[command(flatten)]
+        branches: BranchOverrides,
+        #[command(flatten)]
+        linking: LinkingOverrides,
+    },
+    /// Show all recordings and whether they have been transcribed
+    Status {
+        #[command(flatten)]
+        branches: BranchOverrides,
+        /// Only show .link files whose path (relative to the audio dir) matches this regex
+        #[arg(long)]
+        filter: Option<regex::Regex>,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Log in to OneDrive with a device code (ONEDRIVE_CREDENTIAL_PROVIDER=device_code)
+    Login,
+    /// Delete a transcript and the .link file of its recording
+    ///
+    /// The recording itself stays in the drive; remove it there, or it is linked again
+    Unlink {
+        /// The transcript (path or file name) or the .link file of the recording
+        transcript: String,
+        #[command(flatten)]
+        branches: BranchOverrides,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Load the whole configuration and check the prerequisites
+    Check,
+}
+
+#[derive(Debug, ClapArgs)]
+pub struct BranchOverrides {
+    /// Overrides TRANSCRIPTION_AUDIO_BRANCH
+    #[arg(long)]
+    pub audio_branch: Option<String>,
+    /// Overrides TRANSCRIPTION_GIT_BRANCH
+    #[arg(long)]
+    pub target_branch: Option<String>,
+}
+impl BranchOverrides {
+    pub fn apply(&self, conf: &mut TranscriptionConfig) {
+        if let Some(x) = &self.audio_branch {
+            conf.git_source_branch = x.clone();
+        }
+        if let Some(x) = &self.target_branch {
+            conf.git_target_branch = x.clone();
+        }
+    }
+}
+
+#[derive(Debug, ClapArgs)]
+pub struct LinkingOverrides {
+    /// Overrides TRANSCRIPTION_TIME_WINDOW (in minutes)
+    #[arg(long)]
+    pub time_window: Option<i64>,
+    /// Overrides TRANSCRIPTION_SCRIPT_SEARCH_PATH
+    #[arg(long)]
+    pub search_path: Option<PathBuf>,
+}
+impl LinkingOverrides {
+    pub fn apply(&self, conf: &mut TranscriptionConfig) {
+        if let Some(x) = self.time_window {
+            conf.time_window = chrono::Duration::minutes(x);
+        }
+        if let Some(x) = &self.search_path {
+            conf.transcription_script_search_path = x.clone();
+        }
+    }
+}
+
+impl Command {
+    /// (audio_sync, transcription): the parts of the configuration the command needs
+    pub fn required_config(&self) -> (bool, bool) {
+        match self {
+            Command::Link { .. } => (true, false),
+            Command::Config { .. } => (true, true),
+            Command::Login => (false, false),
+            _ => (false, true),
+        }
+    }
+    pub fn apply_audio_sync_overrides(&self, conf: &mut AudioSyncConfig) {
+        if let Command::Link {
+            branch: Some(branch),
+        } = self
+        {
+            conf.git_branch = branch.clone();
+        }
+    }
+    pub fn apply_transcription_overrides(&self, conf: &mut TranscriptionConfig) {
+        match self {
+            Command::Transcribe {
+                branches,
+                linking,
+                filter,
+                force,
+            } => {
+                branches.apply(conf);
+                linking.apply(conf);
+                conf.link_filter = filter.clone();
+                conf.force = *force;
+            }
+            Command::Relink {
+                branches, linking, ..
+            } => {
+                branches.apply(conf);
+                linking.apply(conf);
+            }
+            Command::Status { branches, filter } => {
+                branches.apply(conf);
+                conf.link_filter = filter.clone();
+            }
+            Command::Unlink { branches, .. } => branches.apply(conf),
+            Command::Link { .. } | Command::Config { .. } | Command::Login => {}
+        }
+    }
+}
+
+#[test]
+fn test_parse_args() {
+    let args = Args::parse_from([
+        "korosensei",
+        "transcribe",
+        "--dry-run",
+        "--target-branch",
+        "notes",
+        "--time-window",
+        "45",
+        "--filter",
+        "^math/",
+        "--force",
+    ]);
+    assert!(args.dry_run);
+    let Command::Transcribe {
+        branches,
+        linking,
+        filter,
+        force,
+    } = &args.command
+    else {
+        panic!("Expected transcribe command, got {:?}", args.command);
+    };
+    assert_eq!(branches.target_branch, Some("notes".to_owned()));
+    assert_eq!(branches.audio_branch, None);
+    assert_eq!(linking.time_window, Some(45));
+    assert!(force);
+    assert!(filter
+        .as_ref()
+        .is_some_and(|x| x.is_match("math/a.mp3.link")));
+
+    let args = Args::parse_from(["korosensei", "config", "check"]);
+    assert!(matches!(
+        args.command,
+        Command::Config {
+            command: ConfigCommand::Check
+        }
+    ));
+    assert_eq!(args.command.required_config(), (true, true));
+
+    let args = Args::parse_from(["korosensei", "relink", "--all"]);
+    assert!(matches!(
+        args.command,
+        Command::Relink {
+            transcript: None,
+            all: true,
+            ..
+        }
+    ));
+    assert!(Args::try_parse_from(["korosensei", "relink"]).is_err());
+    assert!(Args::try_parse_from(["korosensei", "relink", "a.mp3", "--all"]).is_err());
 }
@@ -1,10 +1,10 @@
 use std::{path::PathBuf, str::FromStr};
 
 use chrono::Duration;
-use color_eyre::eyre::Context;
+use color_eyre::eyre::{Context, OptionExt as _};
 use reqwest::{header::HeaderValue, Url};
 
-use super::credentials::OneDriveCredentialsResponse;
+use super::{config_file::Variables, credentials::OneDriveCredentialsResponse};
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -14,29 +14,41 @@ pub struct Config {
     pub git_directory: PathBuf,
     pub timezone: chrono_tz::Tz,
     pub git_exec: PathBuf,
+    /// don't write/commit anything or call paid apis, only report the changes
+    pub dry_run: bool,
 }
 impl Config {
-    pub fn from_environment(audio_sync: bool, transcription: bool) -> color_eyre::Result<Config> {
+    pub fn from_environment(
+        vars: &Variables,
+        audio_sync: bool,
+        transcription: bool,
+    ) -> color_eyre::Result<Config> {
         Ok(Config {
-            credentials: CredentialConfig::from_environment()?,
+            credentials: CredentialConfig::from_environment(vars)?,
             audio_sync: if audio_sync {
-                Some(AudioSyncConfig::from_environment()?)
+                Some(AudioSyncConfig::from_environment(vars)?)
             } else {
                 None
             },
             git_directory: PathBuf::from_str(
-                &dotenv::var("GIT_DIRECTORY").wrap_err("Expected GIT_DIRECTORY to be set")?,
+                &vars
+                    .var("GIT_DIRECTORY")
+                    .wrap_err("Expected GIT_DIRECTORY to be set")?,
             )?,
-            timezone: dotenv::var("TIMEZONE")
+            timezone: vars
+                .var("TIMEZONE")
                 .wrap_err("Expected TIMEZONE to be set")?
-                .parse()?,
+                .parse::<chrono_tz::Tz>()
+                .map_err(|x| color_eyre::eyre::eyre!(x))
+                .wrap_err(format!("Failed to parse {}", vars.name("TIMEZONE")))?,
             transcription: if transcription {
-                Some(TranscriptionConfig::from_environment()?)
+                Some(TranscriptionConfig::from_environment(vars)?)
             } else {
                 None
             },
-            git_exec: PathBuf::from_str(&dotenv::var("GITPATH").unwrap_or("".to_owned()))
+            git_exec: PathBuf::from_str(&vars.var("GITPATH").unwrap_or("".to_owned()))
                 .unwrap_or(PathBuf::new()),
+            dry_run: false,
         })
     }
 }
@@ -48,96 +60,942 @@ pub struct TranscriptionConfig {
     pub git_source_branch: String,
     pub git_target_branch: String,
     pub git_source_path: PathBuf,
+    /// assumed length of recordings, whose duration is unknown
     pub time_window: Duration, // past n minutes
+    /// notes changed this long before the recording started are linked as well
+    pub padding_before: Duration,
+    /// notes changed this long after the recording ended are linked as well
+    pub padding_after: Duration,
+    /// which time of the blamed commits decides if a note has been changed during the recording
+    pub blame_time: BlameTime,
     pub include_parent: bool,
-    pub deepgram_key: String,
+    pub provider: TranscriptionProvider,
+    pub deepgram_key: Option<String>,
+    /// only set when using the local provider
+    pub local: Option<LocalTranscriptionConfig>,
+    /// relative to the repository root; lives in the target branch
+    pub state_file: PathBuf,
+    /// used to estimate the cost of a transcription in the state file
+    pub cost_per_minute: Option<f64>,
+    /// only .link files whose path (relative to the audio dir) matches are processed; set from the cli
+    pub link_filter: Option<regex::Regex>,
+    /// matching links are transcribed again, even if their recording hasn't changed; set from the cli
+    pub force: bool,
+    /// transcripts of deleted recordings & the links to them inside the notes are marked as orphaned
+    pub mark_orphaned: bool,
 }
 impl TranscriptionConfig {
-    pub fn from_environment() -> color_eyre::Result<TranscriptionConfig> {
+    pub fn from_environment(vars: &Variables) -> color_eyre::Result<TranscriptionConfig> {
+        let provider: TranscriptionProvider = vars
+            .var("TRANSCRIPTION_PROVIDER")
+            .unwrap_or("deepgram".to_owned())
+            .parse()
+            .wrap_err(format!(
+                "Failed to parse {}",
+                vars.name("TRANSCRIPTION_PROVIDER")
+            ))?;
         Ok(TranscriptionConfig {
             transcription_script_search_path: PathBuf::from_str(
-                &dotenv::var("TRANSCRIPTION_SCRIPT_SEARCH_PATH")
+                &vars
+                    .var("TRANSCRIPTION_SCRIPT_SEARCH_PATH")
                     .wrap_err("Expected TRANSCRIPTION_SCRIPT_SEARCH_PATH to be set")?,
             )?,
-            git_source_branch: dotenv::var("TRANSCRIPTION_AUDIO_BRANCH")
+            git_source_branch: vars
+                .var("TRANSCRIPTION_AUDIO_BRANCH")
                 .wrap_err("Expected TRANSCRIPTION_AUDIO_BRANCH to be set")?,
-            git_target_branch: dotenv::var("TRANSCRIPTION_GIT_BRANCH")
+            git_target_branch: vars
+                .var("TRANSCRIPTION_GIT_BRANCH")
                 .wrap_err("Expected TRANSCRIPTION_GIT_BRANCH to be set")?,
             git_source_path: PathBuf::from_str(
-                &dotenv::var("TRANSCRIPTION_AUDIO_SOURCE_DIR")
+                &vars
+                    .var("TRANSCRIPTION_AUDIO_SOURCE_DIR")
                     .wrap_err("Expected TRANSCRIPTION_AUDIO_SOURCE_DIR to be set")?,
             )?,
             transcription_target_path: PathBuf::from_str(
-                &dotenv::var("TRANSCRIPTION_TARGET_PATH")
+                &vars
+                    .var("TRANSCRIPTION_TARGET_PATH")
                     .wrap_err("Expected TRANSCRIPTION_TARGET_PATH to be set")?,
             )?,
             time_window: chrono::Duration::minutes(
-                dotenv::var("TRANSCRIPTION_TIME_WINDOW")
+                vars.var("TRANSCRIPTION_TIME_WINDOW")
                     .unwrap_or("100".to_owned())
                     .parse::<i64>()
-                    .wrap_err("Failed to parse TRANSCRIPTION_TIME_WINDOW")?,
+                    .wrap_err(format!(
+                        "Failed to parse {}",
+                        vars.name("TRANSCRIPTION_TIME_WINDOW")
+                    ))?,
             ),
+            padding_before: chrono::Duration::minutes(
+                vars.var("TRANSCRIPTION_PADDING_BEFORE")
+                    .unwrap_or("15".to_owned())
+                    .parse::<i64>()
+                    .wrap_err(format!(
+                        "Failed to parse {}",
+                        vars.name("TRANSCRIPTION_PADDING_BEFORE")
+                    ))?,
+            ),
+            padding_after: chrono::Duration::minutes(
+                vars.var("TRANSCRIPTION_PADDING_AFTER")
+                    .unwrap_or("30".to_owned())
+                    .parse::<i64>()
+                    .wrap_err(format!(
+                        "Failed to parse {}",
+                        vars.name("TRANSCRIPTION_PADDING_AFTER")
+                    ))?,
+            ),
+            blame_time: vars
+                .var("TRANSCRIPTION_BLAME_TIME")
+                .unwrap_or("committer".to_owned())
+                .parse()
+                .wrap_err(format!(
+                    "Failed to parse {}",
+                    vars.name("TRANSCRIPTION_BLAME_TIME")
+                ))?,
             include_parent: vec!["y".to_owned(), "yes".to_owned(), "1".to_owned()].contains(
-                &dotenv::var("TRANSCRIPTION_AUDIO_SOURCE_DIR").unwrap_or("no".to_owned()),
+                &vars
+                    .var("TRANSCRIPTION_INCLUDE_PARENT")
+                    .unwrap_or("no".to_owned()),
+            ),
+            provider,
+            deepgram_key: vars.var("TRANSCRIPTION_DEEPGRAM_KEY").ok(),
+            local: if provider == TranscriptionProvider::Local {
+                Some(LocalTranscriptionConfig::from_environment(vars)?)
+            } else {
+                None
+            },
+            state_file: PathBuf::from_str(
+                &vars
+                    .var("TRANSCRIPTION_STATE_FILE")
+                    .unwrap_or("/.korosensei/state.json".to_owned()),
+            )?,
+            cost_per_minute: match vars.var("TRANSCRIPTION_COST_PER_MINUTE") {
+                Ok(x) => Some(x.parse::<f64>().wrap_err(format!(
+                    "Failed to parse {}",
+                    vars.name("TRANSCRIPTION_COST_PER_MINUTE")
+                ))?),
+                Err(_) => None,
+            },
+            link_filter: None,
+            force: false,
+            mark_orphaned: vec!["y".to_owned(), "yes".to_owned(), "1".to_owned()].contains(
+                &vars
+                    .var("TRANSCRIPTION_MARK_ORPHANED")
+                    .unwrap_or("no".to_owned()),
+            ),
+        })
+    }
+}
+
+/// The service used to turn recordings into transcripts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptionProvider {
+    Deepgram,
+    /// a locally installed whisper.cpp-style executable
+    Local,
+    /// returns a fixed transcript without touching the audio; only available in tests
+    #[cfg(test)]
+    Mock,
+}
+impl FromStr for TranscriptionProvider {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "deepgram" => Ok(TranscriptionProvider::Deepgram),
+            "local" => Ok(TranscriptionProvider::Local),
+            #[cfg(test)]
+            "mock" => Ok(TranscriptionProvider::Mock),
+            x => Err(color_eyre::eyre::eyre!(
+                "Unknown transcription provider '{}'; expected one of: deepgram, local",
+                x
+            )),
+        }
+    }
+}
+
+/// The time of a commit used to correlate the changed lines with a recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlameTime {
+    /// when the change was made; kept when commits are rebased or synced later
+    Author,
+    /// when the change was committed
+    Committer,
+}
+impl FromStr for BlameTime {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "author" => Ok(BlameTime::Author),
+            "committer" => Ok(BlameTime::Committer),
+            x => Err(color_eyre::eyre::eyre!(
+                "Unknown blame time '{}'; expected one of: author, committer",
+                x
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LocalTranscriptionConfig {
+    /// name or path of the executable; resolved to an absolute path by the prerequisits check
+    pub executable: PathBuf,
+    pub model: PathBuf,
+    /// 'auto' lets the executable detect the language
+    pub language: String,
+    pub output_format: LocalOutputFormat,
+    /// passes '-tdrz' to detect speaker turns (requires a tinydiarize model)
+    pub diarize: bool,
+}
+impl LocalTranscriptionConfig {
+    pub fn from_environment(vars: &Variables) -> color_eyre::Result<LocalTranscriptionConfig> {
+        Ok(LocalTranscriptionConfig {
+            executable: PathBuf::from_str(
+                &vars
+                    .var("TRANSCRIPTION_LOCAL_EXECUTABLE")
+                    .unwrap_or("whisper-cli".to_owned()),
+            )?,
+            model: PathBuf::from_str(
+                &vars
+                    .var("TRANSCRIPTION_LOCAL_MODEL")
+                    .wrap_err("Expected TRANSCRIPTION_LOCAL_MODEL to be set")?,
+            )?,
+            language: vars
+                .var("TRANSCRIPTION_LOCAL_LANGUAGE")
+                .unwrap_or("auto".to_owned()),
+            output_format: vars
+                .var("TRANSCRIPTION_LOCAL_OUTPUT_FORMAT")
+                .unwrap_or("json".to_owned())
+                .parse()
+                .wrap_err(format!(
+                    "Failed to parse {}",
+                    vars.name("TRANSCRIPTION_LOCAL_OUTPUT_FORMAT")
+                ))?,
+            diarize: vec!["y".to_owned(), "yes".to_owned(), "1".to_owned()].contains(
+                &vars
+                    .var("TRANSCRIPTION_LOCAL_DIARIZE")
+                    .unwrap_or("no".to_owned()),
             ),
-            deepgram_key: dotenv::var("TRANSCRIPTION_DEEPGRAM_KEY")
-                .wrap_err("Expected TRANSCRIPTION_DEEPGRAM_KEY to be set")?,
         })
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalOutputFormat {
+    Json,
+    Srt,
+}
+impl FromStr for LocalOutputFormat {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "json" => Ok(LocalOutputFormat::Json),
+            "srt" => Ok(LocalOutputFormat::Srt),
+            x => Err(color_eyre::eyre::eyre!(
+                "Unknown output format '{}'; expected one of: json, srt",
+                x
+            )),
+        }
+    }
+}
 #[derive(Debug, Clone)]
 pub struct CredentialConfig {
-    pub onedrive_access_token_authorization: HeaderValue,
-    pub onedrive_access_token_url: Url,
+    pub provider: CredentialProvider,
+    /// only set when using the token_url provider
+    pub token_url: Option<TokenUrlConfig>,
+    /// only set when using the device_code provider
+    pub oauth: Option<OAuthConfig>,
+    /// defaults to https://graph.microsoft.com/v1.0
+    pub graph_api_base_url: Option<Url>,
+    /// only set if GDRIVE_REFRESH_TOKEN is set
+    pub gdrive: Option<RefreshTokenConfig>,
+    /// only set if DROPBOX_REFRESH_TOKEN is set
+    pub dropbox: Option<RefreshTokenConfig>,
+    /// only set if WEBDAV_URL is set
+    pub webdav: Option<WebDavConfig>,
 }
 
 impl CredentialConfig {
-    pub fn from_environment() -> color_eyre::Result<CredentialConfig> {
+    pub fn from_environment(vars: &Variables) -> color_eyre::Result<CredentialConfig> {
+        let provider: CredentialProvider = vars
+            .var("ONEDRIVE_CREDENTIAL_PROVIDER")
+            .unwrap_or("token_url".to_owned())
+            .parse()
+            .wrap_err(format!(
+                "Failed to parse {}",
+                vars.name("ONEDRIVE_CREDENTIAL_PROVIDER")
+            ))?;
         Ok(CredentialConfig {
+            provider,
+            token_url: if provider == CredentialProvider::TokenUrl {
+                Some(TokenUrlConfig::from_environment(vars)?)
+            } else {
+                None
+            },
+            oauth: if provider == CredentialProvider::DeviceCode {
+                Some(OAuthConfig::from_environment(vars)?)
+            } else {
+                None
+            },
+            graph_api_base_url: match vars.var("GRAPH_API_BASE_URL") {
+                Ok(x) => Some(Url::parse(&x).wrap_err(format!(
+                    "Failed to parse {}",
+                    vars.name("GRAPH_API_BASE_URL")
+                ))?),
+                Err(_) => None,
+            },
+            gdrive: RefreshTokenConfig::from_environment(
+                vars,
+                "GDRIVE",
+                "https://oauth2.googleapis.com/token",
+                "https://www.googleapis.com",
+            )?,
+            dropbox: RefreshTokenConfig::from_environment(
+                vars,
+                "DROPBOX",
+                "https://api.dropboxapi.com/oauth2/token",
+                "https://api.dropboxapi.com",
+            )?,
+            webdav: WebDavConfig::from_environment(vars)?,
+        })
+    }
+}
+
+/// How the access token for the graph api is obtained
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialProvider {
+    /// a self hosted http server hands out the token
+    TokenUrl,
+    /// the Microsoft identity platform; logged in once with a device code, then refreshed
+    DeviceCode,
+}
+impl FromStr for CredentialProvider {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "token_url" => Ok(CredentialProvider::TokenUrl),
+            "device_code" => Ok(CredentialProvider::DeviceCode),
+            x => Err(color_eyre::eyre::eyre!(
+                "Unknown credential provider '{}'; expected one of: token_url, device_code",
+                x
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenUrlConfig {
+    pub onedrive_access_token_authorization: HeaderValue,
+    pub onedrive_access_token_url: Url,
+}
+impl TokenUrlConfig {
+    pub fn from_environment(vars: &Variables) -> color_eyre::Result<TokenUrlConfig> {
+        Ok(TokenUrlConfig {
             onedrive_access_token_url: Url::parse(
-                &dotenv::var("ONEDRIVE_ACCESS_TOKEN_URL")
+                &vars
+                    .var("ONEDRIVE_ACCESS_TOKEN_URL")
                     .wrap_err("Expected ONEDRIVE_ACCESS_TOKEN_URL to be set")?,
-            )?,
+            )
+            .wrap_err(format!(
+                "Failed to parse {}",
+                vars.name("ONEDRIVE_ACCESS_TOKEN_URL")
+            ))?,
             onedrive_access_token_authorization: HeaderValue::from_str(
-                &dotenv::var("ONEDRIVE_ACCESS_TOKEN_AUTHORIZATION")
+                &vars
+                    .var("ONEDRIVE_ACCESS_TOKEN_AUTHORIZATION")
                     .wrap_err("Expected ONEDRIVE_ACCESS_TOKEN_AUTHORIZATION to be set")?,
-            )?,
+            )
+            .wrap_err(format!(
+                "Failed to parse {}",
+                vars.name("ONEDRIVE_ACCESS_TOKEN_AUTHORIZATION")
+            ))?,
         })
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct AudioSyncConfig {
-    // OneDrive
-    pub onedrive_source_folder: String,
+pub struct OAuthConfig {
+    /// id of the app registration (public client with the device code flow enabled)
+    pub client_id: String,
+    /// 'common', 'consumers', 'organizations' or a tenant id
+    pub tenant: String,
+    /// space separated; 'offline_access' is needed to get a refresh token
+    pub scopes: String,
+    /// where the refresh token is stored between runs
+    pub token_cache: PathBuf,
+    /// defaults to https://login.microsoftonline.com
+    pub authority_url: Option<Url>,
+}
+impl OAuthConfig {
+    pub fn from_environment(vars: &Variables) -> color_eyre::Result<OAuthConfig> {
+        Ok(OAuthConfig {
+            client_id: vars
+                .var("ONEDRIVE_OAUTH_CLIENT_ID")
+                .wrap_err("Expected ONEDRIVE_OAUTH_CLIENT_ID to be set")?,
+            tenant: vars
+                .var("ONEDRIVE_OAUTH_TENANT")
+                .unwrap_or("common".to_owned()),
+            scopes: vars
+                .var("ONEDRIVE_OAUTH_SCOPES")
+                .unwrap_or("offline_access Files.Read".to_owned()),
+            token_cache: match vars.var("ONEDRIVE_OAUTH_TOKEN_CACHE") {
+                Ok(x) => PathBuf::from_str(&x)?,
+                Err(_) => PathBuf::from_str(
+                    &std::env::var("HOME")
+                        .wrap_err("Expected ONEDRIVE_OAUTH_TOKEN_CACHE or HOME to be set")?,
+                )?
+                .join(".korosensei")
+                .join("onedrive_token.json"),
+            },
+            authority_url: match vars.var("ONEDRIVE_OAUTH_AUTHORITY_URL") {
+                Ok(x) => Some(Url::parse(&x).wrap_err(format!(
+                    "Failed to parse {}",
+                    vars.name("ONEDRIVE_OAUTH_AUTHORITY_URL")
+                ))?),
+                Err(_) => None,
+            },
+        })
+    }
+}
+
+/// An app, whose refresh token is exchanged for access tokens (Google Drive & Dropbox)
+#[derive(Debug, Clone)]
+pub struct RefreshTokenConfig {
+    pub client_id: String,
+    /// not needed for Dropbox apps using PKCE
+    pub client_secret: Option<String>,
+    pub refresh_token: String,
+    pub token_url: Url,
+    pub api_base_url: Url,
+}
+impl RefreshTokenConfig {
+    /// reads <PREFIX>_CLIENT_ID, <PREFIX>_CLIENT_SECRET, <PREFIX>_REFRESH_TOKEN, <PREFIX>_TOKEN_URL & <PREFIX>_API_BASE_URL
+    /// None if <PREFIX>_REFRESH_TOKEN isn't set
+    pub fn from_environment(
+        vars: &Variables,
+        prefix: &str,
+        token_url: &str,
+        api_base_url: &str,
+    ) -> color_eyre::Result<Option<RefreshTokenConfig>> {
+        let var = |x: &str| format!("{}_{}", prefix, x);
+        let url = |key: &str, default: &str| -> color_eyre::Result<Url> {
+            Url::parse(&vars.var(&var(key)).unwrap_or(default.to_owned()))
+                .wrap_err(format!("Failed to parse {}", vars.name(&var(key))))
+        };
+        let Ok(refresh_token) = vars.var(&var("REFRESH_TOKEN")) else {
+            return Ok(None);
+        };
+        Ok(Some(RefreshTokenConfig {
+            client_id: vars
+                .var(&var("CLIENT_ID"))
+                .wrap_err(format!("Expected {} to be set", var("CLIENT_ID")))?,
+            client_secret: vars.var(&var("CLIENT_SECRET")).ok(),
+            refresh_token,
+            token_url: url("TOKEN_URL", token_url)?,
+            api_base_url: url("API_BASE_URL", api_base_url)?,
+        }))
+    }
+}
 
+/// A WebDAV server (e.g. Nextcloud); links store paths relative to the url
+#[derive(Debug, Clone)]
+pub struct WebDavConfig {
+    /// e.g. https://cloud.example.com/remote.php/dav/files/<user>/
+    pub url: Url,
+    pub username: Option<String>,
+    /// for Nextcloud an app password
+    pub password: Option<String>,
+}
+impl WebDavConfig {
+    /// None if WEBDAV_URL isn't set
+    pub fn from_environment(vars: &Variables) -> color_eyre::Result<Option<WebDavConfig>> {
+        let Ok(url) = vars.var("WEBDAV_URL") else {
+            return Ok(None);
+        };
+        Ok(Some(WebDavConfig {
+            url: Url::parse(&url)
+                .wrap_err(format!("Failed to parse {}", vars.name("WEBDAV_URL")))?,
+            username: vars.var("WEBDAV_USERNAME").ok(),
+            password: vars.var("WEBDAV_PASSWORD").ok(),
+        }))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioSyncConfig {
     // Git
     pub git_branch: String,
-    pub git_destination_folder: PathBuf,
 
-    // General Settings
-    pub permitted_file_types: Vec<String>,
+    /// cloud storage folders & where their links are stored
+    pub mappings: Vec<AudioMapping>,
+    /// links of deleted recordings are moved here (keeping their path relative to the target dir);
+    /// should be outside of TRANSCRIPTION_AUDIO_SOURCE_DIR
+    pub archive_dir: PathBuf,
 }
 
 impl AudioSyncConfig {
-    pub fn from_environment() -> color_eyre::Result<AudioSyncConfig> {
-        return Ok(AudioSyncConfig {
-            onedrive_source_folder: dotenv::var("ONEDRIVE_SOURCE_DIR")
-                .wrap_err("Expected ONEDRIVE_SOURCE_DIR to be set")?,
+    /// The mappings are read from `[[audio_mappings]]` in the config file;
+    /// otherwise a single mapping is built from ONEDRIVE_SOURCE_DIR (or GDRIVE_FOLDER_ID, DROPBOX_SOURCE_DIR or WEBDAV_SOURCE_DIR),
+    /// AUDIO_TARGET_DIR & PERMITTED_FILE_TYPES
+    pub fn from_environment(vars: &Variables) -> color_eyre::Result<AudioSyncConfig> {
+        let mappings = match vars.tables("AUDIO_MAPPINGS") {
+            Some((tables, name)) => {
+                let mut mappings = Vec::new();
+                for (idx, table) in tables.iter().enumerate() {
+                    mappings.push(AudioMapping::from_table(
+                        table,
+                        &format!("{}[{}]", name, idx),
+                        vars,
+                    )?);
+                }
+                if mappings.is_empty() {
+                    return Err(color_eyre::eyre::eyre!(
+                        "Expected '{}' to contain at least one mapping",
+                        name
+                    ));
+                }
+                mappings
+            }
+            None => vec![AudioMapping {
+                storage: StorageProvider::OneDrive,
+                source_folder: String::new(),
 
-            git_branch: dotenv::var("AUDIO_GIT_BRANCH")
+                git_destination_folder: PathBuf::from_str(
+                    &vars
+                        .var("AUDIO_TARGET_DIR")
+                        .wrap_err("Expected AUDIO_TARGET_DIR to be set")?,
+                )?,
+
+                permitted_file_types: parse_file_types(
+                    &vars
+                        .var("PERMITTED_FILE_TYPES")
+                        .wrap_err("Expected PERMITTED_FILE_TYPES to be set")?,
+                ),
+                filename_filter: None,
+                recursion_depth: parse_recursion_depth(vars)?,
+                folder_layout: FolderLayout::from_environment(vars)?,
+            }
+            .with_source(
+                StorageProvider::ALL
+                    .iter()
+                    .filter_map(|x| {
+                        vars.var(x.source_variable())
+                            .ok()
+                            .map(|folder| (*x, folder))
+                    })
+                    .collect(),
+                "ONEDRIVE_SOURCE_DIR, GDRIVE_FOLDER_ID, DROPBOX_SOURCE_DIR or WEBDAV_SOURCE_DIR",
+            )?],
+        };
+        return Ok(AudioSyncConfig {
+            git_branch: vars
+                .var("AUDIO_GIT_BRANCH")
                 .wrap_err("Expected AUDIO_GIT_BRANCH to be set")?,
+            mappings,
+            archive_dir: PathBuf::from_str(
+                &vars
+                    .var("AUDIO_ARCHIVE_DIR")
+                    .unwrap_or("/audio_archive/".to_owned()),
+            )?,
+        });
+    }
+}
 
+/// A cloud storage folder, whose recordings are linked into a directory of the audio branch
+#[derive(Debug, Clone)]
+pub struct AudioMapping {
+    pub storage: StorageProvider,
+    /// path of the folder; the folder id for Google Drive
+    pub source_folder: String,
+    pub git_destination_folder: PathBuf,
+    pub permitted_file_types: Vec<String>,
+    /// only files whose name matches are linked
+    pub filename_filter: Option<regex::Regex>,
+    /// how many levels of subfolders are searched for recordings (0: only the folder itself)
+    pub recursion_depth: usize,
+    pub folder_layout: FolderLayout,
+}
+impl AudioMapping {
+    /// keys: onedrive_source_dir | gdrive_folder_id | dropbox_source_dir | webdav_source_dir, target_dir, permitted_file_types (defaults to PERMITTED_FILE_TYPES), filename_filter,
+    /// recursion_depth, folder_layout & flatten_pattern (default to the ONEDRIVE_RECURSION_DEPTH, AUDIO_FOLDER_LAYOUT & AUDIO_FLATTEN_PATTERN)
+    fn from_table(
+        table: &toml::Table,
+        name: &str,
+        vars: &Variables,
+    ) -> color_eyre::Result<AudioMapping> {
+        let get = |key: &str| -> color_eyre::Result<Option<String>> {
+            match table.get(key) {
+                None => Ok(None),
+                Some(toml::Value::String(x)) => Ok(Some(x.clone())),
+                Some(toml::Value::Array(x)) if key == "permitted_file_types" => Ok(Some(
+                    x.iter()
+                        .map(|x| x.as_str().map(|x| x.to_owned()))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_eyre(format!(
+                            "Expected '{}.{}' to be a list of strings",
+                            name, key
+                        ))?
+                        .join(","),
+                )),
+                Some(_) => Err(color_eyre::eyre::eyre!(
+                    "Expected '{}.{}' to be a string",
+                    name,
+                    key
+                )),
+            }
+        };
+        if let Some(key) = table.keys().find(|x| {
+            ![
+                "onedrive_source_dir",
+                "gdrive_folder_id",
+                "dropbox_source_dir",
+                "webdav_source_dir",
+                "target_dir",
+                "permitted_file_types",
+                "filename_filter",
+                "recursion_depth",
+                "folder_layout",
+                "flatten_pattern",
+            ]
+            .contains(&x.as_str())
+        }) {
+            return Err(color_eyre::eyre::eyre!("Unknown key '{}.{}'", name, key));
+        }
+
+        let mut sources = Vec::new();
+        for provider in StorageProvider::ALL {
+            if let Some(x) = get(&provider.source_variable().to_lowercase())? {
+                sources.push((*provider, x));
+            }
+        }
+
+        AudioMapping {
+            storage: StorageProvider::OneDrive,
+            source_folder: String::new(),
             git_destination_folder: PathBuf::from_str(
-                &dotenv::var("AUDIO_TARGET_DIR").wrap_err("Expected AUDIO_TARGET_DIR to be set")?,
+                &get("target_dir")?
+                    .ok_or_eyre(format!("Expected '{}.target_dir' to be set", name))?,
             )?,
+            permitted_file_types: parse_file_types(&match get("permitted_file_types")? {
+                Some(x) => x,
+                None => vars.var("PERMITTED_FILE_TYPES").wrap_err(format!(
+                    "Expected '{}.permitted_file_types' or PERMITTED_FILE_TYPES to be set",
+                    name
+                ))?,
+            }),
+            filename_filter: match get("filename_filter")? {
+                Some(x) => Some(
+                    regex::Regex::new(&x)
+                        .wrap_err(format!("Failed to parse '{}.filename_filter'", name))?,
+                ),
+                None => None,
+            },
+            recursion_depth: match table.get("recursion_depth") {
+                None => parse_recursion_depth(vars)?,
+                Some(toml::Value::Integer(x)) => usize::try_from(*x).wrap_err(format!(
+                    "Expected '{}.recursion_depth' to be positive",
+                    name
+                ))?,
+                Some(_) => {
+                    return Err(color_eyre::eyre::eyre!(
+                        "Expected '{}.recursion_depth' to be an integer",
+                        name
+                    ))
+                }
+            },
+            folder_layout: match get("folder_layout")? {
+                None if table.contains_key("flatten_pattern") => FolderLayout::parse(
+                    "flatten",
+                    get("flatten_pattern")?,
+                    &format!("{}.folder_layout", name),
+                )?,
+                None => FolderLayout::from_environment(vars)?,
+                Some(x) => FolderLayout::parse(
+                    &x,
+                    match get("flatten_pattern")? {
+                        Some(x) => Some(x),
+                        None => vars.var("AUDIO_FLATTEN_PATTERN").ok(),
+                    },
+                    &format!("{}.folder_layout", name),
+                )?,
+            },
+        }
+        .with_source(
+            sources,
+            &format!(
+                "'{0}.onedrive_source_dir', '{0}.gdrive_folder_id', '{0}.dropbox_source_dir' or '{0}.webdav_source_dir'",
+                name
+            ),
+        )
+    }
+    /// sets the storage & folder; exactly one source has to be given
+    fn with_source(
+        self,
+        sources: Vec<(StorageProvider, String)>,
+        names: &str,
+    ) -> color_eyre::Result<AudioMapping> {
+        match sources.as_slice() {
+            [(storage, folder)] => Ok(AudioMapping {
+                storage: *storage,
+                source_folder: folder.clone(),
+                ..self
+            }),
+            [] => Err(color_eyre::eyre::eyre!("Expected {} to be set", names)),
+            _ => Err(color_eyre::eyre::eyre!(
+                "Expected only one of {} to be set",
+                names
+            )),
+        }
+    }
+    /// identifies the mapping inside the links it writes, e.g. 'onedrive:/school'
+    pub fn source(&self) -> String {
+        format!("{}:{}", self.storage.scheme(), self.source_folder)
+    }
+    /// whether a file in the OneDrive folder should be linked
+    pub fn is_permitted(&self, file_name: &str) -> bool {
+        let extension = file_name.split(".").last().unwrap_or_default();
+        self.permitted_file_types.contains(&extension.to_owned())
+            && self
+                .filename_filter
+                .as_ref()
+                .map_or(true, |x| x.is_match(file_name))
+    }
+    /// path of the link (relative to the target dir) for a recording in a subfolder ('' for the folder itself)
+    pub fn link_path(&self, folder: &str, file_name: &str) -> PathBuf {
+        let folder = folder.trim_matches('/');
+        let name = match &self.folder_layout {
+            _ if folder.is_empty() => file_name.to_owned(),
+            FolderLayout::Mirror => format!("{}/{}", folder, file_name),
+            FolderLayout::Flatten(pattern) => pattern
+                .replace("{folder}", &folder.replace("/", "_"))
+                .replace("{name}", file_name),
+        };
+        PathBuf::from(format!("{}.link", name))
+    }
+}
 
-            permitted_file_types: dotenv::var("PERMITTED_FILE_TYPES")
-                .wrap_err("Expected PERMITTED_FILE_TYPES to be set")?
-                .replace(" ", "")
-                .split(",")
-                .map(|x| x.strip_prefix(".").unwrap_or(x).to_owned())
-                .collect(),
-        });
+/// The cloud storage a mapping links recordings from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageProvider {
+    OneDrive,
+    GoogleDrive,
+    Dropbox,
+    /// e.g. Nextcloud
+    WebDav,
+}
+impl StorageProvider {
+    pub const ALL: &'static [StorageProvider] = &[
+        StorageProvider::OneDrive,
+        StorageProvider::GoogleDrive,
+        StorageProvider::Dropbox,
+        StorageProvider::WebDav,
+    ];
+    /// scheme of the link files, e.g. gdrive:(timestamp):id
+    pub fn scheme(&self) -> &'static str {
+        match self {
+            StorageProvider::OneDrive => "onedrive",
+            StorageProvider::GoogleDrive => "gdrive",
+            StorageProvider::Dropbox => "dropbox",
+            StorageProvider::WebDav => "webdav",
+        }
+    }
+    /// the variable which selects the source folder of a mapping (lowercase inside `[[audio_mappings]]`)
+    fn source_variable(&self) -> &'static str {
+        match self {
+            StorageProvider::OneDrive => "ONEDRIVE_SOURCE_DIR",
+            StorageProvider::GoogleDrive => "GDRIVE_FOLDER_ID",
+            StorageProvider::Dropbox => "DROPBOX_SOURCE_DIR",
+            StorageProvider::WebDav => "WEBDAV_SOURCE_DIR",
+        }
+    }
+}
+
+/// Where the links of recordings in subfolders are stored
+#[derive(Debug, Clone, PartialEq)]
+pub enum FolderLayout {
+    /// the subfolders are recreated inside the target dir
+    Mirror,
+    /// all links are stored in the target dir, named by the pattern
+    /// ({folder}: subfolders joined by '_', {name}: file name)
+    Flatten(String),
+}
+impl FolderLayout {
+    /// reads AUDIO_FOLDER_LAYOUT (mirror | flatten, default mirror) & AUDIO_FLATTEN_PATTERN (default '{folder}_{name}')
+    fn from_environment(vars: &Variables) -> color_eyre::Result<FolderLayout> {
+        FolderLayout::parse(
+            &vars
+                .var("AUDIO_FOLDER_LAYOUT")
+                .unwrap_or_else(|_| "mirror".to_owned()),
+            vars.var("AUDIO_FLATTEN_PATTERN").ok(),
+            &vars.name("AUDIO_FOLDER_LAYOUT"),
+        )
+    }
+    fn parse(
+        layout: &str,
+        pattern: Option<String>,
+        name: &str,
+    ) -> color_eyre::Result<FolderLayout> {
+        match layout.trim().to_lowercase().as_str() {
+            "mirror" => Ok(FolderLayout::Mirror),
+            "flatten" => {
+                let pattern = pattern.unwrap_or_else(|| "{folder}_{name}".to_owned());
+                if !pattern.contains("{name}") || pattern.contains("/") {
+                    return Err(color_eyre::eyre::eyre!(
+                        "Expected the flatten pattern of {} to contain '{{name}}' and no '/', got {:?}",
+                        name,
+                        pattern
+                    ));
+                }
+                Ok(FolderLayout::Flatten(pattern))
+            }
+            x => Err(color_eyre::eyre::eyre!(
+                "Expected {} to be 'mirror' or 'flatten', got {:?}",
+                name,
+                x
+            )),
+        }
     }
 }
+
+/// ONEDRIVE_RECURSION_DEPTH, defaults to 0 (no subfolders)
+fn parse_recursion_depth(vars: &Variables) -> color_eyre::Result<usize> {
+    match vars.var("ONEDRIVE_RECURSION_DEPTH") {
+        Ok(x) => x.trim().parse().wrap_err(format!(
+            "Failed to parse {}",
+            vars.name("ONEDRIVE_RECURSION_DEPTH")
+        )),
+        Err(_) => Ok(0),
+    }
+}
+
+/// '.mp3, wav' -> ['mp3', 'wav']
+fn parse_file_types(types: &str) -> Vec<String> {
+    types
+        .replace(" ", "")
+        .split(",")
+        .map(|x| x.strip_prefix(".").unwrap_or(x).to_owned())
+        .collect()
+}
+
+#[test]
+fn test_audio_mappings() {
+    let vars = Variables::parse(
+        r#"
+audio_git_branch = "audio"
+permitted_file_types = ".mp3, .wav"
+
+[[audio_mappings]]
+onedrive_source_dir = "/school/math/"
+target_dir = "/audio_files/math/"
+
+[[audio_mappings]]
+onedrive_source_dir = "/school/physics/"
+target_dir = "/audio_files/physics/"
+permitted_file_types = ["m4a"]
+filename_filter = "^lesson"
+"#,
+        std::path::Path::new("korosensei.toml"),
+        None,
+    )
+    .unwrap();
+    let conf = AudioSyncConfig::from_environment(&vars).unwrap();
+    assert_eq!(conf.git_branch, "audio");
+    assert_eq!(conf.mappings.len(), 2);
+    assert_eq!(conf.mappings[0].permitted_file_types, vec!["mp3", "wav"]);
+    assert!(conf.mappings[0].is_permitted("anything.wav"));
+    assert!(!conf.mappings[0].is_permitted("slides.pdf"));
+    assert_eq!(
+        conf.mappings[1].git_destination_folder,
+        PathBuf::from("/audio_files/physics/")
+    );
+    assert!(conf.mappings[1].is_permitted("lesson_3.m4a"));
+    assert!(!conf.mappings[1].is_permitted("break.m4a"));
+    assert!(!conf.mappings[1].is_permitted("lesson_3.mp3"));
+
+    let vars = Variables::parse(
+        "audio_git_branch = \"audio\"\n[[audio_mappings]]\nonedrive_source_dir = \"/a/\"\ntarget = \"/b/\"\n",
+        std::path::Path::new("korosensei.toml"),
+        None,
+    )
+    .unwrap();
+    let err = AudioSyncConfig::from_environment(&vars).unwrap_err();
+    assert!(
+        err.to_string().contains("'audio_mappings[0].target'"),
+        "{}",
+        err
+    );
+
+    let vars = Variables::parse(
+        r#"
+audio_git_branch = "audio"
+permitted_file_types = "mp3"
+onedrive_recursion_depth = 2
+
+[[audio_mappings]]
+onedrive_source_dir = "/school/math/"
+target_dir = "/audio_files/math/"
+
+[[audio_mappings]]
+onedrive_source_dir = "/school/physics/"
+target_dir = "/audio_files/physics/"
+recursion_depth = 1
+flatten_pattern = "{name}-{folder}"
+"#,
+        std::path::Path::new("korosensei.toml"),
+        None,
+    )
+    .unwrap();
+    let conf = AudioSyncConfig::from_environment(&vars).unwrap();
+    assert_eq!(conf.mappings[0].recursion_depth, 2);
+    assert_eq!(conf.mappings[0].folder_layout, FolderLayout::Mirror);
+    assert_eq!(
+        conf.mappings[0].link_path("week1/monday/", "a.mp3"),
+        PathBuf::from("week1/monday/a.mp3.link")
+    );
+    assert_eq!(conf.mappings[1].recursion_depth, 1);
+    assert_eq!(
+        conf.mappings[1].link_path("week1/monday", "a.mp3"),
+        PathBuf::from("a.mp3-week1_monday.link")
+    );
+    assert_eq!(
+        conf.mappings[1].link_path("", "a.mp3"),
+        PathBuf::from("a.mp3.link")
+    );
+
+    let vars = Variables::parse(
+        r#"
+audio_git_branch = "audio"
+permitted_file_types = "mp3"
+
+[[audio_mappings]]
+gdrive_folder_id = "1aBcD"
+target_dir = "/audio_files/gdrive/"
+
+[[audio_mappings]]
+dropbox_source_dir = "/Recordings/"
+onedrive_source_dir = "/school/"
+target_dir = "/audio_files/dropbox/"
+"#,
+        std::path::Path::new("korosensei.toml"),
+        None,
+    )
+    .unwrap();
+    let err = AudioSyncConfig::from_environment(&vars).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Expected only one of 'audio_mappings[1].onedrive_source_dir'"),
+        "{}",
+        err
+    );
+    let vars = Variables::parse(
+        "audio_git_branch = \"audio\"\npermitted_file_types = \"mp3\"\naudio_target_dir = \"/a/\"\ngdrive_folder_id = \"1aBcD\"\n",
+        std::path::Path::new("korosensei.toml"),
+        None,
+    )
+    .unwrap();
+    let conf = AudioSyncConfig::from_environment(&vars).unwrap();
+    assert_eq!(conf.mappings[0].storage, StorageProvider::GoogleDrive);
+    assert_eq!(conf.mappings[0].source_folder, "1aBcD");
+}
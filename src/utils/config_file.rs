@@ -0,0 +1,371 @@
+//! Support for `korosensei.toml`, which can set the same variables as the environment
+//! Keys are the lowercase variable names; nested tables are joined with '_'
+//! (`[transcription] audio_branch` sets TRANSCRIPTION_AUDIO_BRANCH) and lists are joined with ','
+//! Lists of tables (e.g. `[[audio_mappings]]`) can't be expressed as a variable and are kept as they are
+//! Named profiles (`[profiles.<name>]`) are layered over the rest of the file,
+//! environment variables (& .env) are layered over the file
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{eyre, Context as _};
+use itertools::Itertools as _;
+
+pub const CONFIG_FILE_NAME: &str = "korosensei.toml";
+
+/// every variable, which can be set in the environment or the config file
+pub const KNOWN_VARIABLES: &[&str] = &[
+    "GIT_DIRECTORY",
+    "TIMEZONE",
+    "GITPATH",
+    "ONEDRIVE_CREDENTIAL_PROVIDER",
+    "ONEDRIVE_ACCESS_TOKEN_URL",
+    "ONEDRIVE_ACCESS_TOKEN_AUTHORIZATION",
+    "ONEDRIVE_OAUTH_CLIENT_ID",
+    "ONEDRIVE_OAUTH_TENANT",
+    "ONEDRIVE_OAUTH_SCOPES",
+    "ONEDRIVE_OAUTH_TOKEN_CACHE",
+    "ONEDRIVE_OAUTH_AUTHORITY_URL",
+    "GRAPH_API_BASE_URL",
+    "GDRIVE_CLIENT_ID",
+    "GDRIVE_CLIENT_SECRET",
+    "GDRIVE_REFRESH_TOKEN",
+    "GDRIVE_TOKEN_URL",
+    "GDRIVE_API_BASE_URL",
+    "DROPBOX_CLIENT_ID",
+    "DROPBOX_CLIENT_SECRET",
+    "DROPBOX_REFRESH_TOKEN",
+    "DROPBOX_TOKEN_URL",
+    "DROPBOX_API_BASE_URL",
+    "WEBDAV_URL",
+    "WEBDAV_USERNAME",
+    "WEBDAV_PASSWORD",
+    "ONEDRIVE_SOURCE_DIR",
+    "GDRIVE_FOLDER_ID",
+    "DROPBOX_SOURCE_DIR",
+    "WEBDAV_SOURCE_DIR",
+    "ONEDRIVE_RECURSION_DEPTH",
+    "AUDIO_GIT_BRANCH",
+    "AUDIO_TARGET_DIR",
+    "AUDIO_FOLDER_LAYOUT",
+    "AUDIO_FLATTEN_PATTERN",
+    "AUDIO_ARCHIVE_DIR",
+    "PERMITTED_FILE_TYPES",
+    "TRANSCRIPTION_SCRIPT_SEARCH_PATH",
+    "TRANSCRIPTION_TIME_WINDOW",
+    "TRANSCRIPTION_PADDING_BEFORE",
+    "TRANSCRIPTION_PADDING_AFTER",
+    "TRANSCRIPTION_BLAME_TIME",
+    "TRANSCRIPTION_TARGET_PATH",
+    "TRANSCRIPTION_GIT_BRANCH",
+    "TRANSCRIPTION_AUDIO_BRANCH",
+    "TRANSCRIPTION_AUDIO_SOURCE_DIR",
+    "TRANSCRIPTION_INCLUDE_PARENT",
+    "TRANSCRIPTION_PROVIDER",
+    "TRANSCRIPTION_DEEPGRAM_KEY",
+    "TRANSCRIPTION_STATE_FILE",
+    "TRANSCRIPTION_COST_PER_MINUTE",
+    "TRANSCRIPTION_MARK_ORPHANED",
+    "TRANSCRIPTION_LOCAL_EXECUTABLE",
+    "TRANSCRIPTION_LOCAL_MODEL",
+    "TRANSCRIPTION_LOCAL_LANGUAGE",
+    "TRANSCRIPTION_LOCAL_OUTPUT_FORMAT",
+    "TRANSCRIPTION_LOCAL_DIARIZE",
+];
+
+/// every list of tables, which can be set in the config file (variable style)
+pub const KNOWN_TABLE_LISTS: &[&str] = &["AUDIO_MAPPINGS"];
+
+/// The variables the configuration is read from
+#[derive(Debug, Clone)]
+pub struct Variables {
+    /// variable -> (value, key inside the file)
+    file: HashMap<String, (String, String)>,
+    /// variable -> (tables, key inside the file)
+    tables: HashMap<String, (Vec<toml::Table>, String)>,
+    path: Option<PathBuf>,
+    profile: Option<String>,
+    /// whether the environment (& .env) is read
+    env: bool,
+}
+impl Variables {
+    /// Reads the environment only
+    pub fn from_environment() -> Variables {
+        Variables {
+            file: HashMap::new(),
+            tables: HashMap::new(),
+            path: None,
+            profile: None,
+            env: true,
+        }
+    }
+    /// Reads the given config file (which has to exist) or searches for
+    /// korosensei.toml in the current directory and in GIT_DIRECTORY
+    pub fn load(path: Option<&Path>, profile: Option<&str>) -> color_eyre::Result<Variables> {
+        let path = match path {
+            Some(x) => Some(x.to_path_buf()),
+            None => [
+                Some(PathBuf::from(CONFIG_FILE_NAME)),
+                dotenv::var("GIT_DIRECTORY")
+                    .ok()
+                    .map(|x| PathBuf::from(x).join(CONFIG_FILE_NAME)),
+            ]
+            .into_iter()
+            .flatten()
+            .find(|x| x.is_file()),
+        };
+        let Some(path) = path else {
+            if let Some(profile) = profile {
+                return Err(eyre!(
+                    "Profile '{}' selected, but no {} has been found",
+                    profile,
+                    CONFIG_FILE_NAME
+                ));
+            }
+            return Ok(Variables::from_environment());
+        };
+        log::info!("Using config file {:?}", path);
+        let content = std::fs::read_to_string(&path)
+            .wrap_err(format!("Failed to read config file {:?}", path))?;
+        let mut res = Variables::parse(&content, &path, profile)?;
+        res.env = true;
+        Ok(res)
+    }
+    /// Parses a config file without reading the environment
+    pub fn parse(
+        content: &str,
+        path: &Path,
+        profile: Option<&str>,
+    ) -> color_eyre::Result<Variables> {
+        let mut table: toml::Table =
+            toml::from_str(content).wrap_err(format!("Failed to parse config file {:?}", path))?;
+        let profiles = match table.remove("profiles") {
+            Some(toml::Value::Table(x)) => x,
+            Some(_) => {
+                return Err(eyre!(
+                    "Expected 'profiles' in {:?} to be a table of profiles",
+                    path
+                ))
+            }
+            None => toml::Table::new(),
+        };
+
+        let mut file = HashMap::new();
+        let mut tables = HashMap::new();
+        flatten(&table, "", "", path, &mut file, &mut tables)?;
+        if let Some(profile) = profile {
+            let profile_table = match profiles.get(profile) {
+                Some(toml::Value::Table(x)) => x,
+                Some(_) => {
+                    return Err(eyre!(
+                        "Expected 'profiles.{}' in {:?} to be a table",
+                        profile,
+                        path
+                    ))
+                }
+                None => {
+                    return Err(eyre!(
+                        "Unknown profile '{}' in {:?}; available: {}",
+                        profile,
+                        path,
+                        profiles.keys().join(", ")
+                    ))
+                }
+            };
+            flatten(
+                profile_table,
+                &format!("profiles.{}", profile),
+                "",
+                path,
+                &mut file,
+                &mut tables,
+            )?;
+        }
+
+        Ok(Variables {
+            file,
+            tables,
+            path: Some(path.to_path_buf()),
+            profile: profile.map(|x| x.to_owned()),
+            env: false,
+        })
+    }
+
+    /// the value of the variable; the environment takes precedence over the config file
+    pub fn var(&self, key: &str) -> color_eyre::Result<String> {
+        debug_assert!(KNOWN_VARIABLES.contains(&key), "Unknown variable {}", key);
+        if self.env {
+            if let Ok(x) = dotenv::var(key) {
+                return Ok(x);
+            }
+        }
+        match self.file.get(key) {
+            Some((value, _)) => Ok(value.clone()),
+            None => Err(eyre!("{} is not set", key)),
+        }
+    }
+    /// a list of tables (see `KNOWN_TABLE_LISTS`) & its key inside the file; only set by the config file
+    pub fn tables(&self, key: &str) -> Option<(&[toml::Table], &str)> {
+        debug_assert!(
+            KNOWN_TABLE_LISTS.contains(&key),
+            "Unknown table list {}",
+            key
+        );
+        self.tables
+            .get(key)
+            .map(|(tables, name)| (tables.as_slice(), name.as_str()))
+    }
+    /// names the variable & where it was set, to be used in error messages
+    pub fn name(&self, key: &str) -> String {
+        if self.env && dotenv::var(key).is_ok() {
+            return key.to_owned();
+        }
+        match (self.file.get(key), &self.path) {
+            (Some((_, file_key)), Some(path)) => format!("{} ('{}' in {:?})", key, file_key, path),
+            _ => key.to_owned(),
+        }
+    }
+    /// the config file in use
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+}
+
+fn flatten(
+    table: &toml::Table,
+    key_prefix: &str,
+    var_prefix: &str,
+    path: &Path,
+    res: &mut HashMap<String, (String, String)>,
+    tables: &mut HashMap<String, (Vec<toml::Table>, String)>,
+) -> color_eyre::Result<()> {
+    for (key, value) in table {
+        let full_key = if key_prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", key_prefix, key)
+        };
+        let var = if var_prefix.is_empty() {
+            key.to_uppercase()
+        } else {
+            format!("{}_{}", var_prefix, key.to_uppercase())
+        };
+        if let toml::Value::Table(x) = value {
+            flatten(x, &full_key, &var, path, res, tables)?;
+            continue;
+        }
+        if KNOWN_TABLE_LISTS.contains(&var.as_str()) {
+            let list = value
+                .as_array()
+                .and_then(|x| {
+                    x.iter()
+                        .map(|x| x.as_table().cloned())
+                        .collect::<Option<Vec<_>>>()
+                })
+                .ok_or_else(|| {
+                    eyre!(
+                        "Expected '{}' in {:?} to be a list of tables",
+                        full_key,
+                        path
+                    )
+                })?;
+            tables.insert(var, (list, full_key));
+            continue;
+        }
+        if !KNOWN_VARIABLES.contains(&var.as_str()) {
+            return Err(eyre!("Unknown key '{}' in {:?}", full_key, path));
+        }
+        let value = match value {
+            toml::Value::Array(x) => x
+                .iter()
+                .map(|x| scalar_to_string(x, &full_key, path))
+                .collect::<color_eyre::Result<Vec<_>>>()?
+                .join(","),
+            x => scalar_to_string(x, &full_key, path)?,
+        };
+        res.insert(var, (value, full_key));
+    }
+    Ok(())
+}
+
+fn scalar_to_string(value: &toml::Value, key: &str, path: &Path) -> color_eyre::Result<String> {
+    Ok(match value {
+        toml::Value::String(x) => x.clone(),
+        toml::Value::Integer(x) => x.to_string(),
+        toml::Value::Float(x) => x.to_string(),
+        toml::Value::Boolean(x) => if *x { "yes" } else { "no" }.to_owned(),
+        toml::Value::Datetime(x) => x.to_string(),
+        toml::Value::Array(_) | toml::Value::Table(_) => {
+            return Err(eyre!(
+                "Expected '{}' in {:?} to be a value or a list of values",
+                key,
+                path
+            ))
+        }
+    })
+}
+
+#[test]
+fn test_parse_config_file() {
+    let content = r#"
+git_directory = "/home/notes/"
+timezone = "Europe/Berlin"
+permitted_file_types = ["mp3", "wav"]
+
+[transcription]
+audio_branch = "audio"
+time_window = 100
+include_parent = true
+
+[profiles.math]
+transcription.time_window = 45
+onedrive_source_dir = "/school/math/"
+
+[profiles.physics]
+onedrive_source_dir = "/school/physics/"
+"#;
+    let path = Path::new("/home/notes/korosensei.toml");
+    let vars = Variables::parse(content, path, None).unwrap();
+    assert_eq!(vars.var("GIT_DIRECTORY").unwrap(), "/home/notes/");
+    assert_eq!(vars.var("PERMITTED_FILE_TYPES").unwrap(), "mp3,wav");
+    assert_eq!(vars.var("TRANSCRIPTION_AUDIO_BRANCH").unwrap(), "audio");
+    assert_eq!(vars.var("TRANSCRIPTION_TIME_WINDOW").unwrap(), "100");
+    assert_eq!(vars.var("TRANSCRIPTION_INCLUDE_PARENT").unwrap(), "yes");
+    assert!(vars.var("ONEDRIVE_SOURCE_DIR").is_err());
+
+    let vars = Variables::parse(content, path, Some("math")).unwrap();
+    assert_eq!(vars.var("TRANSCRIPTION_TIME_WINDOW").unwrap(), "45");
+    assert_eq!(vars.var("ONEDRIVE_SOURCE_DIR").unwrap(), "/school/math/");
+    assert_eq!(vars.var("TRANSCRIPTION_AUDIO_BRANCH").unwrap(), "audio");
+    assert_eq!(
+        vars.name("TRANSCRIPTION_TIME_WINDOW"),
+        "TRANSCRIPTION_TIME_WINDOW ('profiles.math.transcription.time_window' in \"/home/notes/korosensei.toml\")"
+    );
+
+    let err = Variables::parse(content, path, Some("biology")).unwrap_err();
+    assert!(
+        err.to_string().contains("available: math, physics"),
+        "{}",
+        err
+    );
+
+    let example = include_str!("../../korosensei.example.toml");
+    let vars = Variables::parse(example, path, Some("math")).unwrap();
+    assert_eq!(vars.var("ONEDRIVE_SOURCE_DIR").unwrap(), "/school/math/");
+    assert_eq!(
+        vars.var("TRANSCRIPTION_DEEPGRAM_KEY").unwrap(),
+        "<your-api-key>"
+    );
+    assert_eq!(vars.var("PERMITTED_FILE_TYPES").unwrap(), "mp3,wav,weba");
+
+    let err = Variables::parse("[transcription]\ntime_windw = 5\n", path, None).unwrap_err();
+    assert!(
+        err.to_string().contains("'transcription.time_windw'"),
+        "{}",
+        err
+    );
+}
@@ -0,0 +1,128 @@
+use std::{
+    ops::Deref,
+    sync::{Mutex, OnceLock},
+};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, OptionExt as _};
+use graph_rs_sdk::GraphClient;
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+
+use super::config::{CredentialConfig, CredentialProvider};
+
+pub mod oauth;
+pub mod refresh;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OneDriveCredentialsResponse {
+    pub scope: String,
+    pub token: String,
+    /// None: valid until the process exits
+    #[serde(skip)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+impl OneDriveCredentialsResponse {
+    /// a token, which expires within the next minute, isn't used anymore
+    pub fn is_valid(&self) -> bool {
+        self.expires_at
+            .map_or(true, |x| x > Utc::now() + chrono::Duration::minutes(1))
+    }
+}
+
+/// the token url may tell how long its token is valid
+#[derive(Debug, Deserialize)]
+struct TokenUrlResponse {
+    #[serde(flatten)]
+    credentials: OneDriveCredentialsResponse,
+    /// seconds
+    expires_in: Option<i64>,
+}
+
+fn credentials_cache() -> &'static Mutex<Option<OneDriveCredentialsResponse>> {
+    static ONEDRIVE_CREDENTIAL_CACHE: std::sync::OnceLock<
+        Mutex<Option<OneDriveCredentialsResponse>>,
+    > = OnceLock::new();
+    ONEDRIVE_CREDENTIAL_CACHE.get_or_init(|| Mutex::new(None))
+}
+
+/// Returns an access token for the graph api from the configured provider
+/// The token is cached until it expires
+pub async fn get_onedrive_credentials(
+    config: &CredentialConfig,
+) -> color_eyre::Result<OneDriveCredentialsResponse> {
+    if let Some(x) = credentials_cache()
+        .lock()
+        .map_err(|x| eyre!("{:?}", x))?
+        .deref()
+    {
+        if x.is_valid() {
+            return Ok(x.clone());
+        }
+        log::info!("The OneDrive access token has expired");
+    }
+    let res = match config.provider {
+        CredentialProvider::TokenUrl => get_token_url_credentials(config).await?,
+        CredentialProvider::DeviceCode => {
+            oauth::get_credentials(
+                config
+                    .oauth
+                    .as_ref()
+                    .ok_or_eyre("Expected oauth config to be initialized")?,
+            )
+            .await?
+        }
+    };
+
+    *(credentials_cache().lock().map_err(|x| eyre!("{:?}", x))?) = Some(res.clone());
+
+    Ok(res)
+}
+
+/// Asks a self hosted http server for the token
+async fn get_token_url_credentials(
+    config: &CredentialConfig,
+) -> color_eyre::Result<OneDriveCredentialsResponse> {
+    let token_url = config
+        .token_url
+        .as_ref()
+        .ok_or_eyre("Expected token_url config to be initialized")?;
+    let client = reqwest::Client::new();
+    let mut headers = HeaderMap::new();
+    headers.append(
+        reqwest::header::AUTHORIZATION,
+        token_url.onedrive_access_token_authorization.clone(),
+    );
+    let res = client
+        .get(token_url.onedrive_access_token_url.clone())
+        .headers(headers)
+        .send()
+        .await?;
+    if !res.status().is_success() {
+        return Err(eyre!(
+            "get_access_token_res; access_token_url returned {}: {:?}",
+            res.status().as_u16(),
+            res.text().await
+        ));
+    }
+    let res: TokenUrlResponse = res
+        .json()
+        .await
+        .map_err(|e| eyre!("get_access_token: Failed to read response: {:?}", e))?;
+
+    Ok(OneDriveCredentialsResponse {
+        expires_at: res
+            .expires_in
+            .map(|x| Utc::now() + chrono::Duration::seconds(x)),
+        ..res.credentials
+    })
+}
+
+/// Creates a graph client, which respects the configured graph api base url
+pub fn new_graph_client(config: &CredentialConfig, token: String) -> GraphClient {
+    let mut client = GraphClient::new(token);
+    if let Some(url) = &config.graph_api_base_url {
+        client.custom_endpoint(url);
+    }
+    client
+}
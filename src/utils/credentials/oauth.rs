@@ -0,0 +1,329 @@
+//! Microsoft identity platform: a device code login, whose refresh token is stored in the token cache
+//! Every later run refreshes the access token from it
+
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context as _};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::config::OAuthConfig;
+
+use super::OneDriveCredentialsResponse;
+
+/// content of the token cache file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TokenCache {
+    access_token: String,
+    refresh_token: Option<String>,
+    scope: String,
+    expires_at: DateTime<Utc>,
+}
+impl From<TokenCache> for OneDriveCredentialsResponse {
+    fn from(value: TokenCache) -> Self {
+        OneDriveCredentialsResponse {
+            scope: value.scope,
+            token: value.access_token,
+            expires_at: Some(value.expires_at),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    scope: String,
+    /// seconds
+    expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceCodeResponse {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    /// seconds
+    expires_in: i64,
+    /// seconds between polling the token endpoint
+    interval: u64,
+    message: Option<String>,
+}
+
+/// Returns the cached access token or refreshes it
+/// Fails if there is no usable refresh token; `korosensei login` has to be run first
+pub async fn get_credentials(
+    conf: &OAuthConfig,
+) -> color_eyre::Result<OneDriveCredentialsResponse> {
+    let cache = read_cache(&conf.token_cache)?.ok_or_else(|| {
+        eyre!(
+            "No OneDrive token found in {:?}; run `korosensei login` first",
+            conf.token_cache
+        )
+    })?;
+    let credentials = OneDriveCredentialsResponse::from(cache.clone());
+    if credentials.is_valid() {
+        return Ok(credentials);
+    }
+    let refresh_token = cache.refresh_token.clone().ok_or_else(|| {
+        eyre!(
+            "The OneDrive token in {:?} has expired & can't be refreshed (is 'offline_access' in ONEDRIVE_OAUTH_SCOPES?); run `korosensei login` again",
+            conf.token_cache
+        )
+    })?;
+    log::info!("Refreshing the OneDrive access token");
+    let res = request_token(
+        conf,
+        &[
+            ("grant_type", "refresh_token"),
+            ("client_id", &conf.client_id),
+            ("refresh_token", &refresh_token),
+            ("scope", &conf.scopes),
+        ],
+    )
+    .await?
+    .map_err(|x| {
+        eyre!(
+            "Failed to refresh the OneDrive token ({}: {}); run `korosensei login` again",
+            x.error,
+            x.error_description.unwrap_or_default()
+        )
+    })?;
+    // the refresh token may be rotated
+    Ok(store_token(conf, res, Some(refresh_token))?.into())
+}
+
+/// Logs in with a device code & stores the refresh token in the token cache
+/// The user has to open the verification uri & enter the printed code
+pub async fn login(conf: &OAuthConfig) -> color_eyre::Result<OneDriveCredentialsResponse> {
+    let client = reqwest::Client::new();
+    let res = client
+        .post(endpoint(conf, "devicecode")?)
+        .form(&[
+            ("client_id", conf.client_id.as_str()),
+            ("scope", &conf.scopes),
+        ])
+        .send()
+        .await?;
+    if !res.status().is_success() {
+        return Err(eyre!(
+            "Failed to request a device code ({}): {:?}",
+            res.status().as_u16(),
+            res.text().await
+        ));
+    }
+    let device_code: DeviceCodeResponse = res
+        .json()
+        .await
+        .wrap_err("Failed to read the device code response")?;
+    println!(
+        "{}",
+        device_code.message.clone().unwrap_or_else(|| format!(
+            "To sign in, open {} and enter the code {}",
+            device_code.verification_uri, device_code.user_code
+        ))
+    );
+
+    let deadline = Utc::now() + chrono::Duration::seconds(device_code.expires_in);
+    let mut interval = device_code.interval;
+    loop {
+        if Utc::now() > deadline {
+            return Err(eyre!(
+                "The device code has expired before the login was completed"
+            ));
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+        let res = request_token(
+            conf,
+            &[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+                ("client_id", &conf.client_id),
+                ("device_code", &device_code.device_code),
+            ],
+        )
+        .await?;
+        match res {
+            Ok(res) => return Ok(store_token(conf, res, None)?.into()),
+            Err(x) if x.error == "authorization_pending" => {}
+            Err(x) if x.error == "slow_down" => interval += 5,
+            Err(x) => {
+                return Err(eyre!(
+                    "Failed to log in ({}: {})",
+                    x.error,
+                    x.error_description.unwrap_or_default()
+                ))
+            }
+        }
+    }
+}
+
+/// Err: the error the identity platform answered with
+async fn request_token(
+    conf: &OAuthConfig,
+    form: &[(&str, &str)],
+) -> color_eyre::Result<Result<TokenResponse, ErrorResponse>> {
+    let client = reqwest::Client::new();
+    let res = client
+        .post(endpoint(conf, "token")?)
+        .form(form)
+        .send()
+        .await?;
+    let status = res.status();
+    let body = res.text().await?;
+    if status.is_success() {
+        return Ok(Ok(
+            serde_json::from_str(&body).wrap_err("Failed to read the token response")?
+        ));
+    }
+    match serde_json::from_str::<ErrorResponse>(&body) {
+        Ok(x) => Ok(Err(x)),
+        Err(_) => Err(eyre!(
+            "The token endpoint returned {}: {:?}",
+            status.as_u16(),
+            body
+        )),
+    }
+}
+
+/// e.g. https://login.microsoftonline.com/common/oauth2/v2.0/token
+fn endpoint(conf: &OAuthConfig, name: &str) -> color_eyre::Result<Url> {
+    let authority = conf
+        .authority_url
+        .as_ref()
+        .map(|x| x.as_str())
+        .unwrap_or("https://login.microsoftonline.com");
+    Ok(Url::parse(&format!(
+        "{}/{}/oauth2/v2.0/{}",
+        authority.trim_end_matches('/'),
+        conf.tenant,
+        name
+    ))?)
+}
+
+fn read_cache(path: &Path) -> color_eyre::Result<Option<TokenCache>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(path)?;
+    Ok(Some(serde_json::from_str(&content).wrap_err(format!(
+        "Failed to parse the token cache {:?}",
+        path
+    ))?))
+}
+
+/// Writes the token cache, which is only readable by the current user
+fn store_token(
+    conf: &OAuthConfig,
+    res: TokenResponse,
+    previous_refresh_token: Option<String>,
+) -> color_eyre::Result<TokenCache> {
+    let cache = TokenCache {
+        access_token: res.access_token,
+        refresh_token: res.refresh_token.or(previous_refresh_token),
+        scope: res.scope,
+        expires_at: Utc::now() + chrono::Duration::seconds(res.expires_in),
+    };
+    if let Some(x) = conf.token_cache.parent() {
+        std::fs::create_dir_all(x)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&conf.token_cache).wrap_err(format!(
+        "Failed to write the token cache {:?}",
+        conf.token_cache
+    ))?;
+    std::io::Write::write_all(&mut file, serde_json::to_string_pretty(&cache)?.as_bytes())?;
+    Ok(cache)
+}
+
+#[tokio::test]
+async fn test_device_code_flow() {
+    use wiremock::{
+        matchers::{body_string_contains, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let conf = OAuthConfig {
+        client_id: "client".to_owned(),
+        tenant: "consumers".to_owned(),
+        scopes: "offline_access Files.Read".to_owned(),
+        token_cache: dir.path().join("token.json"),
+        authority_url: Some(Url::parse(&server.uri()).unwrap()),
+    };
+    assert!(get_credentials(&conf).await.is_err());
+
+    Mock::given(method("POST"))
+        .and(path("/consumers/oauth2/v2.0/devicecode"))
+        .and(body_string_contains("client_id=client"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "device_code": "device",
+            "user_code": "ABCD",
+            "verification_uri": "https://microsoft.com/devicelogin",
+            "expires_in": 900,
+            "interval": 0,
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/consumers/oauth2/v2.0/token"))
+        .and(body_string_contains("device_code=device"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+            "error": "authorization_pending",
+        })))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/consumers/oauth2/v2.0/token"))
+        .and(body_string_contains("device_code=device"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "access_token": "first",
+            "refresh_token": "refresh",
+            "scope": "Files.Read",
+            "expires_in": 3600,
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/consumers/oauth2/v2.0/token"))
+        .and(body_string_contains("grant_type=refresh_token"))
+        .and(body_string_contains("refresh_token=refresh"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "access_token": "second",
+            "scope": "Files.Read",
+            "expires_in": 3600,
+        })))
+        .mount(&server)
+        .await;
+
+    assert_eq!(login(&conf).await.unwrap().token, "first");
+    // cached
+    assert_eq!(get_credentials(&conf).await.unwrap().token, "first");
+
+    let mut cache = read_cache(&conf.token_cache).unwrap().unwrap();
+    cache.expires_at = Utc::now();
+    std::fs::write(&conf.token_cache, serde_json::to_string(&cache).unwrap()).unwrap();
+    let credentials = get_credentials(&conf).await.unwrap();
+    assert_eq!(credentials.token, "second");
+    assert!(credentials.is_valid());
+    // the refresh token is kept, as none was returned
+    assert_eq!(
+        read_cache(&conf.token_cache)
+            .unwrap()
+            .unwrap()
+            .refresh_token,
+        Some("refresh".to_owned())
+    );
+}
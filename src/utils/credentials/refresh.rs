@@ -0,0 +1,74 @@
+//! Access tokens of apps, which were authorized once & hold a refresh token (Google Drive, Dropbox)
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context as _};
+use serde::Deserialize;
+
+use crate::utils::config::RefreshTokenConfig;
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    /// seconds
+    expires_in: i64,
+}
+
+/// key: token url & client id
+fn token_cache() -> &'static Mutex<HashMap<String, (String, DateTime<Utc>)>> {
+    static TOKEN_CACHE: OnceLock<Mutex<HashMap<String, (String, DateTime<Utc>)>>> = OnceLock::new();
+    TOKEN_CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Returns an access token; cached until a minute before it expires
+pub async fn get_access_token(conf: &RefreshTokenConfig) -> color_eyre::Result<String> {
+    let key = format!("{} {}", conf.token_url, conf.client_id);
+    if let Some((token, expires_at)) = token_cache()
+        .lock()
+        .map_err(|x| eyre!("{:?}", x))?
+        .get(&key)
+    {
+        if *expires_at > Utc::now() + chrono::Duration::minutes(1) {
+            return Ok(token.clone());
+        }
+    }
+
+    let mut form = vec![
+        ("grant_type", "refresh_token"),
+        ("refresh_token", conf.refresh_token.as_str()),
+        ("client_id", conf.client_id.as_str()),
+    ];
+    if let Some(x) = &conf.client_secret {
+        form.push(("client_secret", x));
+    }
+    let res = reqwest::Client::new()
+        .post(conf.token_url.clone())
+        .form(&form)
+        .send()
+        .await?;
+    if !res.status().is_success() {
+        return Err(eyre!(
+            "Failed to refresh the access token at {} ({}): {:?}",
+            conf.token_url,
+            res.status().as_u16(),
+            res.text().await
+        ));
+    }
+    let res: TokenResponse = res.json().await.wrap_err(format!(
+        "Failed to read the token response of {}",
+        conf.token_url
+    ))?;
+
+    token_cache().lock().map_err(|x| eyre!("{:?}", x))?.insert(
+        key,
+        (
+            res.access_token.clone(),
+            Utc::now() + chrono::Duration::seconds(res.expires_in),
+        ),
+    );
+    Ok(res.access_token)
+}
@@ -0,0 +1,35 @@
+use std::path::Path;
+
+/// Unified diff (git style) between two versions of a file; empty if nothing changed
+pub fn unified_diff(path: &Path, old: &str, new: &str) -> String {
+    if old == new {
+        return String::new();
+    }
+    let path = path.to_string_lossy();
+    let path = path.strip_prefix("/").unwrap_or(&path);
+    similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("a/{}", path), &format!("b/{}", path))
+        .to_string()
+}
+
+#[test]
+fn test_unified_diff() {
+    assert_eq!(
+        unified_diff(
+            Path::new("/notes/a.md"),
+            "# A\ntext\n",
+            "# A\n> link\ntext\n"
+        ),
+        "\
+--- a/notes/a.md
++++ b/notes/a.md
+@@ -1,2 +1,3 @@
+ # A
++> link
+ text
+"
+    );
+    assert_eq!(unified_diff(Path::new("/notes/a.md"), "# A\n", "# A\n"), "");
+}
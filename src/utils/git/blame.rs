@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, ops::RangeInclusive, path::PathBuf};
 
 use chrono::{DateTime, Utc};
 use color_eyre::eyre::{Context as _, OptionExt as _};
@@ -6,7 +6,7 @@ use itertools::Itertools as _;
 
 use crate::jobs::transcription;
 use crate::jobs::transcription::markdown::{self, CorrelatingFile};
-use crate::utils::config::Config;
+use crate::utils::config::{BlameTime, Config};
 use crate::utils::git;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -89,43 +89,39 @@ impl BlamedFile {
         })
     }
 
+    /// the headings of all lines changed inside the time window (including its bounds)
     pub fn to_correlating_file(
         &self,
         conf: &Config,
-        cutoff_date: DateTime<Utc>,
+        window: &RangeInclusive<DateTime<Utc>>,
     ) -> color_eyre::Result<Option<crate::jobs::transcription::markdown::CorrelatingFile>> {
-        println!("blame: {:?}; cut: {:?}", self.blame, cutoff_date);
-        let lines_of_interest = self
-            .blame
+        log::trace!("blame: {:?}; window: {:?}", self.blame, window);
+        let transcription_config = conf
+            .transcription
             .clone()
-            .into_iter()
-            .filter(|x| x.time > cutoff_date)
-            .map(|x| x.line)
-            .collect_vec();
+            .ok_or_eyre("Expected transcription config to be initialized")?;
+        let lines_of_interest = self.lines_in_window(transcription_config.blame_time, window);
         if lines_of_interest.is_empty() {
             // skip file io
             return Ok(None);
         }
         let content = std::fs::read_to_string(self.file.clone())?;
-        let transcription_config = conf
-            .transcription
-            .clone()
-            .ok_or_eyre("Expected transcription config to be initialized")?;
 
-        let res = lines_of_interest
-            .into_iter()
-            .map(|x| {
-                markdown::get_related_markdown_headings(
-                    x as u64,
-                    &content,
-                    transcription_config.include_parent,
-                )
-            })
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
-            .flatten()
-            .dedup()
-            .collect_vec();
+        let mut res = Vec::new();
+        let mut written_at: HashMap<u64, DateTime<Utc>> = HashMap::new();
+        for (line, time) in lines_of_interest {
+            for headline in markdown::get_related_markdown_headings(
+                line as u64,
+                &content,
+                transcription_config.include_parent,
+            )? {
+                res.push(headline);
+                // a section is written when its first line is
+                let entry = written_at.entry(headline).or_insert(time);
+                *entry = (*entry).min(time);
+            }
+        }
+        let res = res.into_iter().dedup().collect_vec();
         if res.is_empty() {
             return Ok(None);
         }
@@ -133,25 +129,45 @@ impl BlamedFile {
             path: self.file.clone(),
             headlines: res,
             content,
+            written_at,
         }))
     }
+    /// the lines changed inside the time window (including its bounds) & when they were changed
+    fn lines_in_window(
+        &self,
+        blame_time: BlameTime,
+        window: &RangeInclusive<DateTime<Utc>>,
+    ) -> Vec<(usize, DateTime<Utc>)> {
+        self.blame
+            .iter()
+            .map(|x| (x.line, x.time(blame_time)))
+            .filter(|(_, time)| window.contains(time))
+            .collect_vec()
+    }
 }
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BlameResult {
-    /// Utc, raw
+    /// committer time; Utc, raw
     pub time: DateTime<Utc>,
+    /// author time; differs from the committer time for rebased or amended commits
+    pub author_time: DateTime<Utc>,
     /// 0-indexed
     pub line: usize,
 }
 
 impl BlameResult {
+    pub fn time(&self, kind: BlameTime) -> DateTime<Utc> {
+        match kind {
+            BlameTime::Author => self.author_time,
+            BlameTime::Committer => self.time,
+        }
+    }
     /// parses each line for git blame
     /// expects to have the result of git blame --line-porcelain
     fn parse_git_blame(blame: &str) -> color_eyre::Result<Vec<BlameResult>> {
         let to_strip = vec![
             "author ",
             "author-mail ",
-            "author-time ",
             "author-tz ",
             "committer ",
             "committer-mail ",
@@ -167,15 +183,15 @@ impl BlameResult {
             .collect_vec();
 
         let blame = lines.join("\n");
-        println!("{:#?}", blame);
+        log::trace!("{:#?}", blame);
 
         let regex = regex::RegexBuilder::new(
-            r"^(?:[\da-f]{40}) (?:\d{1,}) (\d{1,})(?: \d{1,})?\ncommitter-time (\d{1,})$",
+            r"^(?:[\da-f]{40}) (?:\d{1,}) (\d{1,})(?: \d{1,})?\nauthor-time (\d{1,})\ncommitter-time (\d{1,})$",
         )
         .multi_line(true)
         .build()?;
         let mut res = vec![];
-        for (_, [line, time]) in regex.captures_iter(&blame).map(|x| x.extract()) {
+        for (_, [line, author_time, time]) in regex.captures_iter(&blame).map(|x| x.extract()) {
             let line = line
                 .parse::<usize>()
                 .wrap_err("Failed to parse blame line")?
@@ -185,7 +201,16 @@ impl BlameResult {
                 .wrap_err("Failed to parse committer-time")?;
             let time = DateTime::from_timestamp(time, 0)
                 .ok_or_eyre("Failed to parse committer-time -> DateTime<Utc>")?;
-            res.push(BlameResult { time, line });
+            let author_time = author_time
+                .parse::<i64>()
+                .wrap_err("Failed to parse author-time")?;
+            let author_time = DateTime::from_timestamp(author_time, 0)
+                .ok_or_eyre("Failed to parse author-time -> DateTime<Utc>")?;
+            res.push(BlameResult {
+                time,
+                author_time,
+                line,
+            });
         }
 
         Ok(res)
@@ -235,17 +260,50 @@ filename attachements/test.mp3.transcript.md
     let expected = vec![
         BlameResult {
             time: DateTime::from_timestamp(1721484369, 0).unwrap(),
+            author_time: DateTime::from_timestamp(1721484187, 0).unwrap(),
             line: 0,
         },
         BlameResult {
             time: DateTime::from_timestamp(1721484342, 0).unwrap(),
+            author_time: DateTime::from_timestamp(1721484342, 0).unwrap(),
             line: 1,
         },
         BlameResult {
             time: DateTime::from_timestamp(1721480000, 0).unwrap(),
+            author_time: DateTime::from_timestamp(172140000, 0).unwrap(),
             line: 2,
         },
     ];
     let res = BlameResult::parse_git_blame(&input).unwrap();
     assert_eq!(res, expected);
 }
+
+#[test]
+fn test_lines_in_window() {
+    let at = |x: &str| DateTime::parse_from_rfc3339(x).unwrap().to_utc();
+    let line = |line: usize, author: &str, committer: &str| BlameResult {
+        time: at(committer),
+        author_time: at(author),
+        line,
+    };
+    let file = BlamedFile {
+        file: PathBuf::from("/repo/notes/lesson.md"),
+        blame: vec![
+            line(0, "2024-07-14T10:59:59Z", "2024-07-14T10:59:59Z"),
+            line(1, "2024-07-14T11:00:00Z", "2024-07-14T11:00:00Z"),
+            line(2, "2024-07-14T12:30:00Z", "2024-07-14T12:30:00Z"),
+            line(3, "2024-07-15T12:00:00Z", "2024-07-15T12:00:00Z"),
+            // written during the lesson, but synced (or rebased) a month later
+            line(4, "2024-07-14T11:30:00Z", "2024-08-14T12:00:00Z"),
+        ],
+    };
+    let window = at("2024-07-14T11:00:00Z")..=at("2024-07-14T12:30:00Z");
+    let lines = |kind| {
+        file.lines_in_window(kind, &window)
+            .into_iter()
+            .map(|x| x.0)
+            .collect_vec()
+    };
+    assert_eq!(lines(BlameTime::Committer), vec![1, 2]);
+    assert_eq!(lines(BlameTime::Author), vec![1, 2, 4]);
+}
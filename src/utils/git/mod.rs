@@ -45,6 +45,30 @@ pub fn wrap_git_command_error(res: &GitCommandOutput) -> color_eyre::Result<()>
 
 pub const GIT_AUTHOR: &str = "Koro-sensei <koro-sensei@ansatsu-anime.com>";
 
+/// Stages all changes (including deletions) of the working tree & commits them
+/// return bool -> false if there was nothing to commit
+pub fn commit_all(message: &str, config: &Config) -> color_eyre::Result<bool> {
+    wrap_git_command_error(&git_command_wrapper(
+        &["add", "-A"],
+        &config.git_directory,
+        &config,
+    )?)?;
+    let res = git_command_wrapper(
+        &["diff", "--cached", "--quiet"],
+        &config.git_directory,
+        &config,
+    )?;
+    if res.status.success() {
+        return Ok(false);
+    }
+    wrap_git_command_error(&git_command_wrapper(
+        &["commit", "-m", message, "--author", GIT_AUTHOR],
+        &config.git_directory,
+        &config,
+    )?)?;
+    Ok(true)
+}
+
 pub fn get_branches(config: &Config) -> color_eyre::Result<Vec<String>> {
     let res = git_command_wrapper(
         &["branch", "--list", "--no-color"],
@@ -61,6 +85,10 @@ pub fn get_branches(config: &Config) -> color_eyre::Result<Vec<String>> {
     Ok(branches)
 }
 
+pub fn branch_exists(branch: &str, config: &Config) -> color_eyre::Result<bool> {
+    Ok(get_branches(&config)?.contains(&branch.to_owned()))
+}
+
 /// return bool -> indicates whether or not a branch has been created or not
 pub fn check_out_create_branch(branch: &str, config: &Config) -> color_eyre::Result<bool> {
     let branches = get_branches(&config)?;
@@ -1,7 +1,10 @@
 pub mod char_stream;
+pub mod cloud_storage;
 pub mod commandline;
 pub mod config;
+pub mod config_file;
 pub mod credentials;
+pub mod diff;
 pub mod git;
 pub mod prerequisits;
 pub mod string;
@@ -4,5 +4,19 @@ pub fn check_prerequisits(conf: &mut super::config::Config) -> color_eyre::Resul
     let git_path =
         which::which("git").wrap_err("Git is expected to be installed and in your $PATH")?;
     conf.git_exec = git_path;
+
+    if let Some(local) = conf.transcription.as_mut().and_then(|x| x.local.as_mut()) {
+        let exec_path = which::which(&local.executable).wrap_err(format!(
+            "{:?} (TRANSCRIPTION_LOCAL_EXECUTABLE) is expected to be installed and in your $PATH",
+            local.executable
+        ))?;
+        local.executable = exec_path;
+        if !local.model.is_file() {
+            return Err(color_eyre::eyre::eyre!(
+                "Expected TRANSCRIPTION_LOCAL_MODEL {:?} to be a file",
+                local.model
+            ));
+        }
+    }
     Ok(())
 }